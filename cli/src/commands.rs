@@ -0,0 +1,327 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+
+use bldc_protocol::{
+  ErrorCode, Event, Fault, ImpedanceTarget, ModeId, Name, ParamValue, Request, Response, Status,
+};
+
+use crate::link::Link;
+
+pub struct Param {
+  pub index: u16,
+  pub name: Name,
+  pub value: ParamValue,
+}
+
+pub struct ParamInfo {
+  pub unit: Name,
+  pub min: ParamValue,
+  pub max: ParamValue,
+  pub default: ParamValue,
+}
+
+fn unexpected(response: Response) -> io::Error {
+  let message = match response {
+    Response::Error(code) => format!("controller returned error {:?}", code),
+    other => format!("unexpected response {:?}", other),
+  };
+  io::Error::other(message)
+}
+
+fn expect_ok<P: Read + Write>(link: &mut Link<P>, request: &Request) -> io::Result<()> {
+  match link.request(request)? {
+    Response::Ok => Ok(()),
+    other => Err(unexpected(other)),
+  }
+}
+
+pub fn ping<P: Read + Write>(link: &mut Link<P>) -> io::Result<()> {
+  match link.request(&Request::Ping)? {
+    Response::Pong { version } => {
+      println!("Controller protocol version {}", version);
+      Ok(())
+    }
+    other => Err(unexpected(other)),
+  }
+}
+
+pub fn status<P: Read + Write>(link: &mut Link<P>) -> io::Result<()> {
+  match link.request(&Request::GetStatus)? {
+    Response::Status(status) => {
+      println!("time        {} us", status.timestamp_us);
+      println!("mode        {}", status.mode.name());
+      println!(
+        "gate        {}",
+        if status.gate_enabled {
+          "enabled"
+        } else {
+          "disabled"
+        }
+      );
+      println!("warnings    {:#06x}", status.warnings);
+      println!("faults      {}", fault_names(status.faults));
+      println!("position    {} rad", status.position);
+      println!("velocity    {} rad/s", status.velocity);
+      println!("phase angle {} rad", status.phase_angle);
+      println!("power       {}", status.power);
+      println!("bus voltage {} V", status.bus_voltage);
+      Ok(())
+    }
+    other => Err(unexpected(other)),
+  }
+}
+
+pub fn pwm<P: Read + Write>(link: &mut Link<P>) -> io::Result<()> {
+  match link.request(&Request::GetPwmTiming)? {
+    Response::PwmTiming {
+      frequency,
+      deadtime_ns,
+    } => {
+      println!("frequency   {} Hz", frequency);
+      println!("deadtime    {} ns", deadtime_ns);
+      Ok(())
+    }
+    other => Err(unexpected(other)),
+  }
+}
+
+pub fn set_mode<P: Read + Write>(link: &mut Link<P>, mode: ModeId) -> io::Result<()> {
+  expect_ok(link, &Request::SetMode(mode))
+}
+
+pub fn set_position<P: Read + Write>(link: &mut Link<P>, position: f32) -> io::Result<()> {
+  expect_ok(link, &Request::SetPositionTarget(position))
+}
+
+pub fn set_velocity<P: Read + Write>(link: &mut Link<P>, velocity: f32) -> io::Result<()> {
+  expect_ok(link, &Request::SetVelocityTarget(velocity))
+}
+
+pub fn set_impedance<P: Read + Write>(
+  link: &mut Link<P>,
+  target: ImpedanceTarget,
+) -> io::Result<()> {
+  expect_ok(link, &Request::SetImpedanceTarget(target))
+}
+
+pub fn read_params<P: Read + Write>(link: &mut Link<P>) -> io::Result<Vec<Param>> {
+  let mut params = Vec::new();
+
+  for index in 0..=u16::MAX {
+    match link.request(&Request::GetParam(index))? {
+      Response::Param { index, name, value } => params.push(Param { index, name, value }),
+      Response::Error(ErrorCode::UnknownParameter) => break,
+      other => return Err(unexpected(other)),
+    }
+  }
+
+  Ok(params)
+}
+
+pub fn read_param_info<P: Read + Write>(link: &mut Link<P>, index: u16) -> io::Result<ParamInfo> {
+  match link.request(&Request::GetParamInfo(index))? {
+    Response::ParamInfo {
+      unit,
+      min,
+      max,
+      default,
+      ..
+    } => Ok(ParamInfo {
+      unit,
+      min,
+      max,
+      default,
+    }),
+    other => Err(unexpected(other)),
+  }
+}
+
+fn format_value(value: &ParamValue) -> String {
+  match value {
+    ParamValue::F32(v) => format!("{:?}", v),
+    ParamValue::U32(v) => format!("{}", v),
+    ParamValue::Bool(v) => format!("{}", v),
+  }
+}
+
+fn parse_value(like: &ParamValue, text: &str) -> Option<ParamValue> {
+  match like {
+    ParamValue::F32(_) => text.parse().ok().map(ParamValue::F32),
+    ParamValue::U32(_) => text.parse().ok().map(ParamValue::U32),
+    ParamValue::Bool(_) => text.parse().ok().map(ParamValue::Bool),
+  }
+}
+
+// Writes `name = value` lines, each followed by a comment giving the unit,
+// range and default.
+pub fn dump_params<P: Read + Write, W: Write>(link: &mut Link<P>, out: &mut W) -> io::Result<()> {
+  for param in read_params(link)? {
+    let info = read_param_info(link, param.index)?;
+    let unit = match info.unit.as_str() {
+      "" => String::new(),
+      unit => format!(" {},", unit),
+    };
+    writeln!(
+      out,
+      "{} = {}  #{} range {} to {}, default {}",
+      param.name.as_str(),
+      format_value(&param.value),
+      unit,
+      format_value(&info.min),
+      format_value(&info.max),
+      format_value(&info.default)
+    )?;
+  }
+  Ok(())
+}
+
+pub fn save_params<P: Read + Write>(link: &mut Link<P>) -> io::Result<()> {
+  expect_ok(link, &Request::SaveParams)
+}
+
+// Loads a file in the format written by `dump_params`. Parameters are matched
+// by name, so files survive firmware changes that reorder the table. Text
+// after `#` is ignored.
+//
+// The controller checks each value against the others as it is set, so a
+// value can be rejected only because a related one in the file has not been
+// set yet. Rejected values are retried until a pass makes no progress.
+pub fn load_params<P: Read + Write>(link: &mut Link<P>, path: &str) -> io::Result<()> {
+  let params = read_params(link)?;
+  let contents = fs::read_to_string(path)?;
+  let mut pending = Vec::new();
+
+  for (line_num, line) in contents.lines().enumerate() {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+      continue;
+    }
+
+    let invalid = |message: &str| {
+      io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path, line_num + 1, message),
+      )
+    };
+
+    let mut parts = line.splitn(2, '=');
+    let name = parts.next().unwrap_or("").trim();
+    let text = parts
+      .next()
+      .ok_or_else(|| invalid("expected `name = value`"))?
+      .trim();
+
+    let param = params
+      .iter()
+      .find(|p| p.name.as_str() == name)
+      .ok_or_else(|| invalid("unknown parameter"))?;
+    let value = parse_value(&param.value, text).ok_or_else(|| invalid("invalid value"))?;
+    pending.push((param, value));
+  }
+
+  while !pending.is_empty() {
+    let mut rejected = Vec::new();
+    for (param, value) in pending.iter() {
+      match link.request(&Request::SetParam(param.index, *value))? {
+        Response::Ok => {}
+        Response::Error(ErrorCode::InvalidValue) => rejected.push((*param, *value)),
+        other => return Err(unexpected(other)),
+      }
+    }
+
+    if rejected.len() == pending.len() {
+      let names: Vec<&str> = rejected
+        .iter()
+        .map(|(param, _)| param.name.as_str())
+        .collect();
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+          "controller rejected {} (out of range or inconsistent)",
+          names.join(", ")
+        ),
+      ));
+    }
+    pending = rejected;
+  }
+
+  Ok(())
+}
+
+fn fault_names(faults: u16) -> String {
+  let names: Vec<&str> = Fault::ALL
+    .iter()
+    .filter(|fault| fault.is_set(faults))
+    .map(|fault| fault.name())
+    .collect();
+  match names.is_empty() {
+    true => "none".to_string(),
+    false => names.join(" "),
+  }
+}
+
+fn write_csv_row<W: Write>(out: &mut W, status: &Status) -> io::Result<()> {
+  writeln!(
+    out,
+    "{},{},{},{:#06x},{:#06x},{},{},{},{},{}",
+    status.timestamp_us,
+    status.mode.name(),
+    status.gate_enabled as u8,
+    status.warnings,
+    status.faults,
+    status.position,
+    status.velocity,
+    status.phase_angle,
+    status.power,
+    status.bus_voltage
+  )
+}
+
+pub fn record<P: Read + Write>(
+  link: &mut Link<P>,
+  path: &str,
+  divider: u16,
+  samples: usize,
+) -> io::Result<()> {
+  let mut out = BufWriter::new(File::create(path)?);
+  writeln!(
+    out,
+    "timestamp_us,mode,gate_enabled,warnings,faults,position,velocity,phase_angle,power,bus_voltage"
+  )?;
+
+  expect_ok(link, &Request::StreamTelemetry(divider))?;
+
+  let mut recorded = 0;
+  let result = loop {
+    if recorded >= samples {
+      break Ok(());
+    }
+    match link.receive() {
+      Ok(Response::Telemetry(status)) => {
+        if let Err(e) = write_csv_row(&mut out, &status) {
+          break Err(e);
+        }
+        recorded += 1;
+      }
+      Ok(_) => continue,
+      Err(e) => break Err(e),
+    }
+  };
+
+  expect_ok(link, &Request::StreamTelemetry(0))?;
+  out.flush()?;
+  println!("Recorded {} samples to {}", recorded, path);
+
+  result
+}
+
+pub fn events<P: Read + Write>(link: &mut Link<P>) -> io::Result<()> {
+  loop {
+    match link.receive() {
+      Ok(Response::Event(Event::Detent(index))) => println!("detent {}", index),
+      Ok(_) => continue,
+      Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+      Err(e) => return Err(e),
+    }
+  }
+}
@@ -0,0 +1,226 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::process::Command;
+
+use bldc_protocol::{
+  frame::{self, Decoder, MAX_FRAME_LEN},
+  Message, Request, Response,
+};
+
+// Configures a serial device (or pty) for raw 8N1 at the given baud rate, with
+// reads timing out after half a second.
+pub fn open_port(path: &str, baud_rate: u32) -> io::Result<File> {
+  let status = Command::new("stty")
+    .arg("-F")
+    .arg(path)
+    .arg(baud_rate.to_string())
+    .args([
+      "raw", "-echo", "cs8", "-cstopb", "-parenb", "min", "0", "time", "5",
+    ])
+    .status()?;
+
+  if !status.success() {
+    return Err(io::Error::other(format!(
+      "stty failed to configure {}",
+      path
+    )));
+  }
+
+  OpenOptions::new().read(true).write(true).open(path)
+}
+
+// Request/response transport over any byte stream. Reads returning zero bytes
+// are treated as timeouts.
+pub struct Link<P: Read + Write> {
+  port: P,
+  decoder: Decoder,
+  max_timeouts: u32,
+}
+impl<P: Read + Write> Link<P> {
+  pub fn new(port: P) -> Self {
+    Self {
+      port,
+      decoder: Decoder::new(),
+      max_timeouts: 4,
+    }
+  }
+
+  pub fn send(&mut self, request: &Request) -> io::Result<()> {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = frame::encode_message(request, &mut buf).map_err(protocol_error)?;
+    self.port.write_all(&buf[..len])?;
+    self.port.flush()
+  }
+
+  pub fn receive(&mut self) -> io::Result<Response> {
+    let mut timeouts = 0;
+    let mut byte = [0u8; 1];
+
+    loop {
+      if self.port.read(&mut byte)? == 0 {
+        timeouts += 1;
+        if timeouts >= self.max_timeouts {
+          return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no response from controller",
+          ));
+        }
+        continue;
+      }
+
+      if let Some(payload) = self.decoder.push(byte[0]) {
+        match payload.and_then(Response::decode) {
+          Ok(response) => return Ok(response),
+          Err(e) => eprintln!("Discarding bad frame: {}", e.message()),
+        }
+      }
+    }
+  }
+
+  // Sends a request and waits for its response, skipping any telemetry or
+  // events that were already in flight.
+  pub fn request(&mut self, request: &Request) -> io::Result<Response> {
+    self.send(request)?;
+    loop {
+      match self.receive()? {
+        Response::Telemetry(_) | Response::Event(_) => continue,
+        response => return Ok(response),
+      }
+    }
+  }
+}
+
+pub fn protocol_error(e: bldc_protocol::Error) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, e.message())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bldc_protocol::{ModeId, Status};
+  use std::collections::VecDeque;
+
+  // Stands in for the controller: decodes the requests written to it and
+  // reads back whatever bytes the test queued.
+  struct FakeDevice {
+    decoder: Decoder,
+    requests: Vec<Request>,
+    replies: VecDeque<u8>,
+    reads: u32,
+  }
+  impl FakeDevice {
+    fn new() -> Self {
+      Self {
+        decoder: Decoder::new(),
+        requests: Vec::new(),
+        replies: VecDeque::new(),
+        reads: 0,
+      }
+    }
+
+    fn queue(&mut self, response: &Response) {
+      let mut buf = [0u8; MAX_FRAME_LEN];
+      let len = frame::encode_message(response, &mut buf).unwrap();
+      self.replies.extend(&buf[..len]);
+    }
+  }
+  impl Read for FakeDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      self.reads += 1;
+      match self.replies.pop_front() {
+        Some(byte) => {
+          buf[0] = byte;
+          Ok(1)
+        }
+        None => Ok(0),
+      }
+    }
+  }
+  impl Write for FakeDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      for byte in buf {
+        if let Some(payload) = self.decoder.push(*byte) {
+          self
+            .requests
+            .push(Request::decode(payload.unwrap()).unwrap());
+        }
+      }
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn status() -> Status {
+    Status {
+      timestamp_us: 1234,
+      mode: ModeId::Velocity,
+      gate_enabled: true,
+      warnings: 0,
+      faults: 0,
+      position: 1.5,
+      velocity: -2f32,
+      phase_angle: 0.25,
+      power: 0.5,
+      bus_voltage: 24f32,
+    }
+  }
+
+  #[test]
+  fn request_is_framed_and_response_decoded() {
+    let mut device = FakeDevice::new();
+    device.queue(&Response::Pong { version: 3 });
+    let mut link = Link::new(device);
+
+    let response = link.request(&Request::SetMode(ModeId::Position)).unwrap();
+
+    assert_eq!(response, Response::Pong { version: 3 });
+    assert_eq!(link.port.requests, vec![Request::SetMode(ModeId::Position)]);
+  }
+
+  #[test]
+  fn request_skips_telemetry_in_flight() {
+    let mut device = FakeDevice::new();
+    device.queue(&Response::Telemetry(status()));
+    device.queue(&Response::Status(status()));
+    let mut link = Link::new(device);
+
+    let response = link.request(&Request::GetStatus).unwrap();
+
+    assert_eq!(response, Response::Status(status()));
+  }
+
+  #[test]
+  fn receive_times_out_after_repeated_empty_reads() {
+    let mut link = Link::new(FakeDevice::new());
+
+    let error = link.request(&Request::Ping).unwrap_err();
+
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(link.port.reads, link.max_timeouts);
+  }
+
+  #[test]
+  fn corrupted_frame_is_discarded() {
+    let mut device = FakeDevice::new();
+    device.queue(&Response::Status(status()));
+    // Flip a bit inside the first frame so its checksum fails.
+    device.replies[3] ^= 0x10;
+    device.queue(&Response::Ok);
+    let mut link = Link::new(device);
+
+    assert_eq!(link.receive().unwrap(), Response::Ok);
+  }
+
+  #[test]
+  fn truncated_frame_is_discarded() {
+    let mut device = FakeDevice::new();
+    device.replies.extend(&[0x02, 0x05, frame::DELIMITER]);
+    device.queue(&Response::Ok);
+    let mut link = Link::new(device);
+
+    assert_eq!(link.receive().unwrap(), Response::Ok);
+  }
+}
@@ -0,0 +1,137 @@
+mod commands;
+mod link;
+
+use std::env;
+use std::io;
+use std::process;
+
+use bldc_protocol::{ImpedanceTarget, ModeId};
+
+use link::{open_port, Link};
+
+const DEFAULT_PORT: &str = "/dev/ttyUSB0";
+const DEFAULT_BAUD: u32 = 115200;
+const DEFAULT_DIVIDER: u16 = 10;
+const DEFAULT_SAMPLES: usize = 1000;
+
+const USAGE: &str = "\
+Usage: bldc-cli [--port PATH] [--baud RATE] <command>
+
+Commands:
+  ping                          Check that the controller is responding
+  status                        Print the controller status
+  pwm                           Print the achieved PWM frequency and deadtime
+  mode <name>                   Change mode (idle, calibrate, demo, position, velocity, torque,
+                                sensorless, homing, impedance, haptic,
+                                open_loop, step_dir)
+  calibrate                     Start calibration
+  position <rad>                Set a position target
+  velocity <rad/s>              Set a velocity target
+  impedance <rad> <rad/s> <stiffness> <damping> <feedforward>
+                                Set an impedance target (efforts are fractions of full power)
+  params dump [file]            Write all parameters to a file or stdout
+  params load <file>            Set parameters from a file
+  params save                   Store the current parameters in flash (motor must be idle)
+  record <file.csv> [--divider N] [--samples N]
+                                Record streamed telemetry to CSV
+  events                        Print events, such as haptic detents, until interrupted";
+
+fn fail(message: &str) -> ! {
+  eprintln!("{}\n\n{}", message, USAGE);
+  process::exit(2);
+}
+
+fn parse<T: std::str::FromStr>(text: Option<&String>, what: &str) -> T {
+  match text.map(|t| t.parse()) {
+    Some(Ok(value)) => value,
+    _ => fail(&format!("Expected {}", what)),
+  }
+}
+
+fn main() {
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  let mut port = DEFAULT_PORT.to_string();
+  let mut baud = DEFAULT_BAUD;
+
+  while !args.is_empty() && args[0].starts_with("--") {
+    let flag = args.remove(0);
+    let value = if args.is_empty() {
+      fail(&format!("Missing value for {}", flag))
+    } else {
+      args.remove(0)
+    };
+    match flag.as_str() {
+      "--port" => port = value,
+      "--baud" => baud = parse(Some(&value), "a baud rate"),
+      _ => fail(&format!("Unknown option {}", flag)),
+    }
+  }
+
+  if args.is_empty() {
+    fail("Missing command");
+  }
+
+  let result = open_port(&port, baud).and_then(|file| run(&mut Link::new(file), &args));
+
+  if let Err(e) = result {
+    eprintln!("Error: {}", e);
+    process::exit(1);
+  }
+}
+
+fn run<P: io::Read + io::Write>(link: &mut Link<P>, args: &[String]) -> io::Result<()> {
+  match args[0].as_str() {
+    "ping" => commands::ping(link),
+    "status" => commands::status(link),
+    "pwm" => commands::pwm(link),
+    "mode" => {
+      let name = args.get(1).map(|n| n.as_str()).unwrap_or("");
+      match ModeId::from_name(name) {
+        Some(ModeId::Recovery) | None => fail(&format!("Unknown mode '{}'", name)),
+        Some(mode) => commands::set_mode(link, mode),
+      }
+    }
+    "calibrate" => commands::set_mode(link, ModeId::Calibrate),
+    "position" => commands::set_position(link, parse(args.get(1), "a position in radians")),
+    "velocity" => commands::set_velocity(link, parse(args.get(1), "a velocity in rad/s")),
+    "impedance" => commands::set_impedance(
+      link,
+      ImpedanceTarget {
+        position: parse(args.get(1), "a position in radians"),
+        velocity: parse(args.get(2), "a velocity in rad/s"),
+        stiffness: parse(args.get(3), "a stiffness in 1/rad"),
+        damping: parse(args.get(4), "a damping in s/rad"),
+        feedforward: parse(args.get(5), "a feed-forward effort"),
+      },
+    ),
+    "params" => match (args.get(1).map(|a| a.as_str()), args.get(2)) {
+      (Some("dump"), Some(path)) => {
+        let mut file = std::fs::File::create(path)?;
+        commands::dump_params(link, &mut file)
+      }
+      (Some("dump"), None) => commands::dump_params(link, &mut io::stdout()),
+      (Some("load"), Some(path)) => commands::load_params(link, path),
+      (Some("save"), None) => commands::save_params(link),
+      _ => fail("Expected `params dump [file]`, `params load <file>` or `params save`"),
+    },
+    "record" => {
+      let path = match args.get(1) {
+        Some(path) => path,
+        None => fail("Expected an output file"),
+      };
+      let mut divider = DEFAULT_DIVIDER;
+      let mut samples = DEFAULT_SAMPLES;
+      let mut rest = args[2..].iter();
+      while let Some(flag) = rest.next() {
+        match flag.as_str() {
+          "--divider" => divider = parse(rest.next(), "a divider"),
+          "--samples" => samples = parse(rest.next(), "a sample count"),
+          _ => fail(&format!("Unknown option {}", flag)),
+        }
+      }
+      commands::record(link, path, divider, samples)
+    }
+    "events" => commands::events(link),
+    command => fail(&format!("Unknown command '{}'", command)),
+  }
+}
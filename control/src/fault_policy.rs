@@ -0,0 +1,358 @@
+use bldc_protocol::Fault;
+
+// What to do once a fault source has tripped, in increasing severity. When
+// several sources are active the most severe action applies.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum FaultAction {
+  // Report the fault only.
+  Log = 0,
+  // Limit the power to the derating fraction while the fault is present.
+  Derate = 1,
+  // Stop the motor with the configured stop mode and return to idle.
+  Stop = 2,
+  // Coast with the gate disabled while the fault is present, then carry on
+  // in the same mode.
+  Coast = 3,
+  // Coast with the gate disabled and return to idle, holding the fault until
+  // it is acknowledged with its condition gone.
+  Latch = 4,
+}
+impl FaultAction {
+  pub fn from_u32(value: u32) -> FaultAction {
+    match value {
+      1 => FaultAction::Derate,
+      2 => FaultAction::Stop,
+      3 => FaultAction::Coast,
+      4 => FaultAction::Latch,
+      _ => FaultAction::Log,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FaultSource {
+  // DRV8305 warnings other than its temperature warnings.
+  Driver,
+  // The DRV8305's temperature warnings below shutdown.
+  DriverTemperature,
+  // Angle sensor readings rejected.
+  AngleSensor,
+  Overvoltage,
+  Undervoltage,
+  Overspeed,
+  // A control loop step took longer than allowed.
+  LoopOverrun,
+  Overtemperature,
+}
+
+pub const NUM_FAULT_SOURCES: usize = 8;
+
+impl FaultSource {
+  pub const ALL: [FaultSource; NUM_FAULT_SOURCES] = [
+    FaultSource::Driver,
+    FaultSource::DriverTemperature,
+    FaultSource::AngleSensor,
+    FaultSource::Overvoltage,
+    FaultSource::Undervoltage,
+    FaultSource::Overspeed,
+    FaultSource::LoopOverrun,
+    FaultSource::Overtemperature,
+  ];
+
+  fn index(self) -> usize {
+    self as usize
+  }
+
+  pub fn fault(self) -> Fault {
+    match self {
+      FaultSource::Driver => Fault::Driver,
+      FaultSource::DriverTemperature => Fault::DriverTemperature,
+      FaultSource::AngleSensor => Fault::AngleSensor,
+      FaultSource::Overvoltage => Fault::Overvoltage,
+      FaultSource::Undervoltage => Fault::Undervoltage,
+      FaultSource::Overspeed => Fault::Overspeed,
+      FaultSource::LoopOverrun => Fault::LoopOverrun,
+      FaultSource::Overtemperature => Fault::Overtemperature,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FaultRule {
+  pub action: FaultAction,
+  // Consecutive steps a condition must be present before it trips.
+  pub debounce: u32,
+}
+
+// Decides, from which fault conditions are present each step, which faults
+// are active and what should be done about them. It touches no hardware,
+// leaving the caller to carry out the action.
+pub struct FaultPolicy {
+  rules: [FaultRule; NUM_FAULT_SOURCES],
+  counts: [u32; NUM_FAULT_SOURCES],
+  active: [bool; NUM_FAULT_SOURCES],
+  // Whether each active fault is held by a latching rule.
+  latched: [bool; NUM_FAULT_SOURCES],
+}
+impl FaultPolicy {
+  pub fn new(rules: [FaultRule; NUM_FAULT_SOURCES]) -> Self {
+    Self {
+      rules,
+      counts: [0; NUM_FAULT_SOURCES],
+      active: [false; NUM_FAULT_SOURCES],
+      latched: [false; NUM_FAULT_SOURCES],
+    }
+  }
+
+  pub fn set_rules(&mut self, rules: [FaultRule; NUM_FAULT_SOURCES]) {
+    self.rules = rules;
+  }
+
+  // Records whether a source's condition is present this step, returning
+  // true when this trips it.
+  pub fn update(&mut self, source: FaultSource, present: bool) -> bool {
+    let index = source.index();
+    let rule = self.rules[index];
+
+    if !present {
+      self.counts[index] = 0;
+      if !self.latched[index] {
+        self.active[index] = false;
+      }
+      return false;
+    }
+
+    self.counts[index] = self.counts[index].saturating_add(1);
+    if self.active[index] || self.counts[index] < rule.debounce.max(1) {
+      return false;
+    }
+    self.active[index] = true;
+    self.latched[index] = rule.action == FaultAction::Latch;
+    true
+  }
+
+  pub fn is_active(&self, source: FaultSource) -> bool {
+    self.active[source.index()]
+  }
+
+  pub fn is_latched(&self) -> bool {
+    self.latched.iter().any(|latched| *latched)
+  }
+
+  // The most severe action of the active faults, if any.
+  pub fn action(&self) -> Option<FaultAction> {
+    FaultSource::ALL
+      .iter()
+      .filter(|source| self.is_active(**source))
+      .map(|source| self.action_for(*source))
+      .fold(None, |worst, action| match worst {
+        Some(worst) if worst >= action => Some(worst),
+        _ => Some(action),
+      })
+  }
+
+  // A latched fault keeps the action it tripped with.
+  fn action_for(&self, source: FaultSource) -> FaultAction {
+    match self.latched[source.index()] {
+      true => FaultAction::Latch,
+      false => self.rules[source.index()].action,
+    }
+  }
+
+  // Whether any active fault stops the motor from being driven.
+  pub fn blocks_driving(&self) -> bool {
+    matches!(self.action(), Some(action) if action >= FaultAction::Stop)
+  }
+
+  // Releases latched faults whose conditions are gone.
+  pub fn acknowledge(&mut self) {
+    for index in 0..NUM_FAULT_SOURCES {
+      if self.latched[index] && self.counts[index] == 0 {
+        self.latched[index] = false;
+        self.active[index] = false;
+      }
+    }
+  }
+
+  // `Status::faults` bits of the active faults.
+  pub fn faults(&self) -> u16 {
+    let mut faults = FaultSource::ALL
+      .iter()
+      .filter(|source| self.is_active(**source))
+      .fold(0, |faults, source| faults | source.fault() as u16);
+    if self.is_latched() {
+      faults |= Fault::Latched as u16;
+    }
+    faults
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rules(action: FaultAction, debounce: u32) -> [FaultRule; NUM_FAULT_SOURCES] {
+    [FaultRule { action, debounce }; NUM_FAULT_SOURCES]
+  }
+
+  // Logs every source other than those given.
+  fn policy_with(actions: &[(FaultSource, FaultAction)]) -> FaultPolicy {
+    let mut all = rules(FaultAction::Log, 1);
+    for (source, action) in actions.iter() {
+      all[source.index()].action = *action;
+    }
+    FaultPolicy::new(all)
+  }
+
+  #[test]
+  fn trips_after_debounce_steps() {
+    let mut policy = FaultPolicy::new(rules(FaultAction::Stop, 3));
+    assert!(!policy.update(FaultSource::Overspeed, true));
+    assert!(!policy.update(FaultSource::Overspeed, true));
+    assert!(!policy.is_active(FaultSource::Overspeed));
+    assert!(policy.update(FaultSource::Overspeed, true));
+    assert!(policy.is_active(FaultSource::Overspeed));
+
+    // Trips only once while the condition stays present.
+    assert!(!policy.update(FaultSource::Overspeed, true));
+    assert!(policy.is_active(FaultSource::Overspeed));
+  }
+
+  #[test]
+  fn absent_step_restarts_debounce() {
+    let mut policy = FaultPolicy::new(rules(FaultAction::Stop, 3));
+    policy.update(FaultSource::Overvoltage, true);
+    policy.update(FaultSource::Overvoltage, true);
+    policy.update(FaultSource::Overvoltage, false);
+    assert!(!policy.update(FaultSource::Overvoltage, true));
+    assert!(!policy.update(FaultSource::Overvoltage, true));
+    assert!(policy.update(FaultSource::Overvoltage, true));
+
+    // An unlatched fault clears as soon as its condition is gone.
+    policy.update(FaultSource::Overvoltage, false);
+    assert!(!policy.is_active(FaultSource::Overvoltage));
+    assert_eq!(policy.action(), None);
+  }
+
+  #[test]
+  fn zero_debounce_trips_on_first_step() {
+    let mut policy = FaultPolicy::new(rules(FaultAction::Log, 0));
+    assert!(policy.update(FaultSource::LoopOverrun, true));
+  }
+
+  #[test]
+  fn actions_are_ordered_by_severity() {
+    assert!(FaultAction::Log < FaultAction::Derate);
+    assert!(FaultAction::Derate < FaultAction::Stop);
+    assert!(FaultAction::Stop < FaultAction::Coast);
+    assert!(FaultAction::Coast < FaultAction::Latch);
+    for value in 0..5 {
+      assert_eq!(FaultAction::from_u32(value) as u32, value);
+    }
+    assert_eq!(FaultAction::from_u32(5), FaultAction::Log);
+  }
+
+  #[test]
+  fn most_severe_active_action_applies() {
+    let mut policy = policy_with(&[
+      (FaultSource::DriverTemperature, FaultAction::Derate),
+      (FaultSource::Undervoltage, FaultAction::Stop),
+      (FaultSource::Driver, FaultAction::Coast),
+    ]);
+    assert_eq!(policy.action(), None);
+    assert!(!policy.blocks_driving());
+
+    policy.update(FaultSource::LoopOverrun, true);
+    assert_eq!(policy.action(), Some(FaultAction::Log));
+    policy.update(FaultSource::DriverTemperature, true);
+    assert_eq!(policy.action(), Some(FaultAction::Derate));
+    assert!(!policy.blocks_driving());
+    policy.update(FaultSource::Driver, true);
+    assert_eq!(policy.action(), Some(FaultAction::Coast));
+    policy.update(FaultSource::Undervoltage, true);
+    assert_eq!(policy.action(), Some(FaultAction::Coast));
+    assert!(policy.blocks_driving());
+
+    policy.update(FaultSource::Driver, false);
+    assert_eq!(policy.action(), Some(FaultAction::Stop));
+    assert!(policy.blocks_driving());
+    policy.update(FaultSource::Undervoltage, false);
+    assert_eq!(policy.action(), Some(FaultAction::Derate));
+  }
+
+  #[test]
+  fn latched_fault_holds_until_acknowledged() {
+    let mut policy = policy_with(&[(FaultSource::Overtemperature, FaultAction::Latch)]);
+    policy.update(FaultSource::Overtemperature, true);
+    assert!(policy.is_latched());
+    assert_eq!(policy.action(), Some(FaultAction::Latch));
+
+    policy.update(FaultSource::Overtemperature, false);
+    assert!(policy.is_active(FaultSource::Overtemperature));
+    assert!(policy.blocks_driving());
+    assert_eq!(
+      policy.faults(),
+      Fault::Overtemperature as u16 | Fault::Latched as u16
+    );
+
+    policy.acknowledge();
+    assert!(!policy.is_active(FaultSource::Overtemperature));
+    assert!(!policy.is_latched());
+    assert_eq!(policy.faults(), 0);
+    assert_eq!(policy.action(), None);
+  }
+
+  #[test]
+  fn acknowledge_keeps_latch_while_condition_present() {
+    let mut policy = policy_with(&[(FaultSource::Overvoltage, FaultAction::Latch)]);
+    policy.update(FaultSource::Overvoltage, true);
+    policy.update(FaultSource::Overvoltage, true);
+    policy.acknowledge();
+    assert!(policy.is_latched());
+    assert!(policy.is_active(FaultSource::Overvoltage));
+
+    policy.update(FaultSource::Overvoltage, false);
+    policy.acknowledge();
+    assert!(!policy.is_latched());
+  }
+
+  #[test]
+  fn faults_reports_active_sources() {
+    let mut policy = FaultPolicy::new(rules(FaultAction::Log, 1));
+    policy.update(FaultSource::AngleSensor, true);
+    policy.update(FaultSource::Overspeed, true);
+    assert_eq!(
+      policy.faults(),
+      Fault::AngleSensor as u16 | Fault::Overspeed as u16
+    );
+  }
+
+  #[test]
+  fn set_rules_applies_to_later_trips() {
+    let mut policy = FaultPolicy::new(rules(FaultAction::Log, 1));
+    policy.update(FaultSource::Overspeed, true);
+    assert_eq!(policy.action(), Some(FaultAction::Log));
+
+    // An active unlatched fault takes the new action straight away.
+    policy.set_rules(rules(FaultAction::Stop, 2));
+    assert_eq!(policy.action(), Some(FaultAction::Stop));
+
+    // The new debounce applies from the next trip.
+    policy.update(FaultSource::Overspeed, false);
+    assert!(!policy.update(FaultSource::Overspeed, true));
+    assert!(policy.update(FaultSource::Overspeed, true));
+  }
+
+  #[test]
+  fn set_rules_keeps_latched_faults() {
+    let mut policy = FaultPolicy::new(rules(FaultAction::Latch, 1));
+    policy.update(FaultSource::Driver, true);
+    policy.update(FaultSource::Driver, false);
+
+    policy.set_rules(rules(FaultAction::Log, 1));
+    assert!(policy.is_latched());
+    assert_eq!(policy.action(), Some(FaultAction::Latch));
+    policy.acknowledge();
+    assert_eq!(policy.action(), None);
+  }
+}
@@ -0,0 +1,4 @@
+#![no_std]
+
+pub mod fault_policy;
+pub mod trajectory;
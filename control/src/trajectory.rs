@@ -0,0 +1,470 @@
+// Bisection steps when searching for the largest safe acceleration.
+const SEARCH_STEPS: u32 = 12;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Shape {
+  // Acceleration steps between zero and its limit.
+  Trapezoidal,
+  // Acceleration ramps at the jerk limit, for smoother moves.
+  SCurve,
+}
+
+#[derive(Copy, Clone)]
+pub struct Limits {
+  pub velocity: f32,
+  pub acceleration: f32,
+  pub jerk: f32,
+}
+
+#[derive(Copy, Clone)]
+pub struct Setpoint {
+  pub position: f32,
+  pub velocity: f32,
+  pub acceleration: f32,
+}
+
+#[derive(Copy, Clone)]
+pub enum Goal {
+  Position(f32),
+  Velocity(f32),
+}
+
+// Generates position, velocity and acceleration setpoints towards a goal that
+// can change at any time, starting from wherever the last setpoint left off.
+// Each step is planned from the current state, so velocity and acceleration
+// (and jerk, for S-curves) stay within the limits even when a move is
+// retargeted partway through.
+pub struct Trajectory {
+  shape: Shape,
+  limits: Limits,
+  goal: Goal,
+  setpoint: Setpoint,
+  // Rounding error in the position setpoint.
+  carry: f32,
+}
+impl Trajectory {
+  pub fn new(shape: Shape, limits: Limits) -> Self {
+    Self {
+      shape,
+      limits,
+      goal: Goal::Velocity(0f32),
+      setpoint: Setpoint {
+        position: 0f32,
+        velocity: 0f32,
+        acceleration: 0f32,
+      },
+      carry: 0f32,
+    }
+  }
+
+  pub fn set_shape(&mut self, shape: Shape) {
+    self.shape = shape;
+  }
+
+  pub fn set_limits(&mut self, limits: Limits) {
+    self.limits = limits;
+  }
+
+  pub fn reset(&mut self, position: f32, velocity: f32) {
+    self.setpoint = Setpoint {
+      position,
+      velocity,
+      acceleration: 0f32,
+    };
+    self.carry = 0f32;
+    self.goal = Goal::Velocity(velocity);
+  }
+
+  pub fn set_goal(&mut self, goal: Goal) {
+    self.goal = goal;
+  }
+
+  pub fn get_setpoint(&self) -> Setpoint {
+    self.setpoint
+  }
+
+  // Whether the setpoint has come to rest at a position goal.
+  pub fn is_done(&self) -> bool {
+    match self.goal {
+      Goal::Position(position) => {
+        self.setpoint.position == position && self.setpoint.velocity == 0f32
+      }
+      Goal::Velocity(_) => false,
+    }
+  }
+
+  // Acceleration for the next step towards a velocity, landing on it exactly
+  // once that is within reach.
+  fn approach_velocity(&self, target: f32, velocity: f32, acceleration: f32, dt: f32) -> f32 {
+    let Limits {
+      acceleration: max_acceleration,
+      jerk: max_jerk,
+      ..
+    } = self.limits;
+    let error = target - velocity;
+    let landing = error / dt;
+
+    match self.shape {
+      Shape::Trapezoidal => landing.max(-max_acceleration).min(max_acceleration),
+      Shape::SCurve => {
+        let max_change = max_jerk * dt;
+        if libm::fabsf(landing) <= max_change.min(max_acceleration)
+          && libm::fabsf(landing - acceleration) <= max_change
+        {
+          return landing;
+        }
+
+        // Largest acceleration from which the velocity can still settle on
+        // the target without passing it, reducing the acceleration by the
+        // jerk limit each step.
+        let settling =
+          (libm::sqrtf(2f32 * max_jerk * libm::fabsf(error)) - max_change / 2f32).max(0f32);
+        let desired = match error < 0f32 {
+          true => -settling,
+          false => settling,
+        };
+        (acceleration + (desired - acceleration).max(-max_change).min(max_change))
+          .max(-max_acceleration)
+          .min(max_acceleration)
+      }
+    }
+  }
+
+  // Distance covered while braking to rest as quickly as the limits allow.
+  fn stopping_distance(&self, velocity: f32, acceleration: f32) -> f32 {
+    let Limits {
+      acceleration: max_acceleration,
+      jerk,
+      ..
+    } = self.limits;
+
+    match self.shape {
+      Shape::Trapezoidal => velocity * libm::fabsf(velocity) / (2f32 * max_acceleration),
+      Shape::SCurve => {
+        // Velocity left once the acceleration has been ramped to zero.
+        let settled = velocity + acceleration * libm::fabsf(acceleration) / (2f32 * jerk);
+        if settled < 0f32 {
+          return -self.stopping_distance(-velocity, -acceleration);
+        }
+
+        // Ramp to the peak deceleration, hold it if it is limited, and ramp
+        // back to zero as the velocity reaches zero. Without a hold, the two
+        // ramps shed this much velocity at a peak of sqrt(jerk * shed).
+        let shed = velocity + acceleration * acceleration / (2f32 * jerk);
+        let peak = libm::sqrtf(jerk * shed);
+        let (peak, hold) = match peak > max_acceleration {
+          true => (
+            max_acceleration,
+            (shed - max_acceleration * max_acceleration / jerk) / max_acceleration,
+          ),
+          false => (peak, 0f32),
+        };
+
+        let mut distance = 0f32;
+        let mut velocity = velocity;
+        let mut acceleration = acceleration;
+        for &(jerk, time) in [
+          (-jerk, (acceleration + peak) / jerk),
+          (0f32, hold),
+          (jerk, peak / jerk),
+        ]
+        .iter()
+        {
+          distance += (velocity + (acceleration / 2f32 + jerk * time / 6f32) * time) * time;
+          velocity += (acceleration + jerk * time / 2f32) * time;
+          acceleration += jerk * time;
+        }
+        distance
+      }
+    }
+  }
+
+  // Largest acceleration for the next step from which the setpoint can still
+  // come to rest without passing the target, `remaining` ahead of it.
+  fn approach_position(&self, remaining: f32, velocity: f32, acceleration: f32, dt: f32) -> f32 {
+    if remaining < 0f32 {
+      return -self.approach_position(-remaining, -velocity, -acceleration, dt);
+    }
+
+    let Limits {
+      velocity: max_velocity,
+      acceleration: max_acceleration,
+      jerk: max_jerk,
+    } = self.limits;
+    let fits = |next_acceleration: f32| {
+      let next_velocity = velocity + next_acceleration * dt;
+      let step = (velocity + next_velocity) / 2f32 * dt;
+      remaining - step >= self.stopping_distance(next_velocity, next_acceleration)
+    };
+
+    let cruise = self.approach_velocity(max_velocity, velocity, acceleration, dt);
+    if fits(cruise) {
+      return cruise;
+    }
+
+    let mut low = match self.shape {
+      Shape::Trapezoidal => -max_acceleration,
+      Shape::SCurve => (acceleration - max_jerk * dt).max(-max_acceleration),
+    };
+    let mut high = cruise;
+    if !fits(low) {
+      return low;
+    }
+    for _ in 0..SEARCH_STEPS {
+      let middle = (low + high) / 2f32;
+      match fits(middle) {
+        true => low = middle,
+        false => high = middle,
+      }
+    }
+    low
+  }
+
+  pub fn step(&mut self, dt: f32) -> Setpoint {
+    if dt <= 0f32 {
+      return self.setpoint;
+    }
+
+    let Limits {
+      velocity: max_velocity,
+      acceleration: max_acceleration,
+      jerk: max_jerk,
+    } = self.limits;
+    let Setpoint {
+      position,
+      velocity,
+      acceleration,
+    } = self.setpoint;
+
+    let acceleration = match self.goal {
+      Goal::Velocity(target) => self.approach_velocity(
+        target.max(-max_velocity).min(max_velocity),
+        velocity,
+        acceleration,
+        dt,
+      ),
+      Goal::Position(target) => {
+        let remaining = (target - position) + self.carry;
+
+        // Close enough to stop without breaking the limits.
+        if libm::fabsf(remaining) <= (libm::fabsf(velocity) + max_acceleration * dt) * dt
+          && libm::fabsf(velocity) <= max_acceleration * dt
+          && (self.shape == Shape::Trapezoidal || libm::fabsf(acceleration) <= max_jerk * dt)
+        {
+          self.setpoint = Setpoint {
+            position: target,
+            velocity: 0f32,
+            acceleration: 0f32,
+          };
+          self.carry = 0f32;
+          return self.setpoint;
+        }
+
+        self.approach_position(remaining, velocity, acceleration, dt)
+      }
+    };
+
+    // The position is summed with compensation, as each step can be smaller
+    // than its resolution near the end of a move.
+    let next_velocity = velocity + acceleration * dt;
+    let step = (velocity + next_velocity) / 2f32 * dt - self.carry;
+    let next_position = position + step;
+    self.carry = (next_position - position) - step;
+
+    self.setpoint = Setpoint {
+      position: next_position,
+      velocity: next_velocity,
+      acceleration,
+    };
+    self.setpoint
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DT: f32 = 0.001;
+  const LIMITS: Limits = Limits {
+    velocity: 20f32,
+    acceleration: 200f32,
+    jerk: 5000f32,
+  };
+  // Allowance for rounding, relative to each limit.
+  const TOLERANCE: f32 = 1e-3;
+
+  // Steps until the move finishes or `steps` runs out, checking the limits on
+  // every step. Returns the number of steps taken.
+  fn run(trajectory: &mut Trajectory, steps: u32) -> u32 {
+    let Limits {
+      velocity: max_velocity,
+      acceleration: max_acceleration,
+      jerk: max_jerk,
+    } = LIMITS;
+
+    for step in 0..steps {
+      let previous = trajectory.get_setpoint();
+      let setpoint = trajectory.step(DT);
+
+      let velocity_change = (setpoint.velocity - previous.velocity) / DT;
+      let acceleration_change = (setpoint.acceleration - previous.acceleration) / DT;
+      assert!(
+        setpoint.velocity.abs() <= max_velocity * (1f32 + TOLERANCE),
+        "velocity {} at step {}",
+        setpoint.velocity,
+        step
+      );
+      assert!(
+        setpoint.acceleration.abs() <= max_acceleration * (1f32 + TOLERANCE),
+        "acceleration {} at step {}",
+        setpoint.acceleration,
+        step
+      );
+      assert!(
+        velocity_change.abs() <= max_acceleration * (1f32 + TOLERANCE),
+        "velocity change {} at step {}",
+        velocity_change,
+        step
+      );
+      if trajectory.shape == Shape::SCurve {
+        assert!(
+          acceleration_change.abs() <= max_jerk * (1f32 + TOLERANCE),
+          "jerk {} at step {}",
+          acceleration_change,
+          step
+        );
+      }
+
+      if trajectory.is_done() {
+        return step + 1;
+      }
+    }
+    steps
+  }
+
+  fn trajectory(shape: Shape) -> Trajectory {
+    let mut trajectory = Trajectory::new(shape, LIMITS);
+    trajectory.reset(0f32, 0f32);
+    trajectory
+  }
+
+  fn assert_at_rest(trajectory: &Trajectory, position: f32) {
+    let setpoint = trajectory.get_setpoint();
+    assert!(trajectory.is_done());
+    assert_eq!(setpoint.position, position);
+    assert_eq!(setpoint.velocity, 0f32);
+    assert_eq!(setpoint.acceleration, 0f32);
+  }
+
+  #[test]
+  fn trapezoidal_move_reaches_target() {
+    let mut trajectory = trajectory(Shape::Trapezoidal);
+    trajectory.set_goal(Goal::Position(10f32));
+    let steps = run(&mut trajectory, 5000);
+    assert_at_rest(&trajectory, 10f32);
+
+    // Accelerating and braking take 0.1 s each and the rest is cruising, for
+    // 0.6 s in all.
+    assert!((550..700).contains(&steps), "took {} steps", steps);
+  }
+
+  #[test]
+  fn trapezoidal_short_move_never_cruises() {
+    let mut trajectory = trajectory(Shape::Trapezoidal);
+    trajectory.set_goal(Goal::Position(-0.5f32));
+    let mut peak = 0f32;
+    for _ in 0..1000 {
+      run(&mut trajectory, 1);
+      peak = peak.max(trajectory.get_setpoint().velocity.abs());
+    }
+    assert_at_rest(&trajectory, -0.5f32);
+    assert!(peak < LIMITS.velocity);
+  }
+
+  #[test]
+  fn s_curve_move_reaches_target() {
+    let mut trajectory = trajectory(Shape::SCurve);
+    trajectory.set_goal(Goal::Position(10f32));
+    let steps = run(&mut trajectory, 5000);
+    assert_at_rest(&trajectory, 10f32);
+    assert!(steps < 1000, "took {} steps", steps);
+  }
+
+  #[test]
+  fn s_curve_short_move_reaches_target() {
+    let mut trajectory = trajectory(Shape::SCurve);
+    trajectory.set_goal(Goal::Position(0.01f32));
+    run(&mut trajectory, 5000);
+    assert_at_rest(&trajectory, 0.01f32);
+  }
+
+  #[test]
+  fn retargeting_mid_move_stays_within_limits() {
+    for &shape in [Shape::Trapezoidal, Shape::SCurve].iter() {
+      let mut trajectory = trajectory(shape);
+      trajectory.set_goal(Goal::Position(10f32));
+      run(&mut trajectory, 200);
+      assert!(trajectory.get_setpoint().velocity > 0f32);
+
+      // Reverse while moving at speed.
+      trajectory.set_goal(Goal::Position(-5f32));
+      run(&mut trajectory, 150);
+
+      // Retarget to just ahead, closer than the stopping distance.
+      let setpoint = trajectory.get_setpoint();
+      let ahead = setpoint.position + setpoint.velocity.signum() * 0.05f32;
+      trajectory.set_goal(Goal::Position(ahead));
+      run(&mut trajectory, 5000);
+      assert_at_rest(&trajectory, ahead);
+    }
+  }
+
+  #[test]
+  fn velocity_goal_settles_on_target() {
+    for &shape in [Shape::Trapezoidal, Shape::SCurve].iter() {
+      let mut trajectory = trajectory(shape);
+      trajectory.set_goal(Goal::Velocity(8f32));
+      run(&mut trajectory, 1000);
+      assert_eq!(trajectory.get_setpoint().velocity, 8f32);
+
+      // Goals beyond the velocity limit are clamped to it.
+      trajectory.set_goal(Goal::Velocity(-100f32));
+      run(&mut trajectory, 1000);
+      assert_eq!(trajectory.get_setpoint().velocity, -LIMITS.velocity);
+      assert!(!trajectory.is_done());
+    }
+  }
+
+  #[test]
+  fn position_goal_from_velocity_goal() {
+    for &shape in [Shape::Trapezoidal, Shape::SCurve].iter() {
+      let mut trajectory = trajectory(shape);
+      trajectory.set_goal(Goal::Velocity(15f32));
+      run(&mut trajectory, 300);
+
+      // Behind the setpoint, so it has to brake and come back.
+      trajectory.set_goal(Goal::Position(0f32));
+      run(&mut trajectory, 5000);
+      assert_at_rest(&trajectory, 0f32);
+    }
+  }
+
+  #[test]
+  fn reset_starts_from_given_state() {
+    let mut trajectory = trajectory(Shape::Trapezoidal);
+    trajectory.reset(3f32, 5f32);
+    run(&mut trajectory, 1);
+    let setpoint = trajectory.get_setpoint();
+    assert_eq!(setpoint.velocity, 5f32);
+    assert!((setpoint.position - 3.005f32).abs() < 1e-6);
+  }
+
+  #[test]
+  fn zero_dt_holds_setpoint() {
+    let mut trajectory = trajectory(Shape::SCurve);
+    trajectory.set_goal(Goal::Position(1f32));
+    let setpoint = trajectory.step(0f32);
+    assert_eq!(setpoint.position, 0f32);
+    assert_eq!(setpoint.velocity, 0f32);
+  }
+}
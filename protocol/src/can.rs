@@ -0,0 +1,382 @@
+// CAN message set
+//
+// Standard 11-bit identifiers are split into a 6-bit node ID and a 5-bit
+// message ID: `id = node_id << 5 | message_id`. All multi-byte fields are
+// little-endian, and f32 values are IEEE 754 single precision.
+//
+// | ID   | Name            | Dir | Payload                                          |
+// |------|-----------------|-----|--------------------------------------------------|
+// | 0x01 | Heartbeat       | tx  | mode u8, flags u8, counter u8                    |
+// | 0x02 | Enable          | rx  | (none)                                           |
+// | 0x03 | Disable         | rx  | (none)                                           |
+// | 0x04 | SetMode         | rx  | mode u8                                          |
+// | 0x05 | SetTorque       | rx  | effort f32 (-1 to 1)                             |
+// | 0x06 | SetVelocity     | rx  | velocity f32 (rad/s)                             |
+// | 0x07 | SetPosition     | rx  | position f32 (rad)                               |
+// | 0x08 | SetPublishRate  | rx  | status period u16 (ms, 0 disables)               |
+// | 0x09 | MotionStatus    | tx  | position f32 (rad), velocity f32 (rad/s)         |
+// | 0x0A | DriveStatus     | tx  | current f32 (A), warnings u16, faults u16        |
+//
+// Heartbeat flags: bit 0 is set while the gate driver is enabled, bit 1 while
+// the controller is in recovery. Modes use the `ModeId` values, and faults the
+// `Fault` bits. The current is the phase current amplitude, or NaN without
+// current sensing.
+// Frames for other node IDs are ignored; node ID 0 is reserved as broadcast.
+
+use crate::{
+  codec::{Reader, Writer},
+  Error, ModeId, Result,
+};
+
+pub const BROADCAST_NODE_ID: u8 = 0;
+pub const MAX_NODE_ID: u8 = 0x3F;
+
+const FLAG_GATE_ENABLED: u8 = 1 << 0;
+const FLAG_RECOVERY: u8 = 1 << 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frame {
+  pub id: u16,
+  pub len: u8,
+  pub data: [u8; 8],
+}
+impl Frame {
+  pub fn new(id: u16, payload: &[u8]) -> Result<Self> {
+    if payload.len() > 8 {
+      return Err(Error::BufferTooSmall);
+    }
+
+    let mut data = [0u8; 8];
+    data[..payload.len()].copy_from_slice(payload);
+    Ok(Self {
+      id,
+      len: payload.len() as u8,
+      data,
+    })
+  }
+
+  pub fn payload(&self) -> &[u8] {
+    &self.data[..(self.len as usize).min(8)]
+  }
+
+  pub fn node_id(&self) -> u8 {
+    (self.id >> 5) as u8 & MAX_NODE_ID
+  }
+
+  pub fn message_id(&self) -> u8 {
+    (self.id & 0x1F) as u8
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum MessageId {
+  Heartbeat = 0x01,
+  Enable = 0x02,
+  Disable = 0x03,
+  SetMode = 0x04,
+  SetTorque = 0x05,
+  SetVelocity = 0x06,
+  SetPosition = 0x07,
+  SetPublishRate = 0x08,
+  MotionStatus = 0x09,
+  DriveStatus = 0x0A,
+}
+
+pub fn frame_id(node_id: u8, message_id: MessageId) -> u16 {
+  ((node_id & MAX_NODE_ID) as u16) << 5 | message_id as u16
+}
+
+fn encode_frame<F>(node_id: u8, message_id: MessageId, write: F) -> Result<Frame>
+where
+  F: FnOnce(&mut Writer) -> Result<()>,
+{
+  let mut buf = [0u8; 8];
+  let mut w = Writer::new(&mut buf);
+  write(&mut w)?;
+  let len = w.len();
+  Frame::new(frame_id(node_id, message_id), &buf[..len])
+}
+
+fn flags(gate_enabled: bool, recovery: bool) -> u8 {
+  let mut flags = 0;
+  if gate_enabled {
+    flags |= FLAG_GATE_ENABLED;
+  }
+  if recovery {
+    flags |= FLAG_RECOVERY;
+  }
+  flags
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+  Enable,
+  Disable,
+  SetMode(ModeId),
+  SetTorque(f32),
+  SetVelocity(f32),
+  SetPosition(f32),
+  SetPublishRate(u16),
+}
+impl Command {
+  // Returns `None` for frames addressed to other nodes or carrying messages
+  // that are not commands.
+  pub fn decode(node_id: u8, frame: &Frame) -> Result<Option<Self>> {
+    let addressee = frame.node_id();
+    if addressee != node_id && addressee != BROADCAST_NODE_ID {
+      return Ok(None);
+    }
+
+    let mut r = Reader::new(frame.payload());
+    let command = match frame.message_id() {
+      0x02 => Command::Enable,
+      0x03 => Command::Disable,
+      0x04 => Command::SetMode(ModeId::from_u8(r.u8()?)?),
+      0x05 => Command::SetTorque(r.f32()?),
+      0x06 => Command::SetVelocity(r.f32()?),
+      0x07 => Command::SetPosition(r.f32()?),
+      0x08 => Command::SetPublishRate(r.u16()?),
+      _ => return Ok(None),
+    };
+    r.finish()?;
+
+    Ok(Some(command))
+  }
+
+  pub fn encode(&self, node_id: u8) -> Result<Frame> {
+    match self {
+      Command::Enable => encode_frame(node_id, MessageId::Enable, |_| Ok(())),
+      Command::Disable => encode_frame(node_id, MessageId::Disable, |_| Ok(())),
+      Command::SetMode(mode) => {
+        encode_frame(node_id, MessageId::SetMode, |w| w.put_u8(*mode as u8))
+      }
+      Command::SetTorque(effort) => {
+        encode_frame(node_id, MessageId::SetTorque, |w| w.put_f32(*effort))
+      }
+      Command::SetVelocity(velocity) => {
+        encode_frame(node_id, MessageId::SetVelocity, |w| w.put_f32(*velocity))
+      }
+      Command::SetPosition(position) => {
+        encode_frame(node_id, MessageId::SetPosition, |w| w.put_f32(*position))
+      }
+      Command::SetPublishRate(period_ms) => encode_frame(node_id, MessageId::SetPublishRate, |w| {
+        w.put_u16(*period_ms)
+      }),
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Heartbeat {
+  pub mode: ModeId,
+  pub gate_enabled: bool,
+  pub recovery: bool,
+  pub counter: u8,
+}
+impl Heartbeat {
+  pub fn encode(&self, node_id: u8) -> Result<Frame> {
+    encode_frame(node_id, MessageId::Heartbeat, |w| {
+      w.put_u8(self.mode as u8)?;
+      w.put_u8(flags(self.gate_enabled, self.recovery))?;
+      w.put_u8(self.counter)
+    })
+  }
+
+  pub fn decode(frame: &Frame) -> Result<Self> {
+    let mut r = Reader::new(frame.payload());
+    let mode = ModeId::from_u8(r.u8()?)?;
+    let flags = r.u8()?;
+    let counter = r.u8()?;
+    r.finish()?;
+
+    Ok(Self {
+      mode,
+      gate_enabled: flags & FLAG_GATE_ENABLED != 0,
+      recovery: flags & FLAG_RECOVERY != 0,
+      counter,
+    })
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MotionStatus {
+  pub position: f32,
+  pub velocity: f32,
+}
+impl MotionStatus {
+  pub fn encode(&self, node_id: u8) -> Result<Frame> {
+    encode_frame(node_id, MessageId::MotionStatus, |w| {
+      w.put_f32(self.position)?;
+      w.put_f32(self.velocity)
+    })
+  }
+
+  pub fn decode(frame: &Frame) -> Result<Self> {
+    let mut r = Reader::new(frame.payload());
+    let status = Self {
+      position: r.f32()?,
+      velocity: r.f32()?,
+    };
+    r.finish()?;
+    Ok(status)
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DriveStatus {
+  pub current: f32,
+  pub warnings: u16,
+  pub faults: u16,
+}
+impl DriveStatus {
+  pub fn encode(&self, node_id: u8) -> Result<Frame> {
+    encode_frame(node_id, MessageId::DriveStatus, |w| {
+      w.put_f32(self.current)?;
+      w.put_u16(self.warnings)?;
+      w.put_u16(self.faults)
+    })
+  }
+
+  pub fn decode(frame: &Frame) -> Result<Self> {
+    let mut r = Reader::new(frame.payload());
+    let status = Self {
+      current: r.f32()?,
+      warnings: r.u16()?,
+      faults: r.u16()?,
+    };
+    r.finish()?;
+    Ok(status)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NODE_ID: u8 = 0x12;
+
+  #[test]
+  fn commands_round_trip() {
+    let commands = [
+      Command::Enable,
+      Command::Disable,
+      Command::SetMode(ModeId::Velocity),
+      Command::SetTorque(-0.25),
+      Command::SetVelocity(12.5),
+      Command::SetPosition(-3.75),
+      Command::SetPublishRate(20),
+    ];
+    for command in commands.iter() {
+      let frame = command.encode(NODE_ID).unwrap();
+      assert_eq!(frame.node_id(), NODE_ID);
+      assert_eq!(Command::decode(NODE_ID, &frame), Ok(Some(*command)));
+    }
+  }
+
+  #[test]
+  fn heartbeat_round_trips() {
+    let heartbeat = Heartbeat {
+      mode: ModeId::Recovery,
+      gate_enabled: true,
+      recovery: true,
+      counter: 200,
+    };
+    let frame = heartbeat.encode(NODE_ID).unwrap();
+    assert_eq!(frame.id, frame_id(NODE_ID, MessageId::Heartbeat));
+    assert_eq!(frame.len, 3);
+    assert_eq!(Heartbeat::decode(&frame), Ok(heartbeat));
+  }
+
+  #[test]
+  fn motion_status_round_trips() {
+    let status = MotionStatus {
+      position: 1.5,
+      velocity: -40f32,
+    };
+    let frame = status.encode(NODE_ID).unwrap();
+    assert_eq!(frame.id, frame_id(NODE_ID, MessageId::MotionStatus));
+    assert_eq!(MotionStatus::decode(&frame), Ok(status));
+  }
+
+  #[test]
+  fn drive_status_round_trips() {
+    let status = DriveStatus {
+      current: 3.25,
+      warnings: 0x0102,
+      faults: 0x0410,
+    };
+    let frame = status.encode(NODE_ID).unwrap();
+    assert_eq!(frame.id, frame_id(NODE_ID, MessageId::DriveStatus));
+    assert_eq!(frame.len, 8);
+    assert_eq!(DriveStatus::decode(&frame), Ok(status));
+
+    let unmeasured = DriveStatus {
+      current: f32::NAN,
+      ..status
+    };
+    let decoded = DriveStatus::decode(&unmeasured.encode(NODE_ID).unwrap()).unwrap();
+    assert!(decoded.current.is_nan());
+  }
+
+  #[test]
+  fn identifiers_split_into_node_and_message() {
+    let id = frame_id(0x3F, MessageId::DriveStatus);
+    assert_eq!(id, 0x3F << 5 | 0x0A);
+    let frame = Frame::new(id, &[]).unwrap();
+    assert_eq!(frame.node_id(), 0x3F);
+    assert_eq!(frame.message_id(), 0x0A);
+
+    // Node IDs wider than six bits are masked rather than spilling over.
+    assert_eq!(
+      frame_id(0x41, MessageId::Enable),
+      frame_id(0x01, MessageId::Enable)
+    );
+  }
+
+  #[test]
+  fn commands_for_other_nodes_are_ignored() {
+    let frame = Command::Enable.encode(NODE_ID + 1).unwrap();
+    assert_eq!(Command::decode(NODE_ID, &frame), Ok(None));
+  }
+
+  #[test]
+  fn broadcast_commands_are_accepted() {
+    let frame = Command::Disable.encode(BROADCAST_NODE_ID).unwrap();
+    assert_eq!(Command::decode(NODE_ID, &frame), Ok(Some(Command::Disable)));
+  }
+
+  #[test]
+  fn status_messages_are_not_commands() {
+    let frame = MotionStatus {
+      position: 0f32,
+      velocity: 0f32,
+    }
+    .encode(NODE_ID)
+    .unwrap();
+    assert_eq!(Command::decode(NODE_ID, &frame), Ok(None));
+  }
+
+  #[test]
+  fn bad_lengths_are_rejected() {
+    let short = Frame::new(frame_id(NODE_ID, MessageId::SetVelocity), &[0, 0, 0x80]).unwrap();
+    assert_eq!(Command::decode(NODE_ID, &short), Err(Error::Truncated));
+
+    let long = Frame::new(frame_id(NODE_ID, MessageId::Enable), &[1]).unwrap();
+    assert_eq!(Command::decode(NODE_ID, &long), Err(Error::TrailingBytes));
+
+    let heartbeat = Frame::new(frame_id(NODE_ID, MessageId::Heartbeat), &[0, 0]).unwrap();
+    assert_eq!(Heartbeat::decode(&heartbeat), Err(Error::Truncated));
+
+    let drive = Frame::new(frame_id(NODE_ID, MessageId::DriveStatus), &[0; 7]).unwrap();
+    assert_eq!(DriveStatus::decode(&drive), Err(Error::Truncated));
+
+    assert_eq!(Frame::new(0, &[0; 9]), Err(Error::BufferTooSmall));
+  }
+
+  #[test]
+  fn invalid_modes_are_rejected() {
+    let frame = Frame::new(frame_id(NODE_ID, MessageId::SetMode), &[0xEE]).unwrap();
+    assert_eq!(Command::decode(NODE_ID, &frame), Err(Error::InvalidValue));
+  }
+}
@@ -0,0 +1,398 @@
+// CiA 402 power state machine.
+//
+// The state machine only tracks states; the owner is responsible for applying
+// the outputs (`gate_enabled`, `operation_enabled`) to the hardware and for
+// reporting when a quick stop or fault reaction has completed.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum State {
+  NotReadyToSwitchOn,
+  SwitchOnDisabled,
+  ReadyToSwitchOn,
+  SwitchedOn,
+  OperationEnabled,
+  QuickStopActive,
+  FaultReactionActive,
+  Fault,
+}
+impl State {
+  pub fn gate_enabled(&self) -> bool {
+    matches!(
+      self,
+      State::SwitchedOn
+        | State::OperationEnabled
+        | State::QuickStopActive
+        | State::FaultReactionActive
+    )
+  }
+
+  pub fn operation_enabled(&self) -> bool {
+    *self == State::OperationEnabled
+  }
+
+  fn statusword_bits(&self) -> u16 {
+    match self {
+      State::NotReadyToSwitchOn => 0x0000,
+      State::SwitchOnDisabled => 0x0040,
+      State::ReadyToSwitchOn => 0x0021,
+      State::SwitchedOn => 0x0023,
+      State::OperationEnabled => 0x0027,
+      State::QuickStopActive => 0x0007,
+      State::FaultReactionActive => 0x000F,
+      State::Fault => 0x0008,
+    }
+  }
+}
+
+pub mod controlword {
+  pub const SWITCH_ON: u16 = 1 << 0;
+  pub const ENABLE_VOLTAGE: u16 = 1 << 1;
+  pub const QUICK_STOP: u16 = 1 << 2;
+  pub const ENABLE_OPERATION: u16 = 1 << 3;
+  // Profile position: a rising edge starts a move to the target position.
+  pub const NEW_SETPOINT: u16 = 1 << 4;
+  // Profile position: abort the current move rather than queue behind it.
+  pub const CHANGE_SET_IMMEDIATELY: u16 = 1 << 5;
+  // Profile position: the target is relative to the current target.
+  pub const RELATIVE: u16 = 1 << 6;
+  pub const FAULT_RESET: u16 = 1 << 7;
+  pub const HALT: u16 = 1 << 8;
+}
+
+pub mod statusword {
+  pub const VOLTAGE_ENABLED: u16 = 1 << 4;
+  pub const WARNING: u16 = 1 << 7;
+  pub const REMOTE: u16 = 1 << 9;
+  pub const TARGET_REACHED: u16 = 1 << 10;
+  // Profile position: set-point acknowledge. Profile velocity: speed is zero.
+  pub const OPERATION_MODE_SPECIFIC: u16 = 1 << 12;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DeviceControl {
+  Shutdown,
+  SwitchOn,
+  SwitchOnAndEnable,
+  DisableVoltage,
+  QuickStop,
+}
+
+fn decode_controlword(controlword: u16) -> DeviceControl {
+  use controlword::*;
+
+  if controlword & ENABLE_VOLTAGE == 0 {
+    return DeviceControl::DisableVoltage;
+  }
+  if controlword & QUICK_STOP == 0 {
+    return DeviceControl::QuickStop;
+  }
+  match controlword & (SWITCH_ON | ENABLE_OPERATION) {
+    SWITCH_ON => DeviceControl::SwitchOn,
+    x if x == SWITCH_ON | ENABLE_OPERATION => DeviceControl::SwitchOnAndEnable,
+    _ => DeviceControl::Shutdown,
+  }
+}
+
+pub struct PowerStateMachine {
+  state: State,
+  last_controlword: u16,
+}
+impl Default for PowerStateMachine {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+impl PowerStateMachine {
+  pub fn new() -> Self {
+    Self {
+      state: State::NotReadyToSwitchOn,
+      last_controlword: 0,
+    }
+  }
+
+  pub fn state(&self) -> State {
+    self.state
+  }
+
+  // Transition 1, taken once the drive has finished its own initialization.
+  pub fn initialized(&mut self) {
+    if self.state == State::NotReadyToSwitchOn {
+      self.state = State::SwitchOnDisabled;
+    }
+  }
+
+  // Transition 13. Any state may be interrupted by a fault.
+  pub fn fault(&mut self) {
+    match self.state {
+      State::Fault | State::FaultReactionActive => {}
+      _ => self.state = State::FaultReactionActive,
+    }
+  }
+
+  // Transition 14.
+  pub fn fault_reaction_complete(&mut self) {
+    if self.state == State::FaultReactionActive {
+      self.state = State::Fault;
+    }
+  }
+
+  // Transition 12.
+  pub fn quick_stop_complete(&mut self) {
+    if self.state == State::QuickStopActive {
+      self.state = State::SwitchOnDisabled;
+    }
+  }
+
+  pub fn apply_controlword(&mut self, controlword: u16) -> State {
+    let fault_reset = controlword & controlword::FAULT_RESET != 0
+      && self.last_controlword & controlword::FAULT_RESET == 0;
+    self.last_controlword = controlword;
+
+    let command = decode_controlword(controlword);
+    self.state = match (self.state, command) {
+      (State::Fault, _) if fault_reset => State::SwitchOnDisabled,
+      (State::Fault, _) | (State::FaultReactionActive, _) | (State::NotReadyToSwitchOn, _) => {
+        self.state
+      }
+
+      (State::SwitchOnDisabled, DeviceControl::Shutdown) => State::ReadyToSwitchOn,
+      (State::SwitchOnDisabled, _) => State::SwitchOnDisabled,
+
+      (State::ReadyToSwitchOn, DeviceControl::SwitchOn) => State::SwitchedOn,
+      (State::ReadyToSwitchOn, DeviceControl::SwitchOnAndEnable) => State::OperationEnabled,
+      (State::ReadyToSwitchOn, DeviceControl::DisableVoltage)
+      | (State::ReadyToSwitchOn, DeviceControl::QuickStop) => State::SwitchOnDisabled,
+      (State::ReadyToSwitchOn, DeviceControl::Shutdown) => State::ReadyToSwitchOn,
+
+      (State::SwitchedOn, DeviceControl::SwitchOnAndEnable) => State::OperationEnabled,
+      (State::SwitchedOn, DeviceControl::Shutdown) => State::ReadyToSwitchOn,
+      (State::SwitchedOn, DeviceControl::DisableVoltage)
+      | (State::SwitchedOn, DeviceControl::QuickStop) => State::SwitchOnDisabled,
+      (State::SwitchedOn, DeviceControl::SwitchOn) => State::SwitchedOn,
+
+      (State::OperationEnabled, DeviceControl::SwitchOn) => State::SwitchedOn,
+      (State::OperationEnabled, DeviceControl::Shutdown) => State::ReadyToSwitchOn,
+      (State::OperationEnabled, DeviceControl::DisableVoltage) => State::SwitchOnDisabled,
+      (State::OperationEnabled, DeviceControl::QuickStop) => State::QuickStopActive,
+      (State::OperationEnabled, DeviceControl::SwitchOnAndEnable) => State::OperationEnabled,
+
+      (State::QuickStopActive, DeviceControl::DisableVoltage) => State::SwitchOnDisabled,
+      (State::QuickStopActive, _) => State::QuickStopActive,
+    };
+
+    self.state
+  }
+
+  // `extra` carries the warning, remote, target reached and mode specific bits.
+  pub fn statusword(&self, extra: u16) -> u16 {
+    let voltage = match self.state.gate_enabled() {
+      true => statusword::VOLTAGE_ENABLED,
+      false => 0,
+    };
+    self.state.statusword_bits() | voltage | (extra & 0xFF80)
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(i8)]
+pub enum OperationMode {
+  NoMode = 0,
+  ProfilePosition = 1,
+  ProfileVelocity = 3,
+}
+impl OperationMode {
+  pub fn from_i8(value: i8) -> Option<Self> {
+    match value {
+      0 => Some(OperationMode::NoMode),
+      1 => Some(OperationMode::ProfilePosition),
+      3 => Some(OperationMode::ProfileVelocity),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SHUTDOWN: u16 = 0x0006;
+  const SWITCH_ON: u16 = 0x0007;
+  const ENABLE_OPERATION: u16 = 0x000F;
+  const QUICK_STOP: u16 = 0x0002;
+  const DISABLE_VOLTAGE: u16 = 0x0000;
+  const FAULT_RESET: u16 = 0x0080;
+
+  fn enabled() -> PowerStateMachine {
+    let mut machine = PowerStateMachine::new();
+    machine.initialized();
+    machine.apply_controlword(SHUTDOWN);
+    machine.apply_controlword(SWITCH_ON);
+    machine.apply_controlword(ENABLE_OPERATION);
+    machine
+  }
+
+  #[test]
+  fn starts_not_ready_until_initialized() {
+    let mut machine = PowerStateMachine::new();
+    assert_eq!(machine.state(), State::NotReadyToSwitchOn);
+    assert_eq!(
+      machine.apply_controlword(SHUTDOWN),
+      State::NotReadyToSwitchOn
+    );
+
+    machine.initialized();
+    assert_eq!(machine.state(), State::SwitchOnDisabled);
+    assert_eq!(machine.statusword(0) & 0x6F, 0x40);
+  }
+
+  #[test]
+  fn controlword_enables_operation_step_by_step() {
+    let mut machine = PowerStateMachine::new();
+    machine.initialized();
+
+    assert_eq!(machine.apply_controlword(SHUTDOWN), State::ReadyToSwitchOn);
+    assert!(!machine.state().gate_enabled());
+    assert_eq!(machine.statusword(0), 0x0021);
+
+    assert_eq!(machine.apply_controlword(SWITCH_ON), State::SwitchedOn);
+    assert!(machine.state().gate_enabled());
+    assert_eq!(machine.statusword(0), 0x0033);
+
+    assert_eq!(
+      machine.apply_controlword(ENABLE_OPERATION),
+      State::OperationEnabled
+    );
+    assert!(machine.state().operation_enabled());
+    assert_eq!(machine.statusword(0), 0x0037);
+  }
+
+  #[test]
+  fn enable_is_ignored_until_shutdown() {
+    let mut machine = PowerStateMachine::new();
+    machine.initialized();
+    assert_eq!(
+      machine.apply_controlword(ENABLE_OPERATION),
+      State::SwitchOnDisabled
+    );
+  }
+
+  #[test]
+  fn ready_to_switch_on_can_enable_directly() {
+    let mut machine = PowerStateMachine::new();
+    machine.initialized();
+    machine.apply_controlword(SHUTDOWN);
+    assert_eq!(
+      machine.apply_controlword(ENABLE_OPERATION),
+      State::OperationEnabled
+    );
+  }
+
+  #[test]
+  fn operation_can_be_stepped_back_down() {
+    let mut machine = enabled();
+    assert_eq!(machine.apply_controlword(SWITCH_ON), State::SwitchedOn);
+    assert_eq!(machine.apply_controlword(SHUTDOWN), State::ReadyToSwitchOn);
+    assert_eq!(
+      machine.apply_controlword(DISABLE_VOLTAGE),
+      State::SwitchOnDisabled
+    );
+
+    let mut machine = enabled();
+    assert_eq!(
+      machine.apply_controlword(DISABLE_VOLTAGE),
+      State::SwitchOnDisabled
+    );
+  }
+
+  #[test]
+  fn quick_stop_holds_until_complete() {
+    let mut machine = enabled();
+    assert_eq!(
+      machine.apply_controlword(QUICK_STOP),
+      State::QuickStopActive
+    );
+    assert!(machine.state().gate_enabled());
+    assert!(!machine.state().operation_enabled());
+    assert_eq!(machine.statusword(0), 0x0017);
+
+    // Re-enabling does not cut the quick stop short.
+    assert_eq!(
+      machine.apply_controlword(ENABLE_OPERATION),
+      State::QuickStopActive
+    );
+
+    machine.quick_stop_complete();
+    assert_eq!(machine.state(), State::SwitchOnDisabled);
+  }
+
+  #[test]
+  fn quick_stop_outside_operation_disables() {
+    let mut machine = PowerStateMachine::new();
+    machine.initialized();
+    machine.apply_controlword(SHUTDOWN);
+    assert_eq!(
+      machine.apply_controlword(QUICK_STOP),
+      State::SwitchOnDisabled
+    );
+  }
+
+  #[test]
+  fn fault_reaction_then_fault() {
+    let mut machine = enabled();
+    machine.fault();
+    assert_eq!(machine.state(), State::FaultReactionActive);
+    assert!(machine.state().gate_enabled());
+    assert_eq!(machine.statusword(0), 0x001F);
+
+    // Controlwords are ignored during the reaction.
+    assert_eq!(
+      machine.apply_controlword(DISABLE_VOLTAGE),
+      State::FaultReactionActive
+    );
+
+    machine.fault_reaction_complete();
+    assert_eq!(machine.state(), State::Fault);
+    assert!(!machine.state().gate_enabled());
+    assert_eq!(machine.statusword(0), 0x0008);
+
+    // A repeated fault does not restart the reaction.
+    machine.fault();
+    assert_eq!(machine.state(), State::Fault);
+  }
+
+  #[test]
+  fn fault_reset_needs_a_rising_edge() {
+    let mut machine = enabled();
+    machine.fault();
+    machine.fault_reaction_complete();
+
+    assert_eq!(machine.apply_controlword(ENABLE_OPERATION), State::Fault);
+    assert_eq!(
+      machine.apply_controlword(FAULT_RESET | SHUTDOWN),
+      State::SwitchOnDisabled
+    );
+
+    // Holding the bit through another fault does not reset it again.
+    machine.fault();
+    machine.fault_reaction_complete();
+    assert_eq!(
+      machine.apply_controlword(FAULT_RESET | SHUTDOWN),
+      State::Fault
+    );
+    machine.apply_controlword(SHUTDOWN);
+    assert_eq!(
+      machine.apply_controlword(FAULT_RESET),
+      State::SwitchOnDisabled
+    );
+  }
+
+  #[test]
+  fn statusword_keeps_only_the_upper_extra_bits() {
+    let machine = enabled();
+    let extra = statusword::REMOTE | statusword::TARGET_REACHED | 0x007F;
+    assert_eq!(
+      machine.statusword(extra),
+      0x0037 | statusword::REMOTE | statusword::TARGET_REACHED
+    );
+  }
+}
@@ -0,0 +1,85 @@
+// CANopen (CiA 301) communication layer and CiA 402 drive profile.
+//
+// Only what the controller needs is implemented: NMT slave, heartbeat
+// producer, an expedited SDO server and fixed PDO mappings. COB-IDs use the
+// predefined connection set, `function code + node_id` with 7-bit node IDs.
+
+pub mod cia402;
+pub mod od;
+pub mod sdo;
+
+use crate::can::Frame;
+use crate::Result;
+
+pub const MAX_NODE_ID: u8 = 0x7F;
+
+pub const NMT: u16 = 0x000;
+pub const TPDO1: u16 = 0x180;
+pub const RPDO1: u16 = 0x200;
+pub const TPDO2: u16 = 0x280;
+pub const RPDO2: u16 = 0x300;
+pub const TPDO3: u16 = 0x380;
+pub const RPDO3: u16 = 0x400;
+pub const SDO_TX: u16 = 0x580;
+pub const SDO_RX: u16 = 0x600;
+pub const HEARTBEAT: u16 = 0x700;
+
+pub fn cob_id(function: u16, node_id: u8) -> u16 {
+  function | (node_id & MAX_NODE_ID) as u16
+}
+
+// Splits a COB-ID into its function code and node ID.
+pub fn split_cob_id(id: u16) -> (u16, u8) {
+  (id & 0x780, (id & MAX_NODE_ID as u16) as u8)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum NmtState {
+  Initializing = 0x00,
+  Stopped = 0x04,
+  Operational = 0x05,
+  PreOperational = 0x7F,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NmtCommand {
+  Start,
+  Stop,
+  EnterPreOperational,
+  ResetNode,
+  ResetCommunication,
+}
+impl NmtCommand {
+  // Returns `None` unless the frame is an NMT command for this node or for all
+  // nodes.
+  pub fn decode(node_id: u8, frame: &Frame) -> Option<Self> {
+    let payload = frame.payload();
+    if frame.id != NMT || payload.len() != 2 || (payload[1] != 0 && payload[1] != node_id) {
+      return None;
+    }
+
+    match payload[0] {
+      0x01 => Some(NmtCommand::Start),
+      0x02 => Some(NmtCommand::Stop),
+      0x80 => Some(NmtCommand::EnterPreOperational),
+      0x81 => Some(NmtCommand::ResetNode),
+      0x82 => Some(NmtCommand::ResetCommunication),
+      _ => None,
+    }
+  }
+
+  pub fn next_state(&self) -> NmtState {
+    match self {
+      NmtCommand::Start => NmtState::Operational,
+      NmtCommand::Stop => NmtState::Stopped,
+      NmtCommand::EnterPreOperational => NmtState::PreOperational,
+      NmtCommand::ResetNode | NmtCommand::ResetCommunication => NmtState::Initializing,
+    }
+  }
+}
+
+// Also used as the boot-up message when the state is `Initializing`.
+pub fn heartbeat(node_id: u8, state: NmtState) -> Result<Frame> {
+  Frame::new(cob_id(HEARTBEAT, node_id), &[state as u8])
+}
@@ -0,0 +1,210 @@
+// Object dictionary for the CiA 402 drive. Values are stored in their native
+// types and transferred as little-endian raw values of the object's size.
+
+use super::cia402::OperationMode;
+
+pub const DEVICE_TYPE: u16 = 0x1000;
+pub const ERROR_REGISTER: u16 = 0x1001;
+pub const PRODUCER_HEARTBEAT_TIME: u16 = 0x1017;
+pub const IDENTITY: u16 = 0x1018;
+pub const TPDO1_PARAMETER: u16 = 0x1800;
+pub const TPDO2_PARAMETER: u16 = 0x1801;
+pub const TPDO3_PARAMETER: u16 = 0x1802;
+pub const ERROR_CODE: u16 = 0x603F;
+pub const CONTROLWORD: u16 = 0x6040;
+pub const STATUSWORD: u16 = 0x6041;
+pub const MODES_OF_OPERATION: u16 = 0x6060;
+pub const MODES_OF_OPERATION_DISPLAY: u16 = 0x6061;
+pub const POSITION_ACTUAL_VALUE: u16 = 0x6064;
+pub const VELOCITY_ACTUAL_VALUE: u16 = 0x606C;
+pub const TARGET_POSITION: u16 = 0x607A;
+pub const PROFILE_VELOCITY: u16 = 0x6081;
+pub const PROFILE_ACCELERATION: u16 = 0x6083;
+pub const PROFILE_DECELERATION: u16 = 0x6084;
+pub const QUICK_STOP_DECELERATION: u16 = 0x6085;
+pub const TARGET_VELOCITY: u16 = 0x60FF;
+pub const SUPPORTED_DRIVE_MODES: u16 = 0x6502;
+
+// CiA 402 profile, servo drive.
+const DEVICE_TYPE_VALUE: u32 = 0x0002_0192;
+const SUPPORTED_DRIVE_MODES_VALUE: u32 = 1 << 0 | 1 << 2;
+const TPDO_EVENT_TIMER_SUBINDEX: u8 = 5;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u32)]
+pub enum SdoAbort {
+  UnknownCommand = 0x0504_0001,
+  WriteOnly = 0x0601_0001,
+  ReadOnly = 0x0601_0002,
+  ObjectDoesNotExist = 0x0602_0000,
+  LengthMismatch = 0x0607_0010,
+  SubindexDoesNotExist = 0x0609_0011,
+  ValueOutOfRange = 0x0609_0030,
+  DeviceState = 0x0800_0022,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Value {
+  pub raw: u32,
+  pub size: u8,
+}
+impl Value {
+  fn u8(v: u8) -> Self {
+    Self {
+      raw: v as u32,
+      size: 1,
+    }
+  }
+
+  fn i8(v: i8) -> Self {
+    Self {
+      raw: v as u8 as u32,
+      size: 1,
+    }
+  }
+
+  fn u16(v: u16) -> Self {
+    Self {
+      raw: v as u32,
+      size: 2,
+    }
+  }
+
+  fn u32(v: u32) -> Self {
+    Self { raw: v, size: 4 }
+  }
+
+  fn i32(v: i32) -> Self {
+    Self {
+      raw: v as u32,
+      size: 4,
+    }
+  }
+}
+
+pub struct Objects {
+  pub error_register: u8,
+  pub heartbeat_time_ms: u16,
+  pub tpdo_event_time_ms: [u16; 3],
+  pub error_code: u16,
+  pub controlword: u16,
+  pub statusword: u16,
+  pub modes_of_operation: OperationMode,
+  pub modes_of_operation_display: OperationMode,
+  pub position_actual: i32,
+  pub velocity_actual: i32,
+  pub target_position: i32,
+  pub profile_velocity: u32,
+  pub profile_acceleration: u32,
+  pub profile_deceleration: u32,
+  pub quick_stop_deceleration: u32,
+  pub target_velocity: i32,
+}
+impl Objects {
+  pub fn new(
+    profile_velocity: u32,
+    profile_acceleration: u32,
+    quick_stop_deceleration: u32,
+  ) -> Self {
+    Self {
+      error_register: 0,
+      heartbeat_time_ms: 100,
+      tpdo_event_time_ms: [10, 10, 10],
+      error_code: 0,
+      controlword: 0,
+      statusword: 0,
+      modes_of_operation: OperationMode::NoMode,
+      modes_of_operation_display: OperationMode::NoMode,
+      position_actual: 0,
+      velocity_actual: 0,
+      target_position: 0,
+      profile_velocity,
+      profile_acceleration,
+      profile_deceleration: profile_acceleration,
+      quick_stop_deceleration,
+      target_velocity: 0,
+    }
+  }
+
+  pub fn read(&self, index: u16, subindex: u8) -> Result<Value, SdoAbort> {
+    let value = match index {
+      DEVICE_TYPE => Value::u32(DEVICE_TYPE_VALUE),
+      ERROR_REGISTER => Value::u8(self.error_register),
+      PRODUCER_HEARTBEAT_TIME => Value::u16(self.heartbeat_time_ms),
+      IDENTITY => {
+        return match subindex {
+          0 => Ok(Value::u8(1)),
+          // Vendor ID; none assigned.
+          1 => Ok(Value::u32(0)),
+          _ => Err(SdoAbort::SubindexDoesNotExist),
+        };
+      }
+      TPDO1_PARAMETER | TPDO2_PARAMETER | TPDO3_PARAMETER => {
+        return match subindex {
+          0 => Ok(Value::u8(TPDO_EVENT_TIMER_SUBINDEX)),
+          TPDO_EVENT_TIMER_SUBINDEX => Ok(Value::u16(
+            self.tpdo_event_time_ms[(index - TPDO1_PARAMETER) as usize],
+          )),
+          _ => Err(SdoAbort::SubindexDoesNotExist),
+        }
+      }
+      ERROR_CODE => Value::u16(self.error_code),
+      CONTROLWORD => Value::u16(self.controlword),
+      STATUSWORD => Value::u16(self.statusword),
+      MODES_OF_OPERATION => Value::i8(self.modes_of_operation as i8),
+      MODES_OF_OPERATION_DISPLAY => Value::i8(self.modes_of_operation_display as i8),
+      POSITION_ACTUAL_VALUE => Value::i32(self.position_actual),
+      VELOCITY_ACTUAL_VALUE => Value::i32(self.velocity_actual),
+      TARGET_POSITION => Value::i32(self.target_position),
+      PROFILE_VELOCITY => Value::u32(self.profile_velocity),
+      PROFILE_ACCELERATION => Value::u32(self.profile_acceleration),
+      PROFILE_DECELERATION => Value::u32(self.profile_deceleration),
+      QUICK_STOP_DECELERATION => Value::u32(self.quick_stop_deceleration),
+      TARGET_VELOCITY => Value::i32(self.target_velocity),
+      SUPPORTED_DRIVE_MODES => Value::u32(SUPPORTED_DRIVE_MODES_VALUE),
+      _ => return Err(SdoAbort::ObjectDoesNotExist),
+    };
+
+    match subindex {
+      0 => Ok(value),
+      _ => Err(SdoAbort::SubindexDoesNotExist),
+    }
+  }
+
+  pub fn write(&mut self, index: u16, subindex: u8, value: Value) -> Result<(), SdoAbort> {
+    // Reading first checks that the object exists and gives its size.
+    let current = self.read(index, subindex)?;
+    if value.size != current.size {
+      return Err(SdoAbort::LengthMismatch);
+    }
+
+    let raw = value.raw;
+    match (index, subindex) {
+      (PRODUCER_HEARTBEAT_TIME, 0) => self.heartbeat_time_ms = raw as u16,
+      (TPDO1_PARAMETER, TPDO_EVENT_TIMER_SUBINDEX)
+      | (TPDO2_PARAMETER, TPDO_EVENT_TIMER_SUBINDEX)
+      | (TPDO3_PARAMETER, TPDO_EVENT_TIMER_SUBINDEX) => {
+        self.tpdo_event_time_ms[(index - TPDO1_PARAMETER) as usize] = raw as u16
+      }
+      (CONTROLWORD, 0) => self.controlword = raw as u16,
+      (MODES_OF_OPERATION, 0) => {
+        self.modes_of_operation =
+          OperationMode::from_i8(raw as u8 as i8).ok_or(SdoAbort::ValueOutOfRange)?
+      }
+      (TARGET_POSITION, 0) => self.target_position = raw as i32,
+      (PROFILE_VELOCITY, 0) => self.profile_velocity = raw,
+      (PROFILE_ACCELERATION, 0) | (PROFILE_DECELERATION, 0) | (QUICK_STOP_DECELERATION, 0)
+        if raw == 0 =>
+      {
+        return Err(SdoAbort::ValueOutOfRange)
+      }
+      (PROFILE_ACCELERATION, 0) => self.profile_acceleration = raw,
+      (PROFILE_DECELERATION, 0) => self.profile_deceleration = raw,
+      (QUICK_STOP_DECELERATION, 0) => self.quick_stop_deceleration = raw,
+      (TARGET_VELOCITY, 0) => self.target_velocity = raw as i32,
+      _ => return Err(SdoAbort::ReadOnly),
+    };
+
+    Ok(())
+  }
+}
@@ -0,0 +1,239 @@
+// Expedited SDO server. Segmented and block transfers are not supported since
+// every object in the dictionary fits in four bytes.
+
+use super::od::{Objects, SdoAbort, Value};
+
+const CCS_DOWNLOAD: u8 = 1;
+const CCS_UPLOAD: u8 = 2;
+const CCS_ABORT: u8 = 4;
+
+const EXPEDITED: u8 = 1 << 1;
+const SIZE_INDICATED: u8 = 1 << 0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Written {
+  pub index: u16,
+  pub subindex: u8,
+}
+
+fn abort(index: u16, subindex: u8, code: SdoAbort) -> [u8; 8] {
+  let mut response = [0x80, index as u8, (index >> 8) as u8, subindex, 0, 0, 0, 0];
+  response[4..].copy_from_slice(&(code as u32).to_le_bytes());
+  response
+}
+
+// Handles one request frame from the client and returns the response frame,
+// along with the object that was written, if any. Returns `None` for client
+// aborts, which need no response.
+pub fn handle(objects: &mut Objects, request: &[u8]) -> Option<([u8; 8], Option<Written>)> {
+  if request.len() != 8 {
+    return Some((abort(0, 0, SdoAbort::UnknownCommand), None));
+  }
+
+  let command = request[0];
+  let index = u16::from_le_bytes([request[1], request[2]]);
+  let subindex = request[3];
+  let header = [index as u8, (index >> 8) as u8, subindex];
+
+  match command >> 5 {
+    CCS_UPLOAD => match objects.read(index, subindex) {
+      Ok(value) => {
+        let unused = 4 - value.size;
+        let mut response = [0u8; 8];
+        response[0] = 0x40 | unused << 2 | EXPEDITED | SIZE_INDICATED;
+        response[1..4].copy_from_slice(&header);
+        response[4..].copy_from_slice(&value.raw.to_le_bytes());
+        Some((response, None))
+      }
+      Err(code) => Some((abort(index, subindex, code), None)),
+    },
+    CCS_DOWNLOAD => {
+      if command & EXPEDITED == 0 {
+        return Some((abort(index, subindex, SdoAbort::UnknownCommand), None));
+      }
+
+      let size = match command & SIZE_INDICATED {
+        0 => match objects.read(index, subindex) {
+          Ok(value) => value.size,
+          Err(code) => return Some((abort(index, subindex, code), None)),
+        },
+        _ => 4 - ((command >> 2) & 0x03),
+      };
+
+      let mut raw = [0u8; 4];
+      raw[..size as usize].copy_from_slice(&request[4..4 + size as usize]);
+      let value = Value {
+        raw: u32::from_le_bytes(raw),
+        size,
+      };
+
+      match objects.write(index, subindex, value) {
+        Ok(()) => {
+          let mut response = [0u8; 8];
+          response[0] = 0x60;
+          response[1..4].copy_from_slice(&header);
+          Some((response, Some(Written { index, subindex })))
+        }
+        Err(code) => Some((abort(index, subindex, code), None)),
+      }
+    }
+    CCS_ABORT => None,
+    _ => Some((abort(index, subindex, SdoAbort::UnknownCommand), None)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::canopen::cia402::OperationMode;
+  use crate::canopen::od::{
+    CONTROLWORD, DEVICE_TYPE, MODES_OF_OPERATION, PROFILE_ACCELERATION, STATUSWORD, TARGET_POSITION,
+  };
+
+  fn objects() -> Objects {
+    Objects::new(1000, 2000, 4000)
+  }
+
+  fn request(command: u8, index: u16, subindex: u8, data: [u8; 4]) -> [u8; 8] {
+    [
+      command,
+      index as u8,
+      (index >> 8) as u8,
+      subindex,
+      data[0],
+      data[1],
+      data[2],
+      data[3],
+    ]
+  }
+
+  fn abort_code(response: &[u8; 8]) -> u32 {
+    assert_eq!(response[0], 0x80);
+    u32::from_le_bytes([response[4], response[5], response[6], response[7]])
+  }
+
+  #[test]
+  fn expedited_upload_reports_size() {
+    let mut objects = objects();
+    objects.statusword = 0x1237;
+
+    let (response, written) = handle(&mut objects, &request(0x40, STATUSWORD, 0, [0; 4])).unwrap();
+    // Two bytes unused, expedited, size indicated.
+    assert_eq!(response, [0x4B, 0x41, 0x60, 0, 0x37, 0x12, 0, 0]);
+    assert_eq!(written, None);
+
+    let (response, _) = handle(&mut objects, &request(0x40, DEVICE_TYPE, 0, [0; 4])).unwrap();
+    assert_eq!(response, [0x43, 0x00, 0x10, 0, 0x92, 0x01, 0x02, 0x00]);
+  }
+
+  #[test]
+  fn expedited_download_writes_object() {
+    let mut objects = objects();
+
+    let (response, written) = handle(
+      &mut objects,
+      &request(0x23, TARGET_POSITION, 0, (-5000i32).to_le_bytes()),
+    )
+    .unwrap();
+    assert_eq!(response, [0x60, 0x7A, 0x60, 0, 0, 0, 0, 0]);
+    assert_eq!(
+      written,
+      Some(Written {
+        index: TARGET_POSITION,
+        subindex: 0,
+      })
+    );
+    assert_eq!(objects.target_position, -5000);
+
+    handle(
+      &mut objects,
+      &request(0x2B, CONTROLWORD, 0, [0x0F, 0, 0, 0]),
+    )
+    .unwrap();
+    assert_eq!(objects.controlword, 0x000F);
+  }
+
+  #[test]
+  fn download_without_size_uses_object_size() {
+    let mut objects = objects();
+    handle(
+      &mut objects,
+      &request(0x22, MODES_OF_OPERATION, 0, [3, 0xAA, 0xAA, 0xAA]),
+    )
+    .unwrap();
+    assert_eq!(objects.modes_of_operation, OperationMode::ProfileVelocity);
+  }
+
+  #[test]
+  fn unknown_objects_abort() {
+    let mut objects = objects();
+
+    let (response, written) = handle(&mut objects, &request(0x40, 0x2000, 0, [0; 4])).unwrap();
+    assert_eq!(&response[1..4], &[0x00, 0x20, 0]);
+    assert_eq!(abort_code(&response), SdoAbort::ObjectDoesNotExist as u32);
+    assert_eq!(written, None);
+
+    let (response, _) = handle(&mut objects, &request(0x40, STATUSWORD, 1, [0; 4])).unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::SubindexDoesNotExist as u32);
+
+    let (response, _) = handle(&mut objects, &request(0x23, 0x2000, 0, [0; 4])).unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::ObjectDoesNotExist as u32);
+  }
+
+  #[test]
+  fn read_only_objects_abort() {
+    let mut objects = objects();
+    let (response, written) = handle(
+      &mut objects,
+      &request(0x2B, STATUSWORD, 0, [0xFF, 0xFF, 0, 0]),
+    )
+    .unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::ReadOnly as u32);
+    assert_eq!(written, None);
+    assert_eq!(objects.statusword, 0);
+  }
+
+  #[test]
+  fn bad_values_abort() {
+    let mut objects = objects();
+
+    let (response, _) = handle(&mut objects, &request(0x2B, TARGET_POSITION, 0, [0; 4])).unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::LengthMismatch as u32);
+
+    let (response, _) = handle(
+      &mut objects,
+      &request(0x23, PROFILE_ACCELERATION, 0, [0; 4]),
+    )
+    .unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::ValueOutOfRange as u32);
+    assert_eq!(objects.profile_acceleration, 2000);
+
+    let (response, _) = handle(
+      &mut objects,
+      &request(0x2F, MODES_OF_OPERATION, 0, [7, 0, 0, 0]),
+    )
+    .unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::ValueOutOfRange as u32);
+  }
+
+  #[test]
+  fn unsupported_requests_abort() {
+    let mut objects = objects();
+
+    // Segmented download.
+    let (response, _) = handle(&mut objects, &request(0x21, CONTROLWORD, 0, [0; 4])).unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::UnknownCommand as u32);
+
+    let (response, _) = handle(&mut objects, &[0x40, 0x41, 0x60]).unwrap();
+    assert_eq!(abort_code(&response), SdoAbort::UnknownCommand as u32);
+  }
+
+  #[test]
+  fn client_aborts_get_no_response() {
+    let mut objects = objects();
+    assert_eq!(
+      handle(&mut objects, &request(0x80, CONTROLWORD, 0, [0; 4])),
+      None
+    );
+  }
+}
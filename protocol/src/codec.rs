@@ -0,0 +1,98 @@
+use crate::{Error, Result};
+
+pub struct Writer<'a> {
+  buf: &'a mut [u8],
+  pos: usize,
+}
+impl<'a> Writer<'a> {
+  pub fn new(buf: &'a mut [u8]) -> Self {
+    Self { buf, pos: 0 }
+  }
+
+  pub fn len(&self) -> usize {
+    self.pos
+  }
+
+  pub fn put_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+    let end = self.pos + bytes.len();
+    if end > self.buf.len() {
+      return Err(Error::BufferTooSmall);
+    }
+    self.buf[self.pos..end].copy_from_slice(bytes);
+    self.pos = end;
+    Ok(())
+  }
+
+  pub fn put_u8(&mut self, value: u8) -> Result<()> {
+    self.put_bytes(&[value])
+  }
+
+  pub fn put_bool(&mut self, value: bool) -> Result<()> {
+    self.put_u8(value as u8)
+  }
+
+  pub fn put_u16(&mut self, value: u16) -> Result<()> {
+    self.put_bytes(&value.to_le_bytes())
+  }
+
+  pub fn put_u32(&mut self, value: u32) -> Result<()> {
+    self.put_bytes(&value.to_le_bytes())
+  }
+
+  pub fn put_f32(&mut self, value: f32) -> Result<()> {
+    self.put_bytes(&value.to_le_bytes())
+  }
+}
+
+pub struct Reader<'a> {
+  buf: &'a [u8],
+  pos: usize,
+}
+impl<'a> Reader<'a> {
+  pub fn new(buf: &'a [u8]) -> Self {
+    Self { buf, pos: 0 }
+  }
+
+  pub fn finish(&self) -> Result<()> {
+    match self.pos == self.buf.len() {
+      true => Ok(()),
+      false => Err(Error::TrailingBytes),
+    }
+  }
+
+  pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+    let end = self.pos + len;
+    if end > self.buf.len() {
+      return Err(Error::Truncated);
+    }
+    let bytes = &self.buf[self.pos..end];
+    self.pos = end;
+    Ok(bytes)
+  }
+
+  pub fn u8(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub fn bool(&mut self) -> Result<bool> {
+    match self.u8()? {
+      0 => Ok(false),
+      1 => Ok(true),
+      _ => Err(Error::InvalidValue),
+    }
+  }
+
+  pub fn u16(&mut self) -> Result<u16> {
+    let b = self.take(2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+  }
+
+  pub fn u32(&mut self) -> Result<u32> {
+    let b = self.take(4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+  }
+
+  pub fn f32(&mut self) -> Result<f32> {
+    Ok(f32::from_bits(self.u32()?))
+  }
+}
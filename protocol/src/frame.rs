@@ -0,0 +1,167 @@
+// Frames are COBS encoded so that 0x00 never appears inside a frame and can be
+// used as the delimiter. Each frame carries the message payload followed by a
+// little-endian CRC-16/CCITT of the payload.
+
+use crate::{Error, Message, Result, MAX_MESSAGE_LEN};
+
+pub const DELIMITER: u8 = 0;
+pub const MAX_FRAME_LEN: usize = MAX_MESSAGE_LEN + 2 + MAX_MESSAGE_LEN / 254 + 2 + 1;
+
+pub fn crc16(data: &[u8]) -> u16 {
+  let mut crc = 0xFFFFu16;
+  for byte in data {
+    crc ^= (*byte as u16) << 8;
+    for _ in 0..8 {
+      crc = match crc & 0x8000 {
+        0 => crc << 1,
+        _ => (crc << 1) ^ 0x1021,
+      };
+    }
+  }
+  crc
+}
+
+pub fn encode(payload: &[u8], out: &mut [u8]) -> Result<usize> {
+  if payload.len() > MAX_MESSAGE_LEN {
+    return Err(Error::FrameTooLong);
+  }
+
+  let mut raw = [0u8; MAX_MESSAGE_LEN + 2];
+  raw[..payload.len()].copy_from_slice(payload);
+  raw[payload.len()..payload.len() + 2].copy_from_slice(&crc16(payload).to_le_bytes());
+  let raw = &raw[..payload.len() + 2];
+
+  let mut code_pos = 0;
+  let mut pos = 1;
+  let mut code = 1u8;
+
+  for byte in raw {
+    if pos >= out.len() {
+      return Err(Error::BufferTooSmall);
+    }
+    if *byte == 0 {
+      out[code_pos] = code;
+      code_pos = pos;
+      pos += 1;
+      code = 1;
+    } else {
+      out[pos] = *byte;
+      pos += 1;
+      code += 1;
+      if code == 0xFF {
+        out[code_pos] = code;
+        code_pos = pos;
+        pos += 1;
+        code = 1;
+      }
+    }
+  }
+
+  if pos >= out.len() {
+    return Err(Error::BufferTooSmall);
+  }
+  out[code_pos] = code;
+  out[pos] = DELIMITER;
+
+  Ok(pos + 1)
+}
+
+pub fn encode_message<M: Message>(message: &M, out: &mut [u8]) -> Result<usize> {
+  let mut payload = [0u8; MAX_MESSAGE_LEN];
+  let len = message.encode(&mut payload)?;
+  encode(&payload[..len], out)
+}
+
+fn decode_in_place(buf: &mut [u8]) -> Result<usize> {
+  let mut read = 0;
+  let mut write = 0;
+
+  while read < buf.len() {
+    let code = buf[read];
+    if code == 0 {
+      return Err(Error::BadEncoding);
+    }
+    read += 1;
+
+    for _ in 1..code {
+      if read >= buf.len() {
+        return Err(Error::BadEncoding);
+      }
+      buf[write] = buf[read];
+      write += 1;
+      read += 1;
+    }
+
+    if code != 0xFF && read < buf.len() {
+      buf[write] = 0;
+      write += 1;
+    }
+  }
+
+  Ok(write)
+}
+
+// Accumulates bytes from a stream and yields complete, checksum-verified
+// payloads as their delimiters arrive.
+pub struct Decoder {
+  buf: [u8; MAX_FRAME_LEN],
+  len: usize,
+  overflowed: bool,
+}
+impl Default for Decoder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+impl Decoder {
+  pub fn new() -> Self {
+    Self {
+      buf: [0u8; MAX_FRAME_LEN],
+      len: 0,
+      overflowed: false,
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.len = 0;
+    self.overflowed = false;
+  }
+
+  pub fn push(&mut self, byte: u8) -> Option<Result<&[u8]>> {
+    if byte != DELIMITER {
+      if self.len < self.buf.len() {
+        self.buf[self.len] = byte;
+        self.len += 1;
+      } else {
+        self.overflowed = true;
+      }
+      return None;
+    }
+
+    let len = self.len;
+    let overflowed = self.overflowed;
+    self.reset();
+
+    if len == 0 {
+      return None;
+    }
+    if overflowed {
+      return Some(Err(Error::FrameTooLong));
+    }
+
+    Some(Self::verify(&mut self.buf[..len]))
+  }
+
+  fn verify(buf: &mut [u8]) -> Result<&[u8]> {
+    let len = decode_in_place(buf)?;
+    if len < 2 {
+      return Err(Error::Truncated);
+    }
+
+    let (payload, crc) = buf[..len].split_at(len - 2);
+    match crc16(payload) == u16::from_le_bytes([crc[0], crc[1]]) {
+      true => Ok(payload),
+      false => Err(Error::BadChecksum),
+    }
+  }
+}
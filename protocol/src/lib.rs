@@ -0,0 +1,569 @@
+#![no_std]
+
+pub mod can;
+pub mod canopen;
+mod codec;
+pub mod frame;
+
+use codec::{Reader, Writer};
+
+pub const PROTOCOL_VERSION: u8 = 3;
+pub const MAX_MESSAGE_LEN: usize = 80;
+pub const MAX_NAME_LEN: usize = 24;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+  BufferTooSmall,
+  Truncated,
+  TrailingBytes,
+  UnknownTag(u8),
+  InvalidValue,
+  BadEncoding,
+  BadChecksum,
+  FrameTooLong,
+}
+impl Error {
+  pub fn message(&self) -> &'static str {
+    match self {
+      Error::BufferTooSmall => "Buffer too small for message",
+      Error::Truncated => "Message truncated",
+      Error::TrailingBytes => "Unexpected bytes after message",
+      Error::UnknownTag(_) => "Unknown message tag",
+      Error::InvalidValue => "Invalid value in message",
+      Error::BadEncoding => "Bad frame encoding",
+      Error::BadChecksum => "Bad frame checksum",
+      Error::FrameTooLong => "Frame too long",
+    }
+  }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub trait Message: Sized {
+  fn encode(&self, buf: &mut [u8]) -> Result<usize>;
+  fn decode(buf: &[u8]) -> Result<Self>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ModeId {
+  Idle = 0,
+  Calibrate = 1,
+  Demo = 2,
+  Position = 3,
+  Velocity = 4,
+  Recovery = 5,
+  Torque = 6,
+  Sensorless = 7,
+  Homing = 8,
+  Impedance = 9,
+  Haptic = 10,
+  OpenLoop = 11,
+  StepDir = 12,
+}
+impl ModeId {
+  pub fn from_u8(value: u8) -> Result<Self> {
+    match value {
+      0 => Ok(ModeId::Idle),
+      1 => Ok(ModeId::Calibrate),
+      2 => Ok(ModeId::Demo),
+      3 => Ok(ModeId::Position),
+      4 => Ok(ModeId::Velocity),
+      5 => Ok(ModeId::Recovery),
+      6 => Ok(ModeId::Torque),
+      7 => Ok(ModeId::Sensorless),
+      8 => Ok(ModeId::Homing),
+      9 => Ok(ModeId::Impedance),
+      10 => Ok(ModeId::Haptic),
+      11 => Ok(ModeId::OpenLoop),
+      12 => Ok(ModeId::StepDir),
+      _ => Err(Error::InvalidValue),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      ModeId::Idle => "idle",
+      ModeId::Calibrate => "calibrate",
+      ModeId::Demo => "demo",
+      ModeId::Position => "position",
+      ModeId::Velocity => "velocity",
+      ModeId::Recovery => "recovery",
+      ModeId::Torque => "torque",
+      ModeId::Sensorless => "sensorless",
+      ModeId::Homing => "homing",
+      ModeId::Impedance => "impedance",
+      ModeId::Haptic => "haptic",
+      ModeId::OpenLoop => "open_loop",
+      ModeId::StepDir => "step_dir",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    (0..=u8::MAX)
+      .filter_map(|v| Self::from_u8(v).ok())
+      .find(|m| m.name() == name)
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ErrorCode {
+  Malformed = 1,
+  UnknownParameter = 2,
+  InvalidValue = 3,
+  NotAllowed = 4,
+  Failed = 5,
+}
+impl ErrorCode {
+  pub fn from_u8(value: u8) -> Result<Self> {
+    match value {
+      1 => Ok(ErrorCode::Malformed),
+      2 => Ok(ErrorCode::UnknownParameter),
+      3 => Ok(ErrorCode::InvalidValue),
+      4 => Ok(ErrorCode::NotAllowed),
+      5 => Ok(ErrorCode::Failed),
+      _ => Err(Error::InvalidValue),
+    }
+  }
+}
+
+// Bits of `Status::faults`, for conditions the controller detects itself.
+// How each is acted on is set by the controller's fault policy.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u16)]
+pub enum Fault {
+  // Angle sensor readings are being rejected and bridged with an estimate.
+  AngleSensor = 1 << 0,
+  // The angle sensor has failed; the motor runs on the estimate or stops.
+  AngleSensorFailed = 1 << 1,
+  // The bus voltage is above or below its limit.
+  Overvoltage = 1 << 2,
+  Undervoltage = 1 << 3,
+  // The allowed power is being reduced as the motor or power stage heats.
+  ThermalDerating = 1 << 4,
+  // The motor or power stage reached its trip temperature.
+  Overtemperature = 1 << 5,
+  // The DRV8305 reports a fault, or a temperature warning below shutdown.
+  Driver = 1 << 6,
+  DriverTemperature = 1 << 7,
+  Overspeed = 1 << 8,
+  // A control loop step took longer than allowed.
+  LoopOverrun = 1 << 9,
+  // A latched fault holds the motor stopped until idle is requested with its
+  // condition gone.
+  Latched = 1 << 10,
+}
+impl Fault {
+  pub const ALL: [Fault; 11] = [
+    Fault::AngleSensor,
+    Fault::AngleSensorFailed,
+    Fault::Overvoltage,
+    Fault::Undervoltage,
+    Fault::ThermalDerating,
+    Fault::Overtemperature,
+    Fault::Driver,
+    Fault::DriverTemperature,
+    Fault::Overspeed,
+    Fault::LoopOverrun,
+    Fault::Latched,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Fault::AngleSensor => "angle_sensor",
+      Fault::AngleSensorFailed => "angle_sensor_failed",
+      Fault::Overvoltage => "overvoltage",
+      Fault::Undervoltage => "undervoltage",
+      Fault::ThermalDerating => "thermal_derating",
+      Fault::Overtemperature => "overtemperature",
+      Fault::Driver => "driver",
+      Fault::DriverTemperature => "driver_temperature",
+      Fault::Overspeed => "overspeed",
+      Fault::LoopOverrun => "loop_overrun",
+      Fault::Latched => "latched",
+    }
+  }
+
+  pub fn is_set(&self, faults: u16) -> bool {
+    faults & *self as u16 != 0
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParamValue {
+  F32(f32),
+  U32(u32),
+  Bool(bool),
+}
+impl ParamValue {
+  fn write(&self, w: &mut Writer) -> Result<()> {
+    match self {
+      ParamValue::F32(v) => {
+        w.put_u8(0)?;
+        w.put_f32(*v)
+      }
+      ParamValue::U32(v) => {
+        w.put_u8(1)?;
+        w.put_u32(*v)
+      }
+      ParamValue::Bool(v) => {
+        w.put_u8(2)?;
+        w.put_bool(*v)
+      }
+    }
+  }
+
+  fn read(r: &mut Reader) -> Result<Self> {
+    match r.u8()? {
+      0 => Ok(ParamValue::F32(r.f32()?)),
+      1 => Ok(ParamValue::U32(r.u32()?)),
+      2 => Ok(ParamValue::Bool(r.bool()?)),
+      t => Err(Error::UnknownTag(t)),
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Name {
+  len: u8,
+  bytes: [u8; MAX_NAME_LEN],
+}
+impl Name {
+  pub fn new(name: &str) -> Self {
+    let mut len = name.len().min(MAX_NAME_LEN);
+    while !name.is_char_boundary(len) {
+      len -= 1;
+    }
+
+    let mut bytes = [0u8; MAX_NAME_LEN];
+    bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+    Self {
+      len: len as u8,
+      bytes,
+    }
+  }
+
+  pub fn as_str(&self) -> &str {
+    core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or("")
+  }
+
+  fn write(&self, w: &mut Writer) -> Result<()> {
+    w.put_u8(self.len)?;
+    w.put_bytes(&self.bytes[..self.len as usize])
+  }
+
+  fn read(r: &mut Reader) -> Result<Self> {
+    let len = r.u8()? as usize;
+    if len > MAX_NAME_LEN {
+      return Err(Error::InvalidValue);
+    }
+    let name = core::str::from_utf8(r.take(len)?).map_err(|_| Error::InvalidValue)?;
+    Ok(Self::new(name))
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Status {
+  pub timestamp_us: u32,
+  pub mode: ModeId,
+  pub gate_enabled: bool,
+  pub warnings: u16,
+  pub faults: u16,
+  pub position: f32,
+  pub velocity: f32,
+  pub phase_angle: f32,
+  pub power: f32,
+  pub bus_voltage: f32,
+}
+impl Status {
+  fn write(&self, w: &mut Writer) -> Result<()> {
+    w.put_u32(self.timestamp_us)?;
+    w.put_u8(self.mode as u8)?;
+    w.put_bool(self.gate_enabled)?;
+    w.put_u16(self.warnings)?;
+    w.put_u16(self.faults)?;
+    w.put_f32(self.position)?;
+    w.put_f32(self.velocity)?;
+    w.put_f32(self.phase_angle)?;
+    w.put_f32(self.power)?;
+    w.put_f32(self.bus_voltage)
+  }
+
+  fn read(r: &mut Reader) -> Result<Self> {
+    Ok(Self {
+      timestamp_us: r.u32()?,
+      mode: ModeId::from_u8(r.u8()?)?,
+      gate_enabled: r.bool()?,
+      warnings: r.u16()?,
+      faults: r.u16()?,
+      position: r.f32()?,
+      velocity: r.f32()?,
+      phase_angle: r.f32()?,
+      power: r.f32()?,
+      bus_voltage: r.f32()?,
+    })
+  }
+}
+
+// Effort = stiffness * (position - measured position)
+//        + damping * (velocity - measured velocity) + feedforward,
+// with efforts as fractions of full power (-1 to 1).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImpedanceTarget {
+  pub position: f32,
+  pub velocity: f32,
+  pub stiffness: f32,
+  pub damping: f32,
+  pub feedforward: f32,
+}
+impl ImpedanceTarget {
+  fn write(&self, w: &mut Writer) -> Result<()> {
+    w.put_f32(self.position)?;
+    w.put_f32(self.velocity)?;
+    w.put_f32(self.stiffness)?;
+    w.put_f32(self.damping)?;
+    w.put_f32(self.feedforward)
+  }
+
+  fn read(r: &mut Reader) -> Result<Self> {
+    Ok(Self {
+      position: r.f32()?,
+      velocity: r.f32()?,
+      stiffness: r.f32()?,
+      damping: r.f32()?,
+      feedforward: r.f32()?,
+    })
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Request {
+  Ping,
+  GetStatus,
+  SetMode(ModeId),
+  SetPositionTarget(f32),
+  SetVelocityTarget(f32),
+  GetParam(u16),
+  SetParam(u16, ParamValue),
+  // Send a telemetry sample every n control loop steps, or stop streaming if
+  // n is zero.
+  StreamTelemetry(u16),
+  GetParamInfo(u16),
+  SaveParams,
+  GetPwmTiming,
+  SetImpedanceTarget(ImpedanceTarget),
+}
+impl Message for Request {
+  fn encode(&self, buf: &mut [u8]) -> Result<usize> {
+    let mut w = Writer::new(buf);
+    match self {
+      Request::Ping => w.put_u8(0x01)?,
+      Request::GetStatus => w.put_u8(0x02)?,
+      Request::SetMode(mode) => {
+        w.put_u8(0x03)?;
+        w.put_u8(*mode as u8)?;
+      }
+      Request::SetPositionTarget(target) => {
+        w.put_u8(0x04)?;
+        w.put_f32(*target)?;
+      }
+      Request::SetVelocityTarget(target) => {
+        w.put_u8(0x05)?;
+        w.put_f32(*target)?;
+      }
+      Request::GetParam(index) => {
+        w.put_u8(0x06)?;
+        w.put_u16(*index)?;
+      }
+      Request::SetParam(index, value) => {
+        w.put_u8(0x07)?;
+        w.put_u16(*index)?;
+        value.write(&mut w)?;
+      }
+      Request::StreamTelemetry(divider) => {
+        w.put_u8(0x08)?;
+        w.put_u16(*divider)?;
+      }
+      Request::GetParamInfo(index) => {
+        w.put_u8(0x09)?;
+        w.put_u16(*index)?;
+      }
+      Request::SaveParams => w.put_u8(0x0A)?,
+      Request::GetPwmTiming => w.put_u8(0x0B)?,
+      Request::SetImpedanceTarget(target) => {
+        w.put_u8(0x0C)?;
+        target.write(&mut w)?;
+      }
+    };
+    Ok(w.len())
+  }
+
+  fn decode(buf: &[u8]) -> Result<Self> {
+    let mut r = Reader::new(buf);
+    let request = match r.u8()? {
+      0x01 => Request::Ping,
+      0x02 => Request::GetStatus,
+      0x03 => Request::SetMode(ModeId::from_u8(r.u8()?)?),
+      0x04 => Request::SetPositionTarget(r.f32()?),
+      0x05 => Request::SetVelocityTarget(r.f32()?),
+      0x06 => Request::GetParam(r.u16()?),
+      0x07 => Request::SetParam(r.u16()?, ParamValue::read(&mut r)?),
+      0x08 => Request::StreamTelemetry(r.u16()?),
+      0x09 => Request::GetParamInfo(r.u16()?),
+      0x0A => Request::SaveParams,
+      0x0B => Request::GetPwmTiming,
+      0x0C => Request::SetImpedanceTarget(ImpedanceTarget::read(&mut r)?),
+      t => return Err(Error::UnknownTag(t)),
+    };
+    r.finish()?;
+    Ok(request)
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+  // The haptic detent the rotor clicked into, counted from zero.
+  Detent(i32),
+}
+impl Event {
+  fn write(&self, w: &mut Writer) -> Result<()> {
+    match self {
+      Event::Detent(index) => {
+        w.put_u8(0x01)?;
+        w.put_u32(*index as u32)
+      }
+    }
+  }
+
+  fn read(r: &mut Reader) -> Result<Self> {
+    match r.u8()? {
+      0x01 => Ok(Event::Detent(r.u32()? as i32)),
+      t => Err(Error::UnknownTag(t)),
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Response {
+  Pong {
+    version: u8,
+  },
+  Ok,
+  Error(ErrorCode),
+  Status(Status),
+  Param {
+    index: u16,
+    name: Name,
+    value: ParamValue,
+  },
+  // Unsolicited; sent while telemetry streaming is enabled.
+  Telemetry(Status),
+  ParamInfo {
+    index: u16,
+    name: Name,
+    unit: Name,
+    min: ParamValue,
+    max: ParamValue,
+    default: ParamValue,
+  },
+  // Unsolicited; sent as it happens.
+  Event(Event),
+  // The PWM timing actually achieved by the hardware, which may differ from
+  // the configured parameters due to timer resolution.
+  PwmTiming {
+    frequency: f32,
+    deadtime_ns: u32,
+  },
+}
+impl Message for Response {
+  fn encode(&self, buf: &mut [u8]) -> Result<usize> {
+    let mut w = Writer::new(buf);
+    match self {
+      Response::Pong { version } => {
+        w.put_u8(0x81)?;
+        w.put_u8(*version)?;
+      }
+      Response::Ok => w.put_u8(0x82)?,
+      Response::Error(code) => {
+        w.put_u8(0x83)?;
+        w.put_u8(*code as u8)?;
+      }
+      Response::Status(status) => {
+        w.put_u8(0x84)?;
+        status.write(&mut w)?;
+      }
+      Response::Param { index, name, value } => {
+        w.put_u8(0x85)?;
+        w.put_u16(*index)?;
+        name.write(&mut w)?;
+        value.write(&mut w)?;
+      }
+      Response::Telemetry(status) => {
+        w.put_u8(0x86)?;
+        status.write(&mut w)?;
+      }
+      Response::ParamInfo {
+        index,
+        name,
+        unit,
+        min,
+        max,
+        default,
+      } => {
+        w.put_u8(0x87)?;
+        w.put_u16(*index)?;
+        name.write(&mut w)?;
+        unit.write(&mut w)?;
+        min.write(&mut w)?;
+        max.write(&mut w)?;
+        default.write(&mut w)?;
+      }
+      Response::PwmTiming {
+        frequency,
+        deadtime_ns,
+      } => {
+        w.put_u8(0x88)?;
+        w.put_f32(*frequency)?;
+        w.put_u32(*deadtime_ns)?;
+      }
+      Response::Event(event) => {
+        w.put_u8(0x89)?;
+        event.write(&mut w)?;
+      }
+    };
+    Ok(w.len())
+  }
+
+  fn decode(buf: &[u8]) -> Result<Self> {
+    let mut r = Reader::new(buf);
+    let response = match r.u8()? {
+      0x81 => Response::Pong { version: r.u8()? },
+      0x82 => Response::Ok,
+      0x83 => Response::Error(ErrorCode::from_u8(r.u8()?)?),
+      0x84 => Response::Status(Status::read(&mut r)?),
+      0x85 => Response::Param {
+        index: r.u16()?,
+        name: Name::read(&mut r)?,
+        value: ParamValue::read(&mut r)?,
+      },
+      0x86 => Response::Telemetry(Status::read(&mut r)?),
+      0x87 => Response::ParamInfo {
+        index: r.u16()?,
+        name: Name::read(&mut r)?,
+        unit: Name::read(&mut r)?,
+        min: ParamValue::read(&mut r)?,
+        max: ParamValue::read(&mut r)?,
+        default: ParamValue::read(&mut r)?,
+      },
+      0x88 => Response::PwmTiming {
+        frequency: r.f32()?,
+        deadtime_ns: r.u32()?,
+      },
+      0x89 => Response::Event(Event::read(&mut r)?),
+      t => return Err(Error::UnknownTag(t)),
+    };
+    r.finish()?;
+    Ok(response)
+  }
+}
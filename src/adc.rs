@@ -0,0 +1,160 @@
+use core::ptr::{read_volatile, write_volatile};
+use stm32f303_api::{Error, Result};
+
+// ADC1 to ADC4 (RM0316 section 15.6), configured directly as the hardware
+// API has no ADC support.
+const RCC_AHBENR: *mut u32 = 0x4002_1014 as *mut u32;
+const AHBENR_ADC12EN: u32 = 1 << 28;
+const AHBENR_ADC34EN: u32 = 1 << 29;
+
+const ADC12_CCR: *mut u32 = 0x5000_0308 as *mut u32;
+const ADC34_CCR: *mut u32 = 0x5000_0708 as *mut u32;
+// Synchronous clock from HCLK, undivided.
+const CCR_CKMODE_HCLK: u32 = 0b01 << 16;
+const CCR_CKMODE_MASK: u32 = 0b11 << 16;
+
+const ISR: usize = 0x00;
+const CR: usize = 0x08;
+const SMPR1: usize = 0x14;
+const SMPR2: usize = 0x18;
+const JSQR: usize = 0x4C;
+const JDR1: usize = 0x80;
+
+const ISR_ADRDY: u32 = 1 << 0;
+const ISR_JEOS: u32 = 1 << 6;
+
+const CR_ADEN: u32 = 1 << 0;
+const CR_JADSTART: u32 = 1 << 3;
+const CR_ADVREGEN_MASK: u32 = 0b11 << 28;
+const CR_ADVREGEN_ON: u32 = 0b01 << 28;
+const CR_ADCAL: u32 = 1 << 31;
+
+// 19.5 ADC cycles, enough for the sources here without an external buffer.
+const SAMPLE_TIME: u32 = 0b100;
+
+// Cycles to wait for the voltage regulator, at least 10 us at 64 MHz.
+const REGULATOR_STARTUP_CYCLES: u32 = 1000;
+const MAX_WAIT_POLLS: u32 = 100_000;
+
+pub const VREF: f32 = 3.3;
+pub const FULL_SCALE: f32 = 4095f32;
+
+#[derive(Copy, Clone)]
+pub enum AdcUnit {
+  Adc1,
+  Adc2,
+  Adc3,
+  Adc4,
+}
+
+// Injected group trigger sources (JEXTSEL), the same for each ADC.
+#[derive(Copy, Clone)]
+pub enum InjectedTrigger {
+  Tim1Trgo = 0,
+}
+
+pub struct Adc {
+  base: usize,
+}
+impl Adc {
+  // Powers up, calibrates and enables the converter.
+  pub fn new(unit: AdcUnit) -> Result<Self> {
+    let (base, ahbenr_en, common_ccr) = match unit {
+      AdcUnit::Adc1 => (0x5000_0000, AHBENR_ADC12EN, ADC12_CCR),
+      AdcUnit::Adc2 => (0x5000_0100, AHBENR_ADC12EN, ADC12_CCR),
+      AdcUnit::Adc3 => (0x5000_0400, AHBENR_ADC34EN, ADC34_CCR),
+      AdcUnit::Adc4 => (0x5000_0500, AHBENR_ADC34EN, ADC34_CCR),
+    };
+    let adc = Self { base };
+
+    unsafe {
+      let ahbenr = read_volatile(RCC_AHBENR);
+      write_volatile(RCC_AHBENR, ahbenr | ahbenr_en);
+      let ccr = read_volatile(common_ccr);
+      write_volatile(common_ccr, (ccr & !CCR_CKMODE_MASK) | CCR_CKMODE_HCLK);
+    }
+
+    // The regulator has to pass through the intermediate state.
+    adc.modify(CR, CR_ADVREGEN_MASK, 0);
+    adc.modify(CR, CR_ADVREGEN_MASK, CR_ADVREGEN_ON);
+    cortex_m::asm::delay(REGULATOR_STARTUP_CYCLES);
+
+    adc.modify(CR, CR_ADCAL, CR_ADCAL);
+    adc.wait_for(CR, CR_ADCAL, 0)?;
+
+    adc.modify(CR, CR_ADEN, CR_ADEN);
+    adc.wait_for(ISR, ISR_ADRDY, ISR_ADRDY)?;
+
+    Ok(adc)
+  }
+
+  fn read(&self, offset: usize) -> u32 {
+    unsafe { read_volatile((self.base + offset) as *const u32) }
+  }
+
+  fn write(&self, offset: usize, value: u32) {
+    unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+  }
+
+  fn modify(&self, offset: usize, mask: u32, value: u32) {
+    self.write(offset, (self.read(offset) & !mask) | value);
+  }
+
+  fn wait_for(&self, offset: usize, mask: u32, value: u32) -> Result<()> {
+    for _ in 0..MAX_WAIT_POLLS {
+      if self.read(offset) & mask == value {
+        return Ok(());
+      }
+    }
+    Err(Error::new("Timed out waiting for ADC"))
+  }
+
+  fn set_sample_time(&self, channel: u8) {
+    match channel {
+      1..=9 => self.modify(SMPR1, 0b111 << (3 * channel), SAMPLE_TIME << (3 * channel)),
+      _ => self.modify(
+        SMPR2,
+        0b111 << (3 * (channel - 10)),
+        SAMPLE_TIME << (3 * (channel - 10)),
+      ),
+    }
+  }
+
+  // Converts up to four channels, in order, on each rising edge of the
+  // trigger. Results stay in their data registers until the next trigger.
+  pub fn start_injected(&mut self, channels: &[u8], trigger: InjectedTrigger) -> Result<()> {
+    if channels.is_empty() || channels.len() > 4 {
+      return Err(Error::new(
+        "ADC injected sequence must have 1 to 4 channels",
+      ));
+    }
+
+    let mut jsqr = (channels.len() as u32 - 1) | (trigger as u32) << 2 | 0b01 << 6;
+    for (rank, channel) in channels.iter().enumerate() {
+      self.set_sample_time(*channel);
+      jsqr |= (*channel as u32) << (8 + 6 * rank);
+    }
+
+    self.write(JSQR, jsqr);
+    self.modify(CR, CR_JADSTART, CR_JADSTART);
+    Ok(())
+  }
+
+  // Whether a new injected sequence has completed since the last call.
+  pub fn take_injected_complete(&mut self) -> bool {
+    let complete = self.read(ISR) & ISR_JEOS != 0;
+    if complete {
+      // Flags are cleared by writing one.
+      self.write(ISR, ISR_JEOS);
+    }
+    complete
+  }
+
+  pub fn read_injected(&self, rank: usize) -> u16 {
+    self.read(JDR1 + 4 * rank) as u16
+  }
+}
+
+pub fn to_volts(raw: u16) -> f32 {
+  raw as f32 * VREF / FULL_SCALE
+}
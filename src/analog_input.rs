@@ -0,0 +1,39 @@
+use stm32f303_api::{
+  gpio::gpio_c::{GpioC, Pc4Analog},
+  Result,
+};
+
+use crate::adc::{to_volts, Adc, AdcUnit, InjectedTrigger};
+
+// PC4 is ADC2 channel 5.
+const CHANNEL: u8 = 5;
+
+// An analog input such as a potentiometer wiper on PC4, converted by ADC2
+// alongside the current samples on each TIM1 trigger.
+pub struct AnalogInput {
+  adc: Adc,
+  pin: Pc4Analog,
+}
+impl AnalogInput {
+  pub fn new(gpio_c: &mut GpioC) -> Result<Self> {
+    let mut adc = Adc::new(AdcUnit::Adc2)?;
+    adc.start_injected(&[CHANNEL], InjectedTrigger::Tim1Trgo)?;
+
+    Ok(Self {
+      adc,
+      pin: gpio_c.take_pc4()?.as_analog(),
+    })
+  }
+
+  // Voltage from the conversion completed since the last call, if any.
+  pub fn read(&mut self) -> Option<f32> {
+    match self.adc.take_injected_complete() {
+      true => Some(to_volts(self.adc.read_injected(0))),
+      false => None,
+    }
+  }
+
+  pub fn return_hardware(self, gpio_c: &mut GpioC) -> Result<()> {
+    gpio_c.return_pc4(self.pin.teardown())
+  }
+}
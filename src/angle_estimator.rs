@@ -0,0 +1,174 @@
+use core::fmt::Write;
+use stm32f303_api::{Error, Result};
+
+use crate::{
+  math::{norm_rads, wrap_rads},
+  observer::{clarke, FluxObserver, MotorModel},
+};
+
+#[derive(Copy, Clone)]
+pub struct FusionConfig {
+  // Mechanical speeds (rad/s) over which the model's weight rises from zero
+  // to `model_weight`. Below `blend_start` the model is not trusted at all.
+  pub blend_start: f32,
+  pub blend_end: f32,
+  pub model_weight: f32,
+  // Largest disagreement (rad) between sensor and a trusted model before the
+  // reading is rejected.
+  pub max_deviation: f32,
+  // Consecutive rejected readings before the sensor is declared failed.
+  pub max_bad_readings: u32,
+  // Whether to carry on with the model alone once the sensor has failed.
+  pub fallback: bool,
+  // Filtered observer error (rad) below which it counts as locked.
+  pub lock_error: f32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SensorHealth {
+  Ok,
+  // Recent readings were rejected and bridged with the estimate.
+  Degraded,
+  // Latched until the estimator is reset.
+  Failed,
+}
+
+// Mechanical rotor angle from the angle sensor, cross-checked against and
+// blended with a model-based estimate. With current sensing the model is a
+// flux observer; without it, the last velocity is extrapolated, which can
+// only bridge short gaps.
+pub struct AngleEstimator {
+  config: FusionConfig,
+  num_magnet_pairs: f32,
+  observer: Option<FluxObserver>,
+  last_model_angle: Option<f32>,
+  angle: Option<f32>,
+  bad_readings: u32,
+  failed: bool,
+}
+impl AngleEstimator {
+  pub fn new(config: FusionConfig, num_magnet_pairs: u32, observer: Option<FluxObserver>) -> Self {
+    Self {
+      config,
+      num_magnet_pairs: num_magnet_pairs as f32,
+      observer,
+      last_model_angle: None,
+      angle: None,
+      bad_readings: 0,
+      failed: false,
+    }
+  }
+
+  pub fn set_config(&mut self, config: FusionConfig) {
+    self.config = config;
+  }
+
+  pub fn set_model(&mut self, model: MotorModel, gain: f32, pll_bandwidth: f32) {
+    if let Some(observer) = &mut self.observer {
+      observer.set_model(model, gain, pll_bandwidth);
+    }
+  }
+
+  // Clears a latched sensor failure.
+  pub fn reset(&mut self) {
+    self.bad_readings = 0;
+    self.failed = false;
+  }
+
+  pub fn health(&self) -> SensorHealth {
+    if self.failed {
+      SensorHealth::Failed
+    } else if self.bad_readings > 0 {
+      SensorHealth::Degraded
+    } else {
+      SensorHealth::Ok
+    }
+  }
+
+  // Steps the observer with the voltage (V) applied and phase currents (A)
+  // measured over the last `dt` seconds.
+  pub fn update_model(&mut self, voltage: (f32, f32), phase_currents: [f32; 3], dt: f32) {
+    if let Some(observer) = &mut self.observer {
+      observer.update(voltage, clarke(phase_currents), dt);
+    }
+  }
+
+  // The model's weight at this mechanical velocity, or None when it is not
+  // trusted.
+  fn model_weight(&self) -> Option<f32> {
+    let observer = self.observer.as_ref()?;
+    let speed = libm::fabsf(observer.get_velocity()) / self.num_magnet_pairs;
+    if !observer.is_locked(self.config.lock_error) || speed < self.config.blend_start {
+      return None;
+    }
+
+    let span = (self.config.blend_end - self.config.blend_start).max(core::f32::EPSILON);
+    let ramp = ((speed - self.config.blend_start) / span).min(1f32);
+    Some(ramp * self.config.model_weight)
+  }
+
+  // Fuses a sensor reading with the estimate, given the last mechanical
+  // velocity (rad/s) for extrapolation. Fails when there is neither a usable
+  // reading nor a usable estimate.
+  pub fn update(&mut self, reading: Result<f32>, velocity: f32, dt: f32) -> Result<f32> {
+    let weight = self.model_weight();
+    let model_angle = self.observer.as_ref().map(|observer| observer.get_angle());
+    let model_delta = match (weight, model_angle, self.last_model_angle) {
+      (Some(_), Some(angle), Some(last_angle)) => {
+        Some(wrap_rads(angle - last_angle) / self.num_magnet_pairs)
+      }
+      _ => None,
+    };
+    self.last_model_angle = model_angle;
+
+    let predicted = self
+      .angle
+      .map(|angle| norm_rads(angle + model_delta.unwrap_or(velocity * dt)));
+
+    let reading = match (reading, predicted, model_delta) {
+      (Ok(angle), Some(predicted), Some(_))
+        if libm::fabsf(wrap_rads(angle - predicted)) > self.config.max_deviation =>
+      {
+        Err(Error::new("Position sensor disagrees with the model"))
+      }
+      (reading, _, _) => reading,
+    };
+
+    let angle = match (reading, self.failed) {
+      (Ok(angle), false) => {
+        self.bad_readings = 0;
+        match (predicted, weight) {
+          (Some(predicted), Some(weight)) => {
+            norm_rads(angle + weight * wrap_rads(predicted - angle))
+          }
+          _ => angle,
+        }
+      }
+      (reading, _) => {
+        if let Err(e) = reading {
+          self.bad_readings = self.bad_readings.saturating_add(1);
+          if !self.failed && self.bad_readings >= self.config.max_bad_readings {
+            self.failed = true;
+            println!("Angle sensor failed: {}", e.message).ok();
+          }
+        }
+
+        let estimate = match self.failed {
+          true if !self.config.fallback => None,
+          true => predicted.filter(|_| model_delta.is_some()),
+          false => predicted,
+        };
+        match estimate {
+          Some(angle) => angle,
+          None => {
+            self.angle = None;
+            return Err(Error::new("No usable rotor angle"));
+          }
+        }
+      }
+    };
+
+    self.angle = Some(angle);
+    Ok(angle)
+  }
+}
@@ -0,0 +1,134 @@
+use stm32f303_api::{
+  gpio::{gpio_a::GpioA, gpio_c::GpioC, gpio_d::GpioD},
+  Result, System,
+};
+
+use crate::{encoder::QuadratureEncoder, hall_sensor::HallSensor, position_sensor::PositionSensor};
+
+// Rotor angle as used by the control modes, whichever sensor provides it.
+pub trait AngleSensor {
+  // Mechanical angle in [0, PI2) relative to the calibrated zero.
+  fn read_absolute_angle(&mut self) -> Result<f32>;
+
+  fn absolute_to_phase_angle(&self, absolute_angle: f32) -> f32;
+
+  fn read_phase_angle(&mut self) -> Result<f32> {
+    let absolute_angle = self.read_absolute_angle()?;
+    Ok(self.absolute_to_phase_angle(absolute_angle))
+  }
+
+  fn set_offset(&mut self, offset: f32);
+
+  fn get_offset(&self) -> f32;
+
+  // Mechanical velocity (rad/s) if the sensor measures it directly, rather
+  // than it having to be derived from successive angles.
+  fn read_velocity(&self) -> Option<f32> {
+    None
+  }
+
+  // Called by calibration while it turns the field, with the commanded phase
+  // angle, for sensors that learn their alignment from it.
+  fn begin_learning(&mut self) {}
+
+  fn learn(&mut self, _phase_angle: f32) -> Result<()> {
+    Ok(())
+  }
+
+  fn finish_learning(&mut self) -> Result<()> {
+    Ok(())
+  }
+}
+
+// Stands in when no sensor is fitted, for sensorless operation only.
+pub struct NoSensor {}
+impl AngleSensor for NoSensor {
+  fn read_absolute_angle(&mut self) -> Result<f32> {
+    Ok(0f32)
+  }
+
+  fn absolute_to_phase_angle(&self, _absolute_angle: f32) -> f32 {
+    0f32
+  }
+
+  fn set_offset(&mut self, _offset: f32) {}
+
+  fn get_offset(&self) -> f32 {
+    0f32
+  }
+}
+
+// The sensor fitted, as selected by the `sensor.type` parameter.
+pub enum Sensor {
+  Magnetic(PositionSensor),
+  Hall(HallSensor),
+  Encoder(QuadratureEncoder),
+  None(NoSensor),
+}
+impl Sensor {
+  fn inner(&self) -> &dyn AngleSensor {
+    match self {
+      Sensor::Magnetic(sensor) => sensor,
+      Sensor::Hall(sensor) => sensor,
+      Sensor::Encoder(sensor) => sensor,
+      Sensor::None(sensor) => sensor,
+    }
+  }
+
+  fn inner_mut(&mut self) -> &mut dyn AngleSensor {
+    match self {
+      Sensor::Magnetic(sensor) => sensor,
+      Sensor::Hall(sensor) => sensor,
+      Sensor::Encoder(sensor) => sensor,
+      Sensor::None(sensor) => sensor,
+    }
+  }
+
+  pub fn return_hardware(
+    self,
+    system: &mut System,
+    gpio_a: &mut GpioA,
+    gpio_c: &mut GpioC,
+    gpio_d: &mut GpioD,
+  ) -> Result<()> {
+    match self {
+      Sensor::Magnetic(sensor) => sensor.return_hardware(system, gpio_a),
+      Sensor::Hall(sensor) => sensor.return_hardware(gpio_c),
+      Sensor::Encoder(sensor) => sensor.return_hardware(system, gpio_d),
+      Sensor::None(_) => Ok(()),
+    }
+  }
+}
+impl AngleSensor for Sensor {
+  fn read_absolute_angle(&mut self) -> Result<f32> {
+    self.inner_mut().read_absolute_angle()
+  }
+
+  fn absolute_to_phase_angle(&self, absolute_angle: f32) -> f32 {
+    self.inner().absolute_to_phase_angle(absolute_angle)
+  }
+
+  fn set_offset(&mut self, offset: f32) {
+    self.inner_mut().set_offset(offset)
+  }
+
+  fn get_offset(&self) -> f32 {
+    self.inner().get_offset()
+  }
+
+  fn read_velocity(&self) -> Option<f32> {
+    self.inner().read_velocity()
+  }
+
+  fn begin_learning(&mut self) {
+    self.inner_mut().begin_learning()
+  }
+
+  fn learn(&mut self, phase_angle: f32) -> Result<()> {
+    self.inner_mut().learn(phase_angle)
+  }
+
+  fn finish_learning(&mut self) -> Result<()> {
+    self.inner_mut().finish_learning()
+  }
+}
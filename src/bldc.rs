@@ -1,9 +1,54 @@
 use core::time::Duration;
 
-use crate::modes::{calibration::CalibrationMode, demo::DemoMode, recovery::RecoveryMode};
+use crate::modes::{
+  calibration::CalibrationMode,
+  demo::DemoMode,
+  haptic::HapticMode,
+  homing::{HomingMode, HomingState},
+  impedance::ImpedanceMode,
+  open_loop::OpenLoopMode,
+  recovery::RecoveryMode,
+  sensorless::{motor_model, SensorlessMode},
+  servo::{ServoMode, Target},
+  step_dir::StepDirMode,
+};
 use crate::{
-  drv_8305::Drv8305, drv_8305::WarningFlag, magnet_controller::MagnetController,
-  position_sensor::PositionSensor, runner::Program,
+  analog_input::AnalogInput,
+  angle_estimator::{AngleEstimator, FusionConfig, SensorHealth},
+  angle_sensor::{AngleSensor, NoSensor, Sensor},
+  bus_voltage::BusVoltage,
+  can_node::CanNode,
+  canopen_node::{CanOpenNode, DriveOutput},
+  clock::Clock,
+  comms::Comms,
+  current_sense::CurrentSense,
+  drv_8305::Drv8305,
+  drv_8305::{WarningFlag, Warnings},
+  encoder::QuadratureEncoder,
+  hall_sensor::{HallSensor, NUM_HALL_STATES},
+  magnet_controller::{Chopping, MagnetController, Modulation, OutputState, PwmConfig},
+  observer::{clarke, FluxObserver},
+  param_store,
+  params::{
+    ParamError, ParamId, Params, Range, FAULT_ACTION_PARAMS, FAULT_DEBOUNCE_PARAMS,
+    HALL_ANGLE_PARAMS,
+  },
+  position_sensor::{Motion, MotionTracker, PositionSensor},
+  rc_input::RcInput,
+  runner::Program,
+  setpoint::{
+    rc_fraction, setpoint_mode, setpoint_source, setpoint_target, AnalogSetpoint, SetpointSource,
+  },
+  step_dir::StepDirInput,
+  thermal::{NtcConfig, ThermalConfig, ThermalModel},
+  thermistors::Thermistors,
+};
+use bldc_control::fault_policy::{
+  FaultAction, FaultPolicy, FaultRule, FaultSource, NUM_FAULT_SOURCES,
+};
+use bldc_protocol::{
+  can::Command, canopen::cia402::State, ErrorCode, Event, Fault, ImpedanceTarget, ModeId, Name,
+  ParamValue, Request, Response, Status, PROTOCOL_VERSION,
 };
 use core::fmt::Write;
 use stm32f303_api::{
@@ -13,30 +58,195 @@ use stm32f303_api::{
   },
   gpio::gpio_a::GpioA,
   gpio::gpio_b::GpioB,
+  gpio::gpio_c::GpioC,
+  gpio::gpio_d::GpioD,
   gpio::gpio_e::GpioE,
-  Result, System,
+  Error, Result, System,
 };
 
+// HSI / 2 * 16
+const CORE_FREQ: u32 = 64_000_000;
+// Below this speed (rad/s) regenerative braking gives way to a short brake.
+const REGEN_MIN_VELOCITY: f32 = 1f32;
+// Bandwidth (rad/s) of the regenerative braking current loop.
+const REGEN_CURRENT_BANDWIDTH: f32 = 200f32;
+
+fn modulation(params: &Params) -> Modulation {
+  let advance = params.get_f32(ParamId::SixStepAdvance);
+  match params.get_u32(ParamId::Modulation) {
+    1 => Modulation::SixStep {
+      advance,
+      chopping: Chopping::Complementary,
+    },
+    2 => Modulation::SixStep {
+      advance,
+      chopping: Chopping::HighSide,
+    },
+    _ => Modulation::Sinusoidal,
+  }
+}
+
+fn pwm_config(params: &Params) -> PwmConfig {
+  let nanos = |id| Duration::from_nanos(params.get_u32(id) as u64);
+  PwmConfig {
+    frequency: params.get_f32(ParamId::PwmFrequency),
+    deadtime: nanos(ParamId::Deadtime),
+    sample_advance: nanos(ParamId::PwmSampleAdvance),
+    min_low_side: nanos(ParamId::PwmMinLowSide),
+  }
+}
+
+fn voltage_compensation(params: &Params) -> Option<f32> {
+  match params.get_bool(ParamId::BusVoltageCompensation) {
+    true => Some(params.get_f32(ParamId::SupplyVoltage)),
+    false => None,
+  }
+}
+
+fn thermal_config(params: &Params) -> ThermalConfig {
+  ThermalConfig {
+    ambient: params.get_f32(ParamId::ThermalAmbient),
+    winding_resistance: params.get_f32(ParamId::ThermalWindingResistance),
+    winding_time_constant: params.get_f32(ParamId::ThermalWindingTimeConstant),
+    phase_resistance: params.get_f32(ParamId::MotorResistance),
+    motor_derate: params.get_f32(ParamId::ThermalMotorDerateTemp),
+    motor_trip: params.get_f32(ParamId::ThermalMotorTripTemp),
+    fet_derate: params.get_f32(ParamId::ThermalFetDerateTemp),
+    fet_trip: params.get_f32(ParamId::ThermalFetTripTemp),
+  }
+}
+
+fn ntc_config(params: &Params) -> NtcConfig {
+  NtcConfig {
+    nominal: params.get_f32(ParamId::ThermalNtcNominal),
+    beta: params.get_f32(ParamId::ThermalNtcBeta),
+    pullup: params.get_f32(ParamId::ThermalNtcPullup),
+  }
+}
+
+fn fault_rules(params: &Params) -> [FaultRule; NUM_FAULT_SOURCES] {
+  let mut rules = [FaultRule {
+    action: FaultAction::Log,
+    debounce: 1,
+  }; NUM_FAULT_SOURCES];
+  for (index, rule) in rules.iter_mut().enumerate() {
+    rule.action = FaultAction::from_u32(params.get_u32(FAULT_ACTION_PARAMS[index]));
+    rule.debounce = params.get_u32(FAULT_DEBOUNCE_PARAMS[index]);
+  }
+  rules
+}
+
+fn print_drv_8305_warnings(warnings: &Warnings) {
+  if warnings.has(WarningFlag::Overtemp) {
+    println!("Overtemp").ok();
+  }
+  if warnings.has(WarningFlag::ChargePumpUndervolt) {
+    println!("Charge pump undervolt").ok();
+  }
+  if warnings.has(WarningFlag::VdsOvercurrent) {
+    println!("VDS overcurrent").ok();
+  }
+  if warnings.has(WarningFlag::PvddOvervolt) {
+    println!("PVDD overvolt").ok();
+  }
+  if warnings.has(WarningFlag::PvddUndervolt) {
+    println!("PVDD undervolt").ok();
+  }
+  if warnings.has(WarningFlag::TempOver175C) {
+    println!("Temp over 175 C").ok();
+  }
+  if warnings.has(WarningFlag::Fault) {
+    println!("FAULT").ok();
+  }
+}
+
+fn motion_tracking(params: &Params) -> Option<f32> {
+  match params.get_bool(ParamId::MotionTracking) {
+    true => Some(params.get_f32(ParamId::MotionTrackingBandwidth)),
+    false => None,
+  }
+}
+
+fn fusion_config(params: &Params) -> FusionConfig {
+  FusionConfig {
+    blend_start: params.get_f32(ParamId::FusionBlendStart),
+    blend_end: params.get_f32(ParamId::FusionBlendEnd),
+    model_weight: params.get_f32(ParamId::FusionModelWeight),
+    max_deviation: params.get_f32(ParamId::FusionMaxDeviation),
+    max_bad_readings: params.get_u32(ParamId::FusionMaxBadReadings),
+    fallback: params.get_bool(ParamId::FusionFallback),
+    lock_error: params.get_f32(ParamId::SensorlessLockError),
+  }
+}
+
+pub enum CanInterface {
+  Simple(CanNode),
+  CanOpen(CanOpenNode),
+}
+
 pub enum Mode {
   Start,
+  Idle,
   Calibrate(CalibrationMode),
   Demo(DemoMode),
+  Servo(ServoMode),
+  Sensorless(SensorlessMode),
+  Homing(HomingMode),
+  Impedance(ImpedanceMode),
+  Haptic(HapticMode),
+  OpenLoop(OpenLoopMode),
+  StepDir(StepDirMode),
 }
 
 pub struct Bldc {
   recovery_mode: Option<RecoveryMode>,
   num_magnet_pairs: u32,
   mode: Mode,
+  params: Params,
+  clock: Clock,
+  comms: Comms,
+  can: CanInterface,
+  motion_tracker: MotionTracker,
+  motion: Motion,
+  warnings: u16,
+  drv_dead_time: Duration,
   system: System,
   gpio_a: GpioA,
   gpio_b: GpioB,
+  gpio_c: GpioC,
+  gpio_d: GpioD,
   gpio_e: GpioE,
   drv_8305: Drv8305,
   magnet_controller: MagnetController,
-  position_sensor: PositionSensor,
+  position_sensor: Sensor,
+  angle_estimator: AngleEstimator,
+  current_sense: Option<CurrentSense>,
+  phase_currents: [f32; 3],
+  step_dir: Option<StepDirInput>,
+  rc_input: Option<RcInput>,
+  analog_input: Option<AnalogInput>,
+  analog_setpoint: AnalogSetpoint,
+  bus_voltage: Option<BusVoltage>,
+  thermal: ThermalModel,
+  thermistors: Option<Thermistors>,
+  fault_policy: FaultPolicy,
+  // Power applied by regenerative braking to hold its current.
+  brake_power: f32,
+  // Time since the setpoint input last gave a valid reading.
+  setpoint_age: f32,
 }
 impl Bldc {
   pub fn new(num_magnet_pairs: u32) -> Result<Bldc> {
+    let mut core =
+      cortex_m::Peripherals::take().ok_or(Error::new("Core peripherals already taken"))?;
+    let clock = Clock::new(CORE_FREQ, &mut core.DCB, &mut core.DWT);
+
+    let mut params = Params::new();
+    match param_store::load(&mut params) {
+      Ok(count) => println!("Loaded {} parameters", count).ok(),
+      Err(e) => println!("Using default parameters: {}", e.message).ok(),
+    };
+
     let mut clock_cfg = ClockConfig::with_freqs(0, 0);
 
     clock_cfg.set_pll_source_mux_input(PllSourceMuxInput::Hsi);
@@ -50,85 +260,1010 @@ impl Bldc {
     let mut system = System::with_clocks(clock_cfg)?;
     let mut gpio_a = system.activate_gpio_a()?;
     let mut gpio_b = system.activate_gpio_b()?;
+    let mut gpio_c = system.activate_gpio_c()?;
+    let mut gpio_d = system.activate_gpio_d()?;
     let mut gpio_e = system.activate_gpio_e()?;
 
     let mut drv_8305 = Drv8305::new(&mut system, &mut gpio_b)?;
     drv_8305.start();
+    let drv_dead_time = drv_8305.read_dead_time()?;
 
     let mut current_controller = MagnetController::new(
       &mut system,
       &mut gpio_e,
-      20000f32,
-      Duration::from_nanos(500),
+      &pwm_config(&params),
+      drv_dead_time,
+      params.get_f32(ParamId::SupplyVoltage),
     )?;
 
     current_controller.set_phase_angle_and_power(0f32, 0f32)?;
+    current_controller.set_deadtime_compensation(
+      params.get_bool(ParamId::DeadtimeCompensation),
+      params.get_f32(ParamId::DeadtimeCompensationBand),
+    )?;
+    current_controller.set_modulation(modulation(&params))?;
+    current_controller.set_voltage_compensation(voltage_compensation(&params))?;
     current_controller.start();
 
-    let mut position_sensor = PositionSensor::new(num_magnet_pairs, &mut system, &mut gpio_a)?;
-    position_sensor.start();
+    let position_sensor = match params.get_u32(ParamId::SensorType) {
+      1 => {
+        let mut table = [0f32; NUM_HALL_STATES];
+        for (angle, id) in table.iter_mut().zip(HALL_ANGLE_PARAMS.iter()) {
+          *angle = params.get_f32(*id);
+        }
+        Sensor::Hall(HallSensor::new(
+          num_magnet_pairs,
+          table,
+          params.get_f32(ParamId::HallTimeout),
+          CORE_FREQ,
+          &mut gpio_c,
+        )?)
+      }
+      2 => {
+        let mut encoder = QuadratureEncoder::new(
+          num_magnet_pairs,
+          params.get_u32(ParamId::EncoderCountsPerRev),
+          &mut system,
+          &mut gpio_d,
+        )?;
+        encoder.start();
+        Sensor::Encoder(encoder)
+      }
+      3 => Sensor::None(NoSensor {}),
+      _ => {
+        let mut position_sensor = PositionSensor::new(num_magnet_pairs, &mut system, &mut gpio_a)?;
+        position_sensor.start();
+        Sensor::Magnetic(position_sensor)
+      }
+    };
+
+    // Offsets are measured with the PWM running at zero power, so no current
+    // flows.
+    let current_sense = match params.get_bool(ParamId::CurrentSense) {
+      true => {
+        let mut current_sense = CurrentSense::new(
+          params.get_f32(ParamId::CurrentShunt),
+          params.get_f32(ParamId::CurrentAmpGain),
+          &mut gpio_a,
+        )?;
+        current_sense.calibrate_offsets()?;
+        Some(current_sense)
+      }
+      false => None,
+    };
+
+    // The observer needs measured currents; without them the estimator can
+    // only extrapolate over short gaps in the sensor's readings.
+    let observer = current_sense.as_ref().map(|_| {
+      FluxObserver::new(
+        motor_model(&params),
+        params.get_f32(ParamId::ObserverGain),
+        params.get_f32(ParamId::ObserverPllBandwidth),
+      )
+    });
+    let angle_estimator = AngleEstimator::new(fusion_config(&params), num_magnet_pairs, observer);
+
+    let step_dir = match params.get_bool(ParamId::StepDir) {
+      true => {
+        let mut step_dir = StepDirInput::new(&mut system, &mut gpio_d)?;
+        step_dir.start();
+        Some(step_dir)
+      }
+      false => None,
+    };
+
+    let rc_input = match setpoint_source(&params) {
+      SetpointSource::RcPulse => {
+        let mut rc_input = RcInput::new(&mut system, &mut gpio_b)?;
+        rc_input.start();
+        Some(rc_input)
+      }
+      _ => None,
+    };
+    let analog_input = match setpoint_source(&params) {
+      SetpointSource::Analog => Some(AnalogInput::new(&mut gpio_c)?),
+      _ => None,
+    };
+
+    let bus_voltage = match params.get_bool(ParamId::BusVoltageMeasured) {
+      true => Some(BusVoltage::new(
+        params.get_f32(ParamId::BusVoltageDivider),
+        &mut gpio_b,
+      )?),
+      false => None,
+    };
+
+    let fet_ntc = params.get_bool(ParamId::ThermalFetNtc);
+    let motor_ntc = params.get_bool(ParamId::ThermalMotorNtc);
+    let thermistors = match fet_ntc || motor_ntc {
+      true => Some(Thermistors::new(fet_ntc, motor_ntc, &mut gpio_d)?),
+      false => None,
+    };
+
+    let mut comms = Comms::new(&mut system, &mut gpio_a)?;
+    comms.start();
+
+    let can_node_id = params.get_u32(ParamId::CanNodeId) as u8;
+    let can = match params.get_bool(ParamId::CanOpen) {
+      false => {
+        let mut can_node = CanNode::new(can_node_id, &mut system, &mut gpio_b)?;
+        can_node.start()?;
+        CanInterface::Simple(can_node)
+      }
+      true => {
+        let mut canopen_node = CanOpenNode::new(can_node_id, &mut system, &mut gpio_b)?;
+        canopen_node.start()?;
+        CanInterface::CanOpen(canopen_node)
+      }
+    };
+
+    let thermal = ThermalModel::new(thermal_config(&params));
+    let fault_policy = FaultPolicy::new(fault_rules(&params));
+
+    let mut motion_tracker = MotionTracker::new(params.get_f32(ParamId::VelocityTimeConstant));
+    motion_tracker.set_tracking(motion_tracking(&params));
+    motion_tracker.set_latency(params.get_f32(ParamId::MotionLatency));
 
     Ok(Self {
       recovery_mode: None,
       num_magnet_pairs,
       mode: Mode::Start,
+      params,
+      clock,
+      comms,
+      can,
+      motion_tracker,
+      motion: Motion {
+        angle: 0f32,
+        position: 0f32,
+        velocity: 0f32,
+        acceleration: 0f32,
+      },
+      warnings: 0,
+      drv_dead_time,
       system,
       gpio_a,
       gpio_b,
+      gpio_c,
+      gpio_d,
       gpio_e,
       drv_8305,
       magnet_controller: current_controller,
       position_sensor,
+      angle_estimator,
+      current_sense,
+      phase_currents: [0f32; 3],
+      step_dir,
+      rc_input,
+      analog_input,
+      analog_setpoint: AnalogSetpoint::new(),
+      bus_voltage,
+      thermal,
+      thermistors,
+      fault_policy,
+      brake_power: 0f32,
+      setpoint_age: 0f32,
     })
   }
 
-  fn handle_drv_8305_errors(&mut self) -> Result<()> {
-    let warnings = self.drv_8305.read_warnings()?;
+  fn read_drv_8305_warnings(&mut self) -> Result<()> {
+    self.warnings = self.drv_8305.read_warnings()?.data;
+    Ok(())
+  }
+
+  fn mode_id(&self) -> ModeId {
+    if self.recovery_mode.is_some() {
+      return ModeId::Recovery;
+    }
+
+    match &self.mode {
+      Mode::Start | Mode::Idle => ModeId::Idle,
+      Mode::Calibrate(_) => ModeId::Calibrate,
+      Mode::Demo(_) => ModeId::Demo,
+      Mode::Servo(servo_mode) => match servo_mode.get_target() {
+        Target::Position(_) => ModeId::Position,
+        Target::Velocity(_) => ModeId::Velocity,
+        Target::Torque(_) => ModeId::Torque,
+      },
+      Mode::Sensorless(_) => ModeId::Sensorless,
+      Mode::Homing(_) => ModeId::Homing,
+      Mode::Impedance(_) => ModeId::Impedance,
+      Mode::Haptic(_) => ModeId::Haptic,
+      Mode::OpenLoop(_) => ModeId::OpenLoop,
+      Mode::StepDir(_) => ModeId::StepDir,
+    }
+  }
+
+  fn status(&self) -> Status {
+    Status {
+      timestamp_us: self.clock.micros() as u32,
+      mode: self.mode_id(),
+      gate_enabled: self.drv_8305.is_gate_enabled(),
+      warnings: self.warnings,
+      faults: self.faults(),
+      position: self.motion.position,
+      velocity: self.motion.velocity,
+      phase_angle: self.magnet_controller.get_phase_angle(),
+      power: self.magnet_controller.get_power_scale(),
+      bus_voltage: self.magnet_controller.get_bus_voltage(),
+    }
+  }
+
+  fn faults(&self) -> u16 {
+    let sensor_faults = match self.angle_estimator.health() {
+      SensorHealth::Ok => 0,
+      SensorHealth::Degraded => Fault::AngleSensor as u16,
+      SensorHealth::Failed => Fault::AngleSensor as u16 | Fault::AngleSensorFailed as u16,
+    };
+    let thermal_faults = match self.thermal.derating() < 1f32 {
+      true => Fault::ThermalDerating as u16,
+      false => 0,
+    };
+    sensor_faults | thermal_faults | self.fault_policy.faults()
+  }
+
+  fn update_bus_voltage(&mut self, dt: f32) -> Result<()> {
+    match self.bus_voltage.as_mut().and_then(|bus| bus.read(dt)) {
+      Some(voltage) => self.magnet_controller.set_bus_voltage(voltage),
+      None => Ok(()),
+    }
+  }
+
+  // Phase current amplitude (A), when current sensing is fitted.
+  fn measured_current(&self) -> Option<f32> {
+    self.current_sense.as_ref()?;
+    let (alpha, beta) = clarke(self.phase_currents);
+    Some(libm::sqrtf(alpha * alpha + beta * beta))
+  }
+
+  // Steps the thermal model, whose trip temperatures are a fault source.
+  fn update_thermal(&mut self, dt: f32) {
+    let current = match self.measured_current() {
+      Some(current) => current,
+      // Without current sensing, estimated from the voltage applied less the
+      // back EMF.
+      None => {
+        let model = motor_model(&self.params);
+        let (alpha, beta) = self.magnet_controller.get_voltage_vector();
+        let back_emf =
+          model.flux_linkage * libm::fabsf(self.motion.velocity) * self.num_magnet_pairs as f32;
+        (libm::sqrtf(alpha * alpha + beta * beta) - back_emf).max(0f32) / model.resistance
+      }
+    };
+    let (fet_ntc, motor_ntc) = match &mut self.thermistors {
+      Some(thermistors) => thermistors.read(&ntc_config(&self.params)),
+      None => (None, None),
+    };
+    let drv_temperature = Warnings {
+      data: self.warnings,
+    }
+    .min_temperature();
+    self
+      .thermal
+      .update(current, motor_ntc, (fet_ntc, drv_temperature), dt);
+  }
+
+  // Feeds the fault policy with which fault conditions are present and
+  // carries out the action of the most severe active fault.
+  fn check_faults(&mut self, dt: f32) -> Result<()> {
+    let params = &self.params;
+    let warnings = Warnings {
+      data: self.warnings,
+    };
+    let bus_voltage = self
+      .bus_voltage
+      .as_ref()
+      .map(|_| self.magnet_controller.get_bus_voltage());
+    let conditions = [
+      (FaultSource::Driver, warnings.has_faults()),
+      (
+        FaultSource::DriverTemperature,
+        warnings.min_temperature().is_some(),
+      ),
+      (
+        FaultSource::AngleSensor,
+        self.angle_estimator.health() != SensorHealth::Ok,
+      ),
+      (
+        FaultSource::Overvoltage,
+        bus_voltage.map_or(false, |voltage| {
+          voltage > params.get_f32(ParamId::BusOvervoltage)
+        }),
+      ),
+      (
+        FaultSource::Undervoltage,
+        bus_voltage.map_or(false, |voltage| {
+          voltage < params.get_f32(ParamId::BusUndervoltage)
+        }),
+      ),
+      (
+        FaultSource::Overspeed,
+        libm::fabsf(self.motion.velocity) > params.get_f32(ParamId::FaultMaxVelocity),
+      ),
+      // The first step's time includes startup.
+      (
+        FaultSource::LoopOverrun,
+        !matches!(self.mode, Mode::Start) && dt > params.get_f32(ParamId::FaultMaxLoopTime),
+      ),
+      (
+        FaultSource::Overtemperature,
+        self.thermal.is_overtemperature(),
+      ),
+    ];
+    for (source, present) in conditions.iter() {
+      if self.fault_policy.update(*source, *present) {
+        println!("Fault: {}", source.fault().name()).ok();
+        if let FaultSource::Driver = source {
+          print_drv_8305_warnings(&warnings);
+        }
+      }
+    }
+
+    let action = self.fault_policy.action();
+    let power_limit = match action {
+      Some(FaultAction::Derate) => self.params.get_f32(ParamId::FaultDeratePower),
+      _ => 1f32,
+    };
+    self
+      .magnet_controller
+      .set_power_limit(self.thermal.derating().min(power_limit))?;
+
+    match action {
+      Some(FaultAction::Stop) | Some(FaultAction::Latch) => {
+        if !matches!(self.mode, Mode::Idle) {
+          self.enter_mode(ModeId::Idle)?;
+        }
+      }
+      _ => {}
+    }
+
+    // Coasting while the fault is present lets the mode carry on afterwards.
+    let coast = match action {
+      Some(FaultAction::Coast) | Some(FaultAction::Latch) => true,
+      _ => false,
+    };
+    if coast {
+      if !matches!(
+        self.magnet_controller.get_output_state(),
+        OutputState::Coast
+      ) {
+        self
+          .magnet_controller
+          .set_output_state(OutputState::Coast)?;
+      }
+      if self.drv_8305.is_gate_enabled() {
+        self.drv_8305.disable_gate();
+      }
+      if self.recovery_mode.is_none() {
+        self.recovery_mode = Some(RecoveryMode::new());
+      }
+    } else if self.recovery_mode.take().is_some() && !matches!(self.mode, Mode::Start | Mode::Idle)
+    {
+      self
+        .magnet_controller
+        .set_output_state(OutputState::Drive)?;
+      self.drv_8305.enable_gate();
+    }
+
+    // Braking into the supply would only raise it further.
+    if let (true, OutputState::RegenBrake(_)) = (
+      self.fault_policy.is_active(FaultSource::Overvoltage),
+      self.magnet_controller.get_output_state(),
+    ) {
+      self
+        .magnet_controller
+        .set_output_state(OutputState::ShortBrake)?;
+    }
+    Ok(())
+  }
+
+  fn stop_output_state(&self) -> OutputState {
+    match self.params.get_u32(ParamId::StopMode) {
+      1 => OutputState::ShortBrake,
+      2 if self.current_sense.is_some() => {
+        OutputState::RegenBrake(self.params.get_f32(ParamId::StopRegenCurrent))
+      }
+      // Regulating the braking current needs current sensing.
+      2 => OutputState::ShortBrake,
+      _ => OutputState::Coast,
+    }
+  }
+
+  fn enter_mode(&mut self, mode_id: ModeId) -> Result<()> {
+    // Requesting a mode that drives the motor retries a failed angle sensor.
+    if !matches!(mode_id, ModeId::Idle | ModeId::Recovery) {
+      if self.fault_policy.blocks_driving() {
+        return Err(Error::new("A fault is stopping the motor"));
+      }
+      // Without a sensor the angle always reads zero, so only the modes that
+      // don't commutate from it can run.
+      if let (Sensor::None(_), false) = (
+        &self.position_sensor,
+        matches!(mode_id, ModeId::Sensorless | ModeId::OpenLoop),
+      ) {
+        return Err(Error::new("Mode needs a position sensor"));
+      }
+      self.angle_estimator.reset();
+      self
+        .magnet_controller
+        .set_output_state(OutputState::Drive)?;
+    }
+
+    self.mode = match mode_id {
+      ModeId::Idle => {
+        self
+          .magnet_controller
+          .set_phase_angle_and_power(0f32, 0f32)?;
+        let output_state = self.stop_output_state();
+        self.magnet_controller.set_output_state(output_state)?;
+        self.brake_power = 0f32;
+        match output_state {
+          OutputState::Coast => self.drv_8305.disable_gate(),
+          _ => self.drv_8305.enable_gate(),
+        }
+        Mode::Idle
+      }
+      ModeId::Calibrate => Mode::Calibrate(CalibrationMode::new(&self.params)),
+      ModeId::Demo => Mode::Demo(DemoMode::new(
+        &self.params,
+        &mut self.drv_8305,
+        &mut self.magnet_controller,
+      )?),
+      ModeId::Position => Mode::Servo(ServoMode::new(
+        &mut self.drv_8305,
+        Target::Position(self.motion.position),
+      )),
+      ModeId::Velocity => Mode::Servo(ServoMode::new(&mut self.drv_8305, Target::Velocity(0f32))),
+      ModeId::Torque => Mode::Servo(ServoMode::new(&mut self.drv_8305, Target::Torque(0f32))),
+      ModeId::Sensorless => match self.current_sense {
+        Some(_) => Mode::Sensorless(SensorlessMode::new(
+          &self.params,
+          self.num_magnet_pairs,
+          &mut self.drv_8305,
+        )),
+        None => return Err(Error::new("Sensorless mode needs current sensing")),
+      },
+      ModeId::Homing => Mode::Homing(HomingMode::new(&self.params, &mut self.drv_8305)),
+      ModeId::Impedance => Mode::Impedance(ImpedanceMode::new(&mut self.drv_8305, &self.motion)),
+      ModeId::Haptic => Mode::Haptic(HapticMode::new(&mut self.drv_8305)),
+      ModeId::OpenLoop => Mode::OpenLoop(OpenLoopMode::new(&mut self.drv_8305)),
+      ModeId::StepDir => match self.step_dir {
+        Some(_) => Mode::StepDir(StepDirMode::new(&mut self.drv_8305, &self.motion)),
+        None => return Err(Error::new("Step/dir mode needs the step/dir input enabled")),
+      },
+      ModeId::Recovery => return Err(Error::new("Recovery mode cannot be requested")),
+    };
+
+    Ok(())
+  }
 
-    if warnings.ok() {
-      self.recovery_mode = None;
+  // Enters the mode to run in once calibrated or homed: the one an input
+  // drives, if any is fitted, or the given default.
+  fn enter_run_mode(&mut self, default: ModeId) -> Result<()> {
+    if self.step_dir.is_some() {
+      self.enter_mode(ModeId::StepDir)
+    } else if setpoint_source(&self.params) != SetpointSource::Commands {
+      self.enter_mode(setpoint_mode(&self.params))
     } else {
-      self.drv_8305.disable_gate();
-      self.recovery_mode = Some(RecoveryMode::new());
+      self.enter_mode(default)
+    }
+  }
+
+  // Passes the setpoint input on as the target while in a servo mode. Losing
+  // the input stops the motor, which stays stopped until a mode is requested
+  // again.
+  fn apply_setpoint_input(&mut self, dt: f32) -> Result<()> {
+    let params = &self.params;
+    let analog_setpoint = &mut self.analog_setpoint;
+    let fraction = match (&mut self.rc_input, &mut self.analog_input) {
+      (Some(rc_input), _) => rc_input
+        .read_pulse()
+        .and_then(|width| rc_fraction(params, width)),
+      (None, Some(analog_input)) => analog_input
+        .read()
+        .and_then(|volts| analog_setpoint.update(params, volts, dt)),
+      (None, None) => return Ok(()),
+    };
+
+    match (fraction, &mut self.mode) {
+      (Some(fraction), Mode::Servo(servo_mode)) => {
+        self.setpoint_age = 0f32;
+        servo_mode.set_target(setpoint_target(&self.params, fraction));
+      }
+      (None, Mode::Servo(_)) => {
+        self.setpoint_age += dt;
+        if self.setpoint_age >= self.params.get_f32(ParamId::SetpointTimeout) {
+          println!("Setpoint input lost").ok();
+          self.enter_mode(ModeId::Idle)?;
+        }
+      }
+      _ => self.setpoint_age = 0f32,
+    }
+
+    Ok(())
+  }
+
+  // Calibration and homing have to finish before targets are taken, or the
+  // sensor offset or the zero would be left unset.
+  fn is_preparing(&self) -> bool {
+    matches!(
+      self.mode,
+      Mode::Start | Mode::Calibrate(_) | Mode::Homing(_)
+    )
+  }
+
+  // Sets the target of the running servo mode, entering the mode for the
+  // target first if another is running.
+  fn set_servo_target(&mut self, target: Target) -> Result<()> {
+    if let (Mode::Sensorless(sensorless_mode), Target::Velocity(velocity)) =
+      (&mut self.mode, target)
+    {
+      sensorless_mode.set_target(velocity);
+      return Ok(());
+    }
+
+    if !matches!(self.mode, Mode::Servo(_)) {
+      self.enter_mode(match target {
+        Target::Position(_) => ModeId::Position,
+        Target::Velocity(_) => ModeId::Velocity,
+        Target::Torque(_) => ModeId::Torque,
+      })?;
+    }
+    if let Mode::Servo(servo_mode) = &mut self.mode {
+      servo_mode.set_target(target);
+    }
+
+    Ok(())
+  }
+
+  // Targets the drive can't take are dropped, as mode requests are.
+  fn set_can_target(&mut self, target: Target) {
+    if let Err(e) = self.set_servo_target(target) {
+      println!("CAN target dropped: {}", e.message).ok();
+    }
+  }
+
+  // Switches to impedance mode if needed, as servo targets do.
+  fn set_impedance_target(&mut self, target: ImpedanceTarget) -> Result<()> {
+    if !matches!(self.mode, Mode::Impedance(_)) {
+      self.enter_mode(ModeId::Impedance)?;
+    }
+    if let Mode::Impedance(impedance_mode) = &mut self.mode {
+      impedance_mode.set_target(target);
+    }
+    Ok(())
+  }
 
-      if warnings.has(WarningFlag::Overtemp) {
-        println!("Overtemp").ok();
+  fn handle_request(&mut self, request: Request) -> Response {
+    match request {
+      Request::Ping => Response::Pong {
+        version: PROTOCOL_VERSION,
+      },
+      Request::GetStatus => Response::Status(self.status()),
+      // Requesting idle acknowledges latched faults whose conditions are gone.
+      Request::SetMode(ModeId::Idle) => {
+        self.fault_policy.acknowledge();
+        match self.enter_mode(ModeId::Idle) {
+          Ok(()) => Response::Ok,
+          Err(_) => Response::Error(ErrorCode::Failed),
+        }
+      }
+      Request::SetMode(_)
+      | Request::SetPositionTarget(_)
+      | Request::SetVelocityTarget(_)
+      | Request::SetImpedanceTarget(_)
+        if self.recovery_mode.is_some() =>
+      {
+        Response::Error(ErrorCode::NotAllowed)
+      }
+      Request::SetMode(ModeId::Recovery) => Response::Error(ErrorCode::NotAllowed),
+      Request::SetMode(_)
+      | Request::SetPositionTarget(_)
+      | Request::SetVelocityTarget(_)
+      | Request::SetImpedanceTarget(_)
+        if self.fault_policy.blocks_driving() =>
+      {
+        Response::Error(ErrorCode::NotAllowed)
       }
-      if warnings.has(WarningFlag::TempOver135C) {
-        println!("Temp over 135 C").ok();
+      Request::SetPositionTarget(_)
+      | Request::SetVelocityTarget(_)
+      | Request::SetImpedanceTarget(_)
+        if self.is_preparing() =>
+      {
+        Response::Error(ErrorCode::NotAllowed)
       }
-      if warnings.has(WarningFlag::TempOver125C) {
-        println!("Temp over 125 C").ok();
+      Request::SetMode(mode_id) => match self.enter_mode(mode_id) {
+        Ok(()) => Response::Ok,
+        Err(_) => Response::Error(ErrorCode::Failed),
+      },
+      Request::SetPositionTarget(position) => {
+        match self.set_servo_target(Target::Position(position)) {
+          Ok(()) => Response::Ok,
+          Err(_) => Response::Error(ErrorCode::Failed),
+        }
       }
-      if warnings.has(WarningFlag::TempOver105C) {
-        println!("Temp over 105 C").ok();
+      Request::SetVelocityTarget(velocity) => {
+        match self.set_servo_target(Target::Velocity(velocity)) {
+          Ok(()) => Response::Ok,
+          Err(_) => Response::Error(ErrorCode::Failed),
+        }
       }
-      if warnings.has(WarningFlag::ChargePumpUndervolt) {
-        println!("Charge pump undervolt").ok();
+      Request::SetImpedanceTarget(target) => match self.set_impedance_target(target) {
+        Ok(()) => Response::Ok,
+        Err(_) => Response::Error(ErrorCode::Failed),
+      },
+      Request::GetParam(index) => match ParamId::from_index(index) {
+        Some(id) => Response::Param {
+          index,
+          name: Name::new(id.def().name),
+          value: self.params.get(id),
+        },
+        None => Response::Error(ErrorCode::UnknownParameter),
+      },
+      Request::GetParamInfo(index) => match ParamId::from_index(index) {
+        Some(id) => {
+          let def = id.def();
+          let (min, max, default) = match def.range {
+            Range::F32 { min, max, default } => (
+              ParamValue::F32(min),
+              ParamValue::F32(max),
+              ParamValue::F32(default),
+            ),
+            Range::U32 { min, max, default } => (
+              ParamValue::U32(min),
+              ParamValue::U32(max),
+              ParamValue::U32(default),
+            ),
+            Range::Bool { default } => (
+              ParamValue::Bool(false),
+              ParamValue::Bool(true),
+              ParamValue::Bool(default),
+            ),
+          };
+          Response::ParamInfo {
+            index,
+            name: Name::new(def.name),
+            unit: Name::new(def.unit),
+            min,
+            max,
+            default,
+          }
+        }
+        None => Response::Error(ErrorCode::UnknownParameter),
+      },
+      Request::SetParam(index, value) => match ParamId::from_index(index) {
+        Some(id) => match self.set_param(id, value) {
+          Ok(()) => Response::Ok,
+          Err(_) => Response::Error(ErrorCode::InvalidValue),
+        },
+        None => Response::Error(ErrorCode::UnknownParameter),
+      },
+      Request::GetPwmTiming => {
+        let timing = self.magnet_controller.get_pwm_timing();
+        Response::PwmTiming {
+          frequency: timing.frequency,
+          deadtime_ns: timing.deadtime.as_nanos() as u32,
+        }
       }
-      if warnings.has(WarningFlag::VdsOvercurrent) {
-        println!("VDS overcurrent").ok();
+      // Flash writes stall the control loop, so only while nothing is driven.
+      // Idle can keep the gate on to brake, which is fine.
+      Request::SaveParams => {
+        let idle = matches!(self.mode, Mode::Idle)
+          && self.magnet_controller.get_output_state() != OutputState::Drive;
+        match idle {
+          false => Response::Error(ErrorCode::NotAllowed),
+          true => match param_store::save(&self.params) {
+            Ok(()) => Response::Ok,
+            Err(_) => Response::Error(ErrorCode::Failed),
+          },
+        }
+      }
+      Request::StreamTelemetry(divider) => {
+        self.comms.set_telemetry_divider(divider);
+        Response::Ok
+      }
+    }
+  }
+
+  // Parameters not pushed here (sensor and CAN settings) are read at startup
+  // and take effect after saving and restarting.
+  fn set_param(&mut self, id: ParamId, value: ParamValue) -> core::result::Result<(), ParamError> {
+    let previous = self.params.get(id);
+    self.params.set(id, value)?;
+
+    match id {
+      ParamId::VelocityTimeConstant => self
+        .motion_tracker
+        .set_velocity_time_constant(self.params.get_f32(id)),
+      ParamId::MotionTracking | ParamId::MotionTrackingBandwidth => self
+        .motion_tracker
+        .set_tracking(motion_tracking(&self.params)),
+      ParamId::MotionLatency => self.motion_tracker.set_latency(self.params.get_f32(id)),
+      ParamId::CurrentShunt | ParamId::CurrentAmpGain => {
+        if let Some(current_sense) = &mut self.current_sense {
+          current_sense.set_scale(
+            self.params.get_f32(ParamId::CurrentShunt),
+            self.params.get_f32(ParamId::CurrentAmpGain),
+          );
+        }
+      }
+      ParamId::FusionBlendStart
+      | ParamId::FusionBlendEnd
+      | ParamId::FusionModelWeight
+      | ParamId::FusionMaxDeviation
+      | ParamId::FusionMaxBadReadings
+      | ParamId::FusionFallback
+      | ParamId::SensorlessLockError => {
+        self.angle_estimator.set_config(fusion_config(&self.params))
+      }
+      ParamId::MotorResistance
+      | ParamId::MotorInductance
+      | ParamId::MotorFluxLinkage
+      | ParamId::ObserverGain
+      | ParamId::ObserverPllBandwidth => {
+        self.angle_estimator.set_model(
+          motor_model(&self.params),
+          self.params.get_f32(ParamId::ObserverGain),
+          self.params.get_f32(ParamId::ObserverPllBandwidth),
+        );
+        self.thermal.set_config(thermal_config(&self.params));
+      }
+      ParamId::ThermalAmbient
+      | ParamId::ThermalWindingResistance
+      | ParamId::ThermalWindingTimeConstant
+      | ParamId::ThermalMotorDerateTemp
+      | ParamId::ThermalMotorTripTemp
+      | ParamId::ThermalFetDerateTemp
+      | ParamId::ThermalFetTripTemp => self.thermal.set_config(thermal_config(&self.params)),
+      id if FAULT_ACTION_PARAMS.contains(&id) || FAULT_DEBOUNCE_PARAMS.contains(&id) => {
+        self.fault_policy.set_rules(fault_rules(&self.params))
+      }
+      ParamId::HallTimeout => {
+        if let Sensor::Hall(hall_sensor) = &mut self.position_sensor {
+          hall_sensor.set_timeout(self.params.get_f32(id));
+        }
+      }
+      ParamId::PwmFrequency
+      | ParamId::Deadtime
+      | ParamId::PwmSampleAdvance
+      | ParamId::PwmMinLowSide => {
+        if self.apply_pwm_timing().is_err() {
+          self.params.set(id, previous).ok();
+          return Err(ParamError::Rejected);
+        }
+      }
+      ParamId::DeadtimeCompensation | ParamId::DeadtimeCompensationBand => {
+        if self
+          .magnet_controller
+          .set_deadtime_compensation(
+            self.params.get_bool(ParamId::DeadtimeCompensation),
+            self.params.get_f32(ParamId::DeadtimeCompensationBand),
+          )
+          .is_err()
+        {
+          self.params.set(id, previous).ok();
+          return Err(ParamError::Rejected);
+        }
+      }
+      ParamId::SupplyVoltage | ParamId::BusVoltageCompensation => {
+        if self.bus_voltage.is_none() {
+          self
+            .magnet_controller
+            .set_bus_voltage(self.params.get_f32(ParamId::SupplyVoltage))
+            .ok();
+        }
+        if self
+          .magnet_controller
+          .set_voltage_compensation(voltage_compensation(&self.params))
+          .is_err()
+        {
+          self.params.set(id, previous).ok();
+          return Err(ParamError::Rejected);
+        }
       }
-      if warnings.has(WarningFlag::PvddOvervolt) {
-        println!("PVDD overvolt").ok();
+      ParamId::BusVoltageDivider => {
+        if let Some(bus_voltage) = &mut self.bus_voltage {
+          bus_voltage.set_divider(self.params.get_f32(id));
+        }
       }
-      if warnings.has(WarningFlag::PvddUndervolt) {
-        println!("PVDD undervolt").ok();
+      ParamId::Modulation | ParamId::SixStepAdvance => {
+        if self
+          .magnet_controller
+          .set_modulation(modulation(&self.params))
+          .is_err()
+        {
+          self.params.set(id, previous).ok();
+          return Err(ParamError::Rejected);
+        }
       }
-      if warnings.has(WarningFlag::TempOver175C) {
-        println!("Temp over 175 C").ok();
+      _ => {}
+    }
+
+    Ok(())
+  }
+
+  fn apply_pwm_timing(&mut self) -> Result<()> {
+    let timing = self
+      .magnet_controller
+      .set_pwm_timing(&pwm_config(&self.params), self.drv_dead_time)?;
+
+    println!(
+      "PWM at {} Hz with {} ns deadtime",
+      timing.frequency,
+      timing.deadtime.as_nanos()
+    )
+    .ok();
+
+    Ok(())
+  }
+
+  fn handle_requests(&mut self) -> Result<()> {
+    while let Some(request) = self.comms.poll()? {
+      let response = self.handle_request(request);
+      self.comms.send(&response)?;
+    }
+
+    if let Mode::Haptic(haptic_mode) = &mut self.mode {
+      if let Some(index) = haptic_mode.take_detent_change() {
+        self.comms.send(&Response::Event(Event::Detent(index)))?;
       }
-      if warnings.has(WarningFlag::Fault) {
-        println!("FAULT").ok();
+    }
+
+    if self.comms.telemetry_due() {
+      let status = self.status();
+      self.comms.send(&Response::Telemetry(status))?;
+    }
+
+    Ok(())
+  }
+
+  fn handle_can_command(&mut self, command: Command) -> Result<()> {
+    if let Command::Disable = command {
+      self.fault_policy.acknowledge();
+    }
+    if self.recovery_mode.is_some() {
+      if let Command::Disable = command {
+        self.enter_mode(ModeId::Idle)?;
       }
+      return Ok(());
     }
 
+    match command {
+      Command::Enable => self.drv_8305.enable_gate(),
+      Command::Disable => self.enter_mode(ModeId::Idle)?,
+      Command::SetMode(ModeId::Recovery) => {}
+      // A mode the drive can't enter is a bad request rather than a failure
+      // of the drive, so it is dropped and the drive carries on as it was.
+      Command::SetMode(mode_id) => {
+        if let Err(e) = self.enter_mode(mode_id) {
+          println!("CAN mode request dropped: {}", e.message).ok();
+        }
+      }
+      Command::SetTorque(_) | Command::SetVelocity(_) | Command::SetPosition(_)
+        if self.fault_policy.blocks_driving() =>
+      {
+        println!("CAN target dropped: a fault is stopping the motor").ok();
+      }
+      Command::SetTorque(_) | Command::SetVelocity(_) | Command::SetPosition(_)
+        if self.is_preparing() =>
+      {
+        println!("CAN target dropped: calibration or homing is running").ok();
+      }
+      Command::SetTorque(effort) => self.set_can_target(Target::Torque(effort)),
+      Command::SetVelocity(velocity) => self.set_can_target(Target::Velocity(velocity)),
+      Command::SetPosition(position) => self.set_can_target(Target::Position(position)),
+      Command::SetPublishRate(period_ms) => {
+        if let CanInterface::Simple(can_node) = &mut self.can {
+          can_node.set_publish_rate(period_ms);
+        }
+      }
+    };
+
     Ok(())
   }
-}
-impl<'a> Program for Bldc {
-  fn step(&mut self) -> Result<()> {
-    self.handle_drv_8305_errors()?;
+
+  fn apply_drive_output(&mut self, output: DriveOutput) -> Result<()> {
+    match output {
+      DriveOutput::Disabled => {
+        if !matches!(self.mode, Mode::Idle) || self.drv_8305.is_gate_enabled() {
+          self.enter_mode(ModeId::Idle)?;
+        }
+      }
+      DriveOutput::Holding => {
+        if !matches!(self.mode, Mode::Idle) {
+          self.enter_mode(ModeId::Idle)?;
+        }
+        if self.magnet_controller.get_output_state() != OutputState::ShortBrake {
+          self
+            .magnet_controller
+            .set_output_state(OutputState::ShortBrake)?;
+        }
+        if !self.drv_8305.is_gate_enabled() {
+          self.drv_8305.enable_gate();
+        }
+      }
+      // A target the drive can't take, such as without a sensor, leaves it as
+      // it is. The node repeats it every step, so it isn't logged.
+      DriveOutput::Position(position) => {
+        self.set_servo_target(Target::Position(position)).ok();
+      }
+      DriveOutput::Velocity(velocity) => {
+        self.set_servo_target(Target::Velocity(velocity)).ok();
+      }
+    };
+
+    Ok(())
+  }
+
+  fn handle_can(&mut self, dt: f32) -> Result<()> {
+    let status = self.status();
+    let current = self.measured_current();
+    let now_us = self.clock.micros();
+
+    match &mut self.can {
+      CanInterface::Simple(can_node) => {
+        can_node.publish(now_us, &status, current)?;
+        while let Some(command) = self.can_node_poll() {
+          self.handle_can_command(command)?;
+        }
+      }
+      CanInterface::CanOpen(canopen_node) => {
+        // A fault stopping the motor puts the node in its fault state, so it
+        // disables the drive rather than asking for targets.
+        let fault = self.recovery_mode.is_some() || self.fault_policy.blocks_driving();
+        let previous_state = canopen_node.get_state();
+        let output = canopen_node.process(dt, &status, fault)?;
+        canopen_node.publish(now_us)?;
+
+        // The node only takes charge of the drive on changes of power state
+        // and while it is moving it, so that a mode chosen some other way
+        // isn't overridden every step. Calibration and homing are left to
+        // finish.
+        let state = canopen_node.get_state();
+        let driving = matches!(state, State::OperationEnabled | State::QuickStopActive);
+        if self.recovery_mode.is_none()
+          && !self.is_preparing()
+          && (driving || state != previous_state)
+        {
+          self.apply_drive_output(output)?;
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  fn can_node_poll(&mut self) -> Option<Command> {
+    match &mut self.can {
+      CanInterface::Simple(can_node) => can_node.poll(),
+      CanInterface::CanOpen(_) => None,
+    }
+  }
+
+  // Falls back to the last angle, stopping the motor if the mode needs one,
+  // when neither the sensor nor the estimate gives a usable angle.
+  fn estimate_angle(&mut self, dt: f32) -> Result<f32> {
+    if let Sensor::None(_) = self.position_sensor {
+      return Ok(0f32);
+    }
+
+    let reading = self.position_sensor.read_absolute_angle();
+    match self
+      .angle_estimator
+      .update(reading, self.motion.velocity, dt)
+    {
+      Ok(angle) => Ok(angle),
+      Err(e) => {
+        if !matches!(self.mode, Mode::Start | Mode::Idle | Mode::Sensorless(_)) {
+          println!("{}, stopping", e.message).ok();
+          self.enter_mode(ModeId::Idle)?;
+        }
+        Ok(self.motion.angle)
+      }
+    }
+  }
+
+  fn step_mode(&mut self, dt: f32) -> Result<()> {
     match &mut self.recovery_mode {
       Some(recovery_mode) => recovery_mode.step(
         &mut self.drv_8305,
@@ -136,40 +1271,207 @@ impl<'a> Program for Bldc {
         &mut self.position_sensor,
       ),
       None => match &mut self.mode {
-        Mode::Start => {
-          self.mode = Mode::Calibrate(CalibrationMode::new());
+        // There is nothing to calibrate without a sensor.
+        Mode::Start => match self.position_sensor {
+          Sensor::None(_) => self.enter_mode(ModeId::Idle),
+          _ => {
+            self.mode = Mode::Calibrate(CalibrationMode::new(&self.params));
+            Ok(())
+          }
+        },
+        Mode::Idle => {
+          if let OutputState::RegenBrake(current) = self.magnet_controller.get_output_state() {
+            if libm::fabsf(self.motion.velocity) < REGEN_MIN_VELOCITY {
+              self
+                .magnet_controller
+                .set_output_state(OutputState::ShortBrake)?;
+            } else {
+              // Integral control of the power, with the gain that gives the
+              // loop its bandwidth through the winding resistance. Phase
+              // voltage amplitude is half the bus voltage at full power.
+              let measured = self.measured_current().unwrap_or(0f32);
+              let volts_per_power = self.magnet_controller.get_bus_voltage() / 2f32;
+              let gain =
+                REGEN_CURRENT_BANDWIDTH * motor_model(&self.params).resistance / volts_per_power;
+              self.brake_power = (self.brake_power + gain * (current - measured) * dt)
+                .max(0f32)
+                .min(1f32);
+
+              let phase_angle = self
+                .position_sensor
+                .absolute_to_phase_angle(self.motion.angle);
+              self
+                .magnet_controller
+                .brake(phase_angle, self.motion.velocity, self.brake_power)?;
+            }
+          }
           Ok(())
         }
         Mode::Calibrate(calibration_mode) => {
           calibration_mode.step(
+            &self.params,
             &mut self.drv_8305,
             &mut self.magnet_controller,
             &mut self.position_sensor,
           )?;
           if calibration_mode.is_done() {
-            self.mode = Mode::Demo(DemoMode::new(
-              &mut self.drv_8305,
-              &mut self.magnet_controller,
-            )?);
+            // Keep a learned hall table so it can be saved.
+            if let Sensor::Hall(hall_sensor) = &self.position_sensor {
+              for (angle, id) in hall_sensor.get_table().iter().zip(HALL_ANGLE_PARAMS.iter()) {
+                self.params.set(*id, ParamValue::F32(*angle)).ok();
+              }
+            }
+            self.motion_tracker.reset();
+            match self.params.get_bool(ParamId::HomeOnStartup) {
+              true => self.enter_mode(ModeId::Homing)?,
+              false => self.enter_run_mode(ModeId::Demo)?,
+            }
           }
           Ok(())
         }
         Mode::Demo(demo_mode) => {
           demo_mode.step(
+            &self.params,
             &mut self.drv_8305,
             &mut self.magnet_controller,
             &mut self.position_sensor,
           )?;
           Ok(())
         }
+        Mode::Servo(servo_mode) => servo_mode.step(
+          &self.params,
+          dt,
+          &self.motion,
+          &mut self.drv_8305,
+          &mut self.magnet_controller,
+          &mut self.position_sensor,
+        ),
+        Mode::Sensorless(sensorless_mode) => {
+          sensorless_mode.step(
+            &self.params,
+            dt,
+            self.phase_currents,
+            &mut self.drv_8305,
+            &mut self.magnet_controller,
+          )?;
+          match sensorless_mode.is_stopped() {
+            true => self.enter_mode(ModeId::Idle),
+            false => Ok(()),
+          }
+        }
+        Mode::Impedance(impedance_mode) => impedance_mode.step(
+          &self.params,
+          &self.motion,
+          &mut self.drv_8305,
+          &mut self.magnet_controller,
+          &mut self.position_sensor,
+        ),
+        Mode::Haptic(haptic_mode) => haptic_mode.step(
+          &self.params,
+          &self.motion,
+          &mut self.drv_8305,
+          &mut self.magnet_controller,
+          &mut self.position_sensor,
+        ),
+        Mode::OpenLoop(open_loop_mode) => open_loop_mode.step(
+          &self.params,
+          dt,
+          &mut self.drv_8305,
+          &mut self.magnet_controller,
+        ),
+        Mode::StepDir(step_dir_mode) => match &mut self.step_dir {
+          Some(step_dir) => step_dir_mode.step(
+            &self.params,
+            dt,
+            &self.motion,
+            step_dir.read_steps(),
+            step_dir.is_enable_high(),
+            &mut self.drv_8305,
+            &mut self.magnet_controller,
+            &mut self.position_sensor,
+          ),
+          None => Ok(()),
+        },
+        Mode::Homing(homing_mode) => {
+          homing_mode.step(
+            &self.params,
+            dt,
+            &self.motion,
+            &mut self.drv_8305,
+            &mut self.magnet_controller,
+            &mut self.position_sensor,
+          )?;
+          match homing_mode.get_state() {
+            // Holds where it backed off to, now relative to the new zero.
+            HomingState::Homed(zero) => {
+              println!("Homed").ok();
+              self.motion_tracker.set_zero(zero);
+              self.motion.position -= zero;
+              self.enter_run_mode(ModeId::Position)
+            }
+            HomingState::Failed => self.enter_mode(ModeId::Idle),
+            HomingState::Seeking | HomingState::BackingOff => Ok(()),
+          }
+        }
       },
     }
   }
+}
+impl<'a> Program for Bldc {
+  fn step(&mut self) -> Result<()> {
+    let dt = self.clock.tick();
+    self.update_bus_voltage(dt)?;
+
+    if let Some(current_sense) = &mut self.current_sense {
+      self.phase_currents = current_sense.read();
+      self
+        .magnet_controller
+        .set_phase_currents(Some(self.phase_currents));
+
+      let voltage = self.magnet_controller.get_voltage_vector();
+      self
+        .angle_estimator
+        .update_model(voltage, self.phase_currents, dt);
+    }
+
+    let angle = self.estimate_angle(dt)?;
+    self.motion = self.motion_tracker.update(angle, dt);
+    if let Some(velocity) = self.position_sensor.read_velocity() {
+      self.motion.velocity = velocity;
+    }
+    if let Mode::Sensorless(sensorless_mode) = &self.mode {
+      if let Some(velocity) = sensorless_mode.get_velocity() {
+        self.motion.velocity = velocity;
+      }
+    }
+
+    self.read_drv_8305_warnings()?;
+    self.update_thermal(dt);
+    self.check_faults(dt)?;
+    self.handle_requests()?;
+    self.handle_can(dt)?;
+    self.apply_setpoint_input(dt)?;
+    self.step_mode(dt)
+  }
 
+  // Regenerative braking needs the control loop, so only a configured short
+  // brake is kept; otherwise the motor coasts.
   fn safemode(&mut self) {
-    // Brake mode
-    self.magnet_controller.set_power_scale(0f32).ok();
-    self.drv_8305.disable_gate();
+    match self.stop_output_state() {
+      OutputState::ShortBrake => {
+        self
+          .magnet_controller
+          .set_output_state(OutputState::ShortBrake)
+          .ok();
+      }
+      _ => {
+        self
+          .magnet_controller
+          .set_output_state(OutputState::Coast)
+          .ok();
+        self.drv_8305.disable_gate();
+      }
+    }
   }
 
   fn shutdown(mut self) -> Result<()> {
@@ -181,10 +1483,50 @@ impl<'a> Program for Bldc {
       .drv_8305
       .return_hardware(&mut self.system, &mut self.gpio_b)?;
 
+    self.position_sensor.return_hardware(
+      &mut self.system,
+      &mut self.gpio_a,
+      &mut self.gpio_c,
+      &mut self.gpio_d,
+    )?;
+
     self
-      .position_sensor
+      .comms
       .return_hardware(&mut self.system, &mut self.gpio_a)?;
 
+    if let Some(current_sense) = self.current_sense {
+      current_sense.return_hardware(&mut self.gpio_a)?;
+    }
+
+    if let Some(step_dir) = self.step_dir {
+      step_dir.return_hardware(&mut self.system, &mut self.gpio_d)?;
+    }
+
+    if let Some(rc_input) = self.rc_input {
+      rc_input.return_hardware(&mut self.system, &mut self.gpio_b)?;
+    }
+
+    if let Some(analog_input) = self.analog_input {
+      analog_input.return_hardware(&mut self.gpio_c)?;
+    }
+
+    if let Some(bus_voltage) = self.bus_voltage {
+      bus_voltage.return_hardware(&mut self.gpio_b)?;
+    }
+
+    if let Some(thermistors) = self.thermistors {
+      thermistors.return_hardware(&mut self.gpio_d)?;
+    }
+
+    match self.can {
+      CanInterface::Simple(can_node) => {
+        can_node.return_hardware(&mut self.system, &mut self.gpio_b)?
+      }
+      CanInterface::CanOpen(canopen_node) => {
+        canopen_node.return_hardware(&mut self.system, &mut self.gpio_b)?
+      }
+    };
+
     Ok(())
   }
 
@@ -0,0 +1,59 @@
+use stm32f303_api::{
+  gpio::gpio_b::{GpioB, Pb1Analog},
+  Error, Result,
+};
+
+use crate::adc::{to_volts, Adc, AdcUnit, InjectedTrigger};
+
+// PB1 is ADC3 channel 1.
+const CHANNEL: u8 = 1;
+// Time constant (s) of the filter on the measurement, long enough to ride
+// out switching noise but short against a supply dropping out.
+const FILTER_TIME: f32 = 0.002;
+
+// Supply (VBUS) voltage through a resistive divider on PB1, converted by
+// ADC3 on each TIM1 trigger.
+pub struct BusVoltage {
+  adc: Adc,
+  divider: f32,
+  voltage: Option<f32>,
+  pin: Pb1Analog,
+}
+impl BusVoltage {
+  // `divider` is the ratio of the bus voltage to the voltage at the pin.
+  pub fn new(divider: f32, gpio_b: &mut GpioB) -> Result<Self> {
+    if divider < 1f32 {
+      return Err(Error::new("Bus voltage divider ratio must be at least 1"));
+    }
+
+    let mut adc = Adc::new(AdcUnit::Adc3)?;
+    adc.start_injected(&[CHANNEL], InjectedTrigger::Tim1Trgo)?;
+
+    Ok(Self {
+      adc,
+      divider,
+      voltage: None,
+      pin: gpio_b.take_pb1()?.as_analog(),
+    })
+  }
+
+  pub fn set_divider(&mut self, divider: f32) {
+    self.divider = divider.max(1f32);
+  }
+
+  // Filtered bus voltage (V), once the first conversion has completed.
+  pub fn read(&mut self, dt: f32) -> Option<f32> {
+    if self.adc.take_injected_complete() {
+      let sample = to_volts(self.adc.read_injected(0)) * self.divider;
+      self.voltage = Some(match self.voltage {
+        Some(voltage) => voltage + (sample - voltage) * (dt / FILTER_TIME).min(1f32),
+        None => sample,
+      });
+    }
+    self.voltage
+  }
+
+  pub fn return_hardware(self, gpio_b: &mut GpioB) -> Result<()> {
+    gpio_b.return_pb1(self.pin.teardown())
+  }
+}
@@ -0,0 +1,118 @@
+use bldc_protocol::can::Frame;
+use stm32f303_api::{
+  can::{Can, FilterBank, Mailbox},
+  gpio::{
+    gpio_b::{GpioB, Pb8AltFunc, Pb8CanRx, Pb9AltFunc, Pb9CanTx},
+    OutputSpeed, OutputType, PullDirection,
+  },
+  Result, System,
+};
+
+// Bit timing assumes an 8 MHz APB1 clock and 8 time quanta per bit with the
+// sample point at 87.5%.
+const APB1_FREQ: u32 = 8_000_000;
+const QUANTA_PER_BIT: u32 = 8;
+const TIME_SEGMENT_1: u8 = 6;
+const TIME_SEGMENT_2: u8 = 1;
+const SYNC_JUMP_WIDTH: u8 = 1;
+
+#[derive(Copy, Clone)]
+pub enum Bitrate {
+  Kbps125 = 125_000,
+  Kbps250 = 250_000,
+  Kbps500 = 500_000,
+  Kbps1000 = 1_000_000,
+}
+impl Bitrate {
+  fn prescaler(&self) -> u16 {
+    (APB1_FREQ / (QUANTA_PER_BIT * *self as u32)) as u16
+  }
+}
+
+pub struct CanBus {
+  can: Can,
+  rx: Pb8AltFunc<Pb8CanRx>,
+  tx: Pb9AltFunc<Pb9CanTx>,
+}
+impl CanBus {
+  pub fn new(bitrate: Bitrate, system: &mut System, gpio_b: &mut GpioB) -> Result<Self> {
+    let mut can = system.activate_can()?;
+    can.enter_init_mode()?;
+    can.set_bit_timing(
+      bitrate.prescaler(),
+      TIME_SEGMENT_1,
+      TIME_SEGMENT_2,
+      SYNC_JUMP_WIDTH,
+    )?;
+    can.enable_automatic_bus_off_recovery();
+    can.enable_transmit_fifo_priority();
+    can.config_filter_accept_all(FilterBank::Bank0)?;
+
+    Ok(Self {
+      can,
+      rx: gpio_b.take_pb8()?.as_alt_func(
+        PullDirection::Up,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+      tx: gpio_b.take_pb9()?.as_alt_func(
+        PullDirection::Up,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+    })
+  }
+
+  pub fn start(&mut self) -> Result<()> {
+    self.can.leave_init_mode()
+  }
+
+  pub fn stop(&mut self) -> Result<()> {
+    self.can.enter_init_mode()
+  }
+
+  // Queues a frame for transmission. Returns false if every mailbox is busy,
+  // in which case the frame is dropped.
+  pub fn transmit(&mut self, frame: &Frame) -> Result<bool> {
+    let mailbox = match self.can.free_tx_mailbox() {
+      Some(mailbox) => mailbox,
+      None => return Ok(false),
+    };
+
+    self.write_mailbox(mailbox, frame)?;
+    Ok(true)
+  }
+
+  fn write_mailbox(&mut self, mailbox: Mailbox, frame: &Frame) -> Result<()> {
+    self
+      .can
+      .write_tx_mailbox(mailbox, frame.id, frame.payload())?;
+    self.can.request_transmission(mailbox);
+    Ok(())
+  }
+
+  pub fn receive(&mut self) -> Option<Frame> {
+    if self.can.rx_fifo0_pending() == 0 {
+      return None;
+    }
+
+    let mut data = [0u8; 8];
+    let id = self.can.read_rx_fifo0_id();
+    let len = self.can.read_rx_fifo0_data(&mut data);
+    self.can.release_rx_fifo0();
+
+    Some(Frame {
+      id,
+      len: len as u8,
+      data,
+    })
+  }
+
+  pub fn return_hardware(mut self, system: &mut System, gpio_b: &mut GpioB) -> Result<()> {
+    self.stop()?;
+    system.deactivate_can(self.can)?;
+    gpio_b.return_pb8(self.rx.teardown())?;
+    gpio_b.return_pb9(self.tx.teardown())?;
+    Ok(())
+  }
+}
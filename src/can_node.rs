@@ -0,0 +1,110 @@
+use bldc_protocol::{
+  can::{Command, DriveStatus, Frame, Heartbeat, MotionStatus},
+  ModeId, Status,
+};
+use stm32f303_api::{gpio::gpio_b::GpioB, Error, Result, System};
+
+use crate::can::{Bitrate, CanBus};
+
+const BITRATE: Bitrate = Bitrate::Kbps500;
+const HEARTBEAT_PERIOD_US: u64 = 100_000;
+const DEFAULT_PUBLISH_PERIOD_MS: u16 = 10;
+
+pub struct CanNode {
+  bus: CanBus,
+  node_id: u8,
+  publish_period_us: u64,
+  last_publish_us: u64,
+  last_heartbeat_us: u64,
+  heartbeat_counter: u8,
+}
+impl CanNode {
+  pub fn new(node_id: u8, system: &mut System, gpio_b: &mut GpioB) -> Result<Self> {
+    Ok(Self {
+      bus: CanBus::new(BITRATE, system, gpio_b)?,
+      node_id,
+      publish_period_us: DEFAULT_PUBLISH_PERIOD_MS as u64 * 1000,
+      last_publish_us: 0,
+      last_heartbeat_us: 0,
+      heartbeat_counter: 0,
+    })
+  }
+
+  pub fn start(&mut self) -> Result<()> {
+    self.bus.start()
+  }
+
+  pub fn get_node_id(&self) -> u8 {
+    self.node_id
+  }
+
+  // Sets the period of the cyclic status frames. Zero disables them; the
+  // heartbeat is always sent.
+  pub fn set_publish_rate(&mut self, period_ms: u16) {
+    self.publish_period_us = period_ms as u64 * 1000;
+  }
+
+  // Returns the next command addressed to this node. Malformed commands are
+  // dropped since CAN has no reply path to report them on.
+  pub fn poll(&mut self) -> Option<Command> {
+    while let Some(frame) = self.bus.receive() {
+      if let Ok(Some(command)) = Command::decode(self.node_id, &frame) {
+        return Some(command);
+      }
+    }
+
+    None
+  }
+
+  // `current` is the phase current amplitude (A), when it is measured.
+  pub fn publish(&mut self, now_us: u64, status: &Status, current: Option<f32>) -> Result<()> {
+    let recovery = status.mode == ModeId::Recovery;
+
+    if now_us.wrapping_sub(self.last_heartbeat_us) >= HEARTBEAT_PERIOD_US {
+      self.last_heartbeat_us = now_us;
+      self.heartbeat_counter = self.heartbeat_counter.wrapping_add(1);
+      self.send(
+        Heartbeat {
+          mode: status.mode,
+          gate_enabled: status.gate_enabled,
+          recovery,
+          counter: self.heartbeat_counter,
+        }
+        .encode(self.node_id),
+      )?;
+    }
+
+    if self.publish_period_us > 0
+      && now_us.wrapping_sub(self.last_publish_us) >= self.publish_period_us
+    {
+      self.last_publish_us = now_us;
+      self.send(
+        MotionStatus {
+          position: status.position,
+          velocity: status.velocity,
+        }
+        .encode(self.node_id),
+      )?;
+      self.send(
+        DriveStatus {
+          current: current.unwrap_or(core::f32::NAN),
+          warnings: status.warnings,
+          faults: status.faults,
+        }
+        .encode(self.node_id),
+      )?;
+    }
+
+    Ok(())
+  }
+
+  fn send(&mut self, frame: bldc_protocol::Result<Frame>) -> Result<()> {
+    let frame = frame.map_err(|e| Error::new(e.message()))?;
+    self.bus.transmit(&frame)?;
+    Ok(())
+  }
+
+  pub fn return_hardware(self, system: &mut System, gpio_b: &mut GpioB) -> Result<()> {
+    self.bus.return_hardware(system, gpio_b)
+  }
+}
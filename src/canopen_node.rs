@@ -0,0 +1,387 @@
+use bldc_control::trajectory::{Goal, Limits, Shape, Trajectory};
+use bldc_protocol::{
+  can::Frame,
+  canopen::{
+    cia402::{controlword, statusword, OperationMode, PowerStateMachine, State},
+    cob_id, heartbeat,
+    od::{self, Objects},
+    sdo, split_cob_id, NmtCommand, NmtState, RPDO1, RPDO2, RPDO3, SDO_RX, SDO_TX, TPDO1, TPDO2,
+    TPDO3,
+  },
+  Status,
+};
+use stm32f303_api::{gpio::gpio_b::GpioB, Error, Result, System};
+
+use crate::{
+  can::{Bitrate, CanBus},
+  math::PI2,
+};
+
+const BITRATE: Bitrate = Bitrate::Kbps500;
+
+// Positions are exchanged in counts of the 14-bit encoder, velocities in
+// counts/s and accelerations in counts/s^2.
+const COUNTS_PER_RAD: f32 = 16384f32 / PI2;
+
+const DEFAULT_PROFILE_VELOCITY: u32 = 16384;
+const DEFAULT_PROFILE_ACCELERATION: u32 = 32768;
+const DEFAULT_QUICK_STOP_DECELERATION: u32 = 131072;
+
+const POSITION_WINDOW: f32 = 0.01;
+const VELOCITY_WINDOW: f32 = 0.1;
+
+fn to_counts(rads: f32) -> i32 {
+  (rads * COUNTS_PER_RAD) as i32
+}
+
+fn from_counts(counts: i32) -> f32 {
+  counts as f32 / COUNTS_PER_RAD
+}
+
+fn from_counts_unsigned(counts: u32) -> f32 {
+  counts as f32 / COUNTS_PER_RAD
+}
+
+// Profile moves ramp linearly, so the jerk limit goes unused.
+fn profile_limits(velocity: f32, acceleration: f32) -> Limits {
+  Limits {
+    velocity,
+    acceleration,
+    jerk: core::f32::INFINITY,
+  }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum DriveOutput {
+  // Gate driver off.
+  Disabled,
+  // Gate driver on with no torque applied.
+  Holding,
+  Position(f32),
+  Velocity(f32),
+}
+
+pub struct CanOpenNode {
+  bus: CanBus,
+  node_id: u8,
+  nmt_state: NmtState,
+  objects: Objects,
+  power: PowerStateMachine,
+  trajectory: Trajectory,
+  position_target: f32,
+  last_heartbeat_us: u64,
+  last_tpdo_us: [u64; 3],
+}
+impl CanOpenNode {
+  pub fn new(node_id: u8, system: &mut System, gpio_b: &mut GpioB) -> Result<Self> {
+    Ok(Self {
+      bus: CanBus::new(BITRATE, system, gpio_b)?,
+      node_id,
+      nmt_state: NmtState::Initializing,
+      objects: Objects::new(
+        DEFAULT_PROFILE_VELOCITY,
+        DEFAULT_PROFILE_ACCELERATION,
+        DEFAULT_QUICK_STOP_DECELERATION,
+      ),
+      power: PowerStateMachine::new(),
+      trajectory: Trajectory::new(
+        Shape::Trapezoidal,
+        profile_limits(
+          from_counts_unsigned(DEFAULT_PROFILE_VELOCITY),
+          from_counts_unsigned(DEFAULT_PROFILE_ACCELERATION),
+        ),
+      ),
+      position_target: 0f32,
+      last_heartbeat_us: 0,
+      last_tpdo_us: [0; 3],
+    })
+  }
+
+  pub fn start(&mut self) -> Result<()> {
+    self.bus.start()?;
+    self.boot()
+  }
+
+  pub fn get_node_id(&self) -> u8 {
+    self.node_id
+  }
+
+  pub fn get_state(&self) -> State {
+    self.power.state()
+  }
+
+  fn boot(&mut self) -> Result<()> {
+    self.send(heartbeat(self.node_id, NmtState::Initializing))?;
+    self.nmt_state = NmtState::PreOperational;
+    self.power = PowerStateMachine::new();
+    self.power.initialized();
+    Ok(())
+  }
+
+  fn send(&mut self, frame: bldc_protocol::Result<Frame>) -> Result<()> {
+    let frame = frame.map_err(|e| Error::new(e.message()))?;
+    self.bus.transmit(&frame)?;
+    Ok(())
+  }
+
+  fn receive(&mut self, motion_position: f32) -> Result<()> {
+    while let Some(frame) = self.bus.receive() {
+      if let Some(command) = NmtCommand::decode(self.node_id, &frame) {
+        match command.next_state() {
+          NmtState::Initializing => self.boot()?,
+          state => self.nmt_state = state,
+        }
+        continue;
+      }
+
+      let (function, node_id) = split_cob_id(frame.id);
+      if node_id != self.node_id || self.nmt_state == NmtState::Stopped {
+        continue;
+      }
+
+      match function {
+        SDO_RX => {
+          if let Some((response, written)) = sdo::handle(&mut self.objects, frame.payload()) {
+            if let Some(written) = written {
+              self.object_written(written.index, motion_position);
+            }
+            self.send(Frame::new(cob_id(SDO_TX, self.node_id), &response))?;
+          }
+        }
+        RPDO1 | RPDO2 | RPDO3 if self.nmt_state == NmtState::Operational => {
+          self.receive_pdo(function, frame.payload(), motion_position)
+        }
+        _ => {}
+      }
+    }
+
+    Ok(())
+  }
+
+  // Fixed mappings:
+  //   RPDO1: controlword u16, modes of operation i8
+  //   RPDO2: controlword u16, target position i32
+  //   RPDO3: controlword u16, target velocity i32
+  fn receive_pdo(&mut self, function: u16, payload: &[u8], motion_position: f32) {
+    let expected_len = match function {
+      RPDO1 => 3,
+      _ => 6,
+    };
+    if payload.len() != expected_len {
+      return;
+    }
+
+    match function {
+      RPDO1 => {
+        if let Some(mode) = OperationMode::from_i8(payload[2] as i8) {
+          self.objects.modes_of_operation = mode;
+        }
+      }
+      RPDO2 => {
+        self.objects.target_position =
+          i32::from_le_bytes([payload[2], payload[3], payload[4], payload[5]])
+      }
+      _ => {
+        self.objects.target_velocity =
+          i32::from_le_bytes([payload[2], payload[3], payload[4], payload[5]])
+      }
+    }
+
+    self.objects.controlword = u16::from_le_bytes([payload[0], payload[1]]);
+    self.object_written(od::CONTROLWORD, motion_position);
+  }
+
+  fn object_written(&mut self, index: u16, motion_position: f32) {
+    if index != od::CONTROLWORD {
+      return;
+    }
+
+    let was_enabled = self.power.state().operation_enabled();
+    self.power.apply_controlword(self.objects.controlword);
+
+    if !was_enabled && self.power.state().operation_enabled() {
+      self.trajectory.reset(motion_position, 0f32);
+      self.position_target = motion_position;
+    }
+  }
+
+  fn accept_new_setpoint(&mut self, controlword: u16) {
+    let target = from_counts(self.objects.target_position);
+    self.position_target = match controlword & controlword::RELATIVE {
+      0 => target,
+      _ => self.position_target + target,
+    };
+  }
+
+  // Processes received frames, advances the drive profile and returns what the
+  // controller should do this step. `fault` should be true while the controller
+  // is in recovery or a fault is stopping the motor.
+  pub fn process(&mut self, dt: f32, status: &Status, fault: bool) -> Result<DriveOutput> {
+    let previous_controlword = self.objects.controlword;
+    self.receive(status.position)?;
+    let controlword = self.objects.controlword;
+
+    if fault {
+      self.power.fault();
+      self.power.fault_reaction_complete();
+      self.objects.error_register |= 1;
+    } else if self.power.state() != State::Fault {
+      self.objects.error_register = 0;
+    }
+
+    let mode = self.objects.modes_of_operation;
+    let accel = from_counts_unsigned(self.objects.profile_acceleration);
+    let decel = from_counts_unsigned(self.objects.profile_deceleration);
+    let mut mode_specific = 0;
+
+    let output = match self.power.state() {
+      State::OperationEnabled if controlword & controlword::HALT != 0 => {
+        self
+          .trajectory
+          .set_limits(profile_limits(core::f32::INFINITY, decel));
+        self.trajectory.set_goal(Goal::Velocity(0f32));
+        DriveOutput::Velocity(self.trajectory.step(dt).velocity)
+      }
+      State::OperationEnabled => match mode {
+        OperationMode::ProfilePosition => {
+          let new_setpoint = controlword & controlword::NEW_SETPOINT != 0;
+          let rising = new_setpoint && previous_controlword & controlword::NEW_SETPOINT == 0;
+          let moving = self.trajectory.get_setpoint().velocity != 0f32;
+          if rising && (!moving || controlword & controlword::CHANGE_SET_IMMEDIATELY != 0) {
+            self.accept_new_setpoint(controlword);
+          }
+          if new_setpoint {
+            mode_specific |= statusword::OPERATION_MODE_SPECIFIC;
+          }
+
+          // The trajectory has a single acceleration limit, and plans its
+          // braking with it, so take the lower of the two to be sure of
+          // stopping at the target.
+          let max_velocity = from_counts_unsigned(self.objects.profile_velocity);
+          self
+            .trajectory
+            .set_limits(profile_limits(max_velocity, accel.min(decel)));
+          self
+            .trajectory
+            .set_goal(Goal::Position(self.position_target));
+          DriveOutput::Position(self.trajectory.step(dt).position)
+        }
+        OperationMode::ProfileVelocity => {
+          let target = from_counts(self.objects.target_velocity);
+          let velocity = self.trajectory.get_setpoint().velocity;
+          let speeding_up =
+            libm::fabsf(target) > libm::fabsf(velocity) && (target >= 0f32) == (velocity >= 0f32);
+          let acceleration = match speeding_up {
+            true => accel,
+            false => decel,
+          };
+          self
+            .trajectory
+            .set_limits(profile_limits(core::f32::INFINITY, acceleration));
+          self.trajectory.set_goal(Goal::Velocity(target));
+          let velocity = self.trajectory.step(dt).velocity;
+          if libm::fabsf(status.velocity) < VELOCITY_WINDOW {
+            mode_specific |= statusword::OPERATION_MODE_SPECIFIC;
+          }
+          DriveOutput::Velocity(velocity)
+        }
+        OperationMode::NoMode => {
+          self.trajectory.reset(status.position, 0f32);
+          DriveOutput::Holding
+        }
+      },
+      State::QuickStopActive => {
+        let quick_stop_decel = from_counts_unsigned(self.objects.quick_stop_deceleration);
+        self
+          .trajectory
+          .set_limits(profile_limits(core::f32::INFINITY, quick_stop_decel));
+        self.trajectory.set_goal(Goal::Velocity(0f32));
+        let velocity = self.trajectory.step(dt).velocity;
+        if velocity == 0f32 {
+          self.power.quick_stop_complete();
+        }
+        DriveOutput::Velocity(velocity)
+      }
+      State::SwitchedOn => DriveOutput::Holding,
+      _ => DriveOutput::Disabled,
+    };
+
+    let target_reached = match (self.power.state(), mode) {
+      (State::OperationEnabled, OperationMode::ProfilePosition) => {
+        libm::fabsf(self.position_target - status.position) < POSITION_WINDOW
+          && self.trajectory.get_setpoint().velocity == 0f32
+      }
+      (State::OperationEnabled, OperationMode::ProfileVelocity) => {
+        libm::fabsf(from_counts(self.objects.target_velocity) - status.velocity) < VELOCITY_WINDOW
+      }
+      _ => false,
+    };
+
+    let mut extra = statusword::REMOTE | mode_specific;
+    if target_reached {
+      extra |= statusword::TARGET_REACHED;
+    }
+    if status.warnings != 0 {
+      extra |= statusword::WARNING;
+    }
+
+    self.objects.statusword = self.power.statusword(extra);
+    self.objects.modes_of_operation_display = mode;
+    self.objects.position_actual = to_counts(status.position);
+    self.objects.velocity_actual = to_counts(status.velocity);
+    self.objects.error_code = match self.power.state() {
+      State::Fault => status.warnings,
+      _ => 0,
+    };
+
+    Ok(output)
+  }
+
+  // Fixed mappings:
+  //   TPDO1: statusword u16, modes of operation display i8
+  //   TPDO2: statusword u16, position actual value i32
+  //   TPDO3: statusword u16, velocity actual value i32
+  pub fn publish(&mut self, now_us: u64) -> Result<()> {
+    let heartbeat_us = self.objects.heartbeat_time_ms as u64 * 1000;
+    if heartbeat_us > 0 && now_us.wrapping_sub(self.last_heartbeat_us) >= heartbeat_us {
+      self.last_heartbeat_us = now_us;
+      self.send(heartbeat(self.node_id, self.nmt_state))?;
+    }
+
+    if self.nmt_state != NmtState::Operational {
+      return Ok(());
+    }
+
+    let status = self.objects.statusword.to_le_bytes();
+    for (i, function) in [TPDO1, TPDO2, TPDO3].iter().enumerate() {
+      let period_us = self.objects.tpdo_event_time_ms[i] as u64 * 1000;
+      if period_us == 0 || now_us.wrapping_sub(self.last_tpdo_us[i]) < period_us {
+        continue;
+      }
+      self.last_tpdo_us[i] = now_us;
+
+      let mut payload = [status[0], status[1], 0, 0, 0, 0];
+      let len = match *function {
+        TPDO1 => {
+          payload[2] = self.objects.modes_of_operation_display as i8 as u8;
+          3
+        }
+        TPDO2 => {
+          payload[2..].copy_from_slice(&self.objects.position_actual.to_le_bytes());
+          6
+        }
+        _ => {
+          payload[2..].copy_from_slice(&self.objects.velocity_actual.to_le_bytes());
+          6
+        }
+      };
+      self.send(Frame::new(cob_id(*function, self.node_id), &payload[..len]))?;
+    }
+
+    Ok(())
+  }
+
+  pub fn return_hardware(self, system: &mut System, gpio_b: &mut GpioB) -> Result<()> {
+    self.bus.return_hardware(system, gpio_b)
+  }
+}
@@ -0,0 +1,41 @@
+use cortex_m::peripheral::{DCB, DWT};
+
+// Measures time between control loop steps using the DWT cycle counter. The
+// counter wraps every few tens of seconds, so elapsed time is accumulated on
+// each tick rather than read directly.
+pub struct Clock {
+  cycles_per_us: u32,
+  last_cycles: u32,
+  leftover_cycles: u32,
+  elapsed_us: u64,
+}
+impl Clock {
+  pub fn new(core_freq: u32, dcb: &mut DCB, dwt: &mut DWT) -> Self {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+
+    Self {
+      cycles_per_us: core_freq / 1_000_000,
+      last_cycles: DWT::cycle_count(),
+      leftover_cycles: 0,
+      elapsed_us: 0,
+    }
+  }
+
+  // Returns the number of seconds since the previous tick.
+  pub fn tick(&mut self) -> f32 {
+    let now = DWT::cycle_count();
+    let cycles = now.wrapping_sub(self.last_cycles);
+    self.last_cycles = now;
+
+    let total = cycles as u64 + self.leftover_cycles as u64;
+    self.elapsed_us += total / self.cycles_per_us as u64;
+    self.leftover_cycles = (total % self.cycles_per_us as u64) as u32;
+
+    cycles as f32 / (self.cycles_per_us as f32 * 1_000_000f32)
+  }
+
+  pub fn micros(&self) -> u64 {
+    self.elapsed_us
+  }
+}
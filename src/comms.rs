@@ -0,0 +1,77 @@
+use bldc_protocol::{
+  frame::{self, Decoder, MAX_FRAME_LEN},
+  ErrorCode, Message, Request, Response,
+};
+use stm32f303_api::{gpio::gpio_a::GpioA, Error, Result, System};
+
+use crate::serial::SerialPort;
+
+const BAUD_RATE: u32 = 115200;
+
+pub struct Comms {
+  serial: SerialPort,
+  decoder: Decoder,
+  telemetry_divider: u16,
+  steps_since_telemetry: u16,
+}
+impl Comms {
+  pub fn new(system: &mut System, gpio_a: &mut GpioA) -> Result<Self> {
+    Ok(Self {
+      serial: SerialPort::new(BAUD_RATE, system, gpio_a)?,
+      decoder: Decoder::new(),
+      telemetry_divider: 0,
+      steps_since_telemetry: 0,
+    })
+  }
+
+  pub fn start(&mut self) {
+    self.serial.start();
+  }
+
+  // Returns the next complete request, if one has arrived. Malformed frames are
+  // answered with an error response and skipped.
+  pub fn poll(&mut self) -> Result<Option<Request>> {
+    while let Some(byte) = self.serial.read_byte() {
+      let request = match self.decoder.push(byte) {
+        None => continue,
+        Some(payload) => payload.and_then(Request::decode),
+      };
+
+      match request {
+        Ok(request) => return Ok(Some(request)),
+        Err(_) => self.send(&Response::Error(ErrorCode::Malformed))?,
+      }
+    }
+
+    Ok(None)
+  }
+
+  pub fn send(&mut self, response: &Response) -> Result<()> {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = frame::encode_message(response, &mut buf).map_err(|e| Error::new(e.message()))?;
+    self.serial.write_bytes(&buf[..len])
+  }
+
+  pub fn set_telemetry_divider(&mut self, divider: u16) {
+    self.telemetry_divider = divider;
+    self.steps_since_telemetry = 0;
+  }
+
+  pub fn telemetry_due(&mut self) -> bool {
+    if self.telemetry_divider == 0 {
+      return false;
+    }
+
+    self.steps_since_telemetry += 1;
+    if self.steps_since_telemetry >= self.telemetry_divider {
+      self.steps_since_telemetry = 0;
+      return true;
+    }
+
+    false
+  }
+
+  pub fn return_hardware(self, system: &mut System, gpio_a: &mut GpioA) -> Result<()> {
+    self.serial.return_hardware(system, gpio_a)
+  }
+}
@@ -0,0 +1,91 @@
+use stm32f303_api::{
+  gpio::gpio_a::{GpioA, Pa0Analog, Pa1Analog, Pa2Analog},
+  Error, Result,
+};
+
+use crate::adc::{to_volts, Adc, AdcUnit, InjectedTrigger};
+
+// DRV8305 current sense amplifier outputs SO1 to SO3, on ADC1 channels 1 to 3.
+const CHANNELS: [u8; 3] = [1, 2, 3];
+const OFFSET_SAMPLES: u32 = 256;
+const MAX_OFFSET_POLLS: u32 = 1_000_000;
+
+// Low-side shunt current measurement, converted at the middle of the
+// low-side on-time by the TIM1 trigger (see magnet_controller.rs).
+pub struct CurrentSense {
+  adc: Adc,
+  // Amps per volt at the amplifier output.
+  scale: f32,
+  offsets: [f32; 3],
+  currents: [f32; 3],
+  so1: Pa0Analog,
+  so2: Pa1Analog,
+  so3: Pa2Analog,
+}
+impl CurrentSense {
+  // `amp_gain` is negative when the amplifier output falls as current flows
+  // into the motor.
+  pub fn new(shunt: f32, amp_gain: f32, gpio_a: &mut GpioA) -> Result<Self> {
+    if shunt <= 0f32 || amp_gain == 0f32 {
+      return Err(Error::new("Current sense shunt and gain must be non-zero"));
+    }
+
+    let mut adc = Adc::new(AdcUnit::Adc1)?;
+    adc.start_injected(&CHANNELS, InjectedTrigger::Tim1Trgo)?;
+
+    Ok(Self {
+      adc,
+      scale: 1f32 / (shunt * amp_gain),
+      offsets: [0f32; 3],
+      currents: [0f32; 3],
+      so1: gpio_a.take_pa0()?.as_analog(),
+      so2: gpio_a.take_pa1()?.as_analog(),
+      so3: gpio_a.take_pa2()?.as_analog(),
+    })
+  }
+
+  pub fn set_scale(&mut self, shunt: f32, amp_gain: f32) {
+    self.scale = 1f32 / (shunt * amp_gain);
+  }
+
+  // Averages the amplifier outputs with no current flowing. Needs the PWM
+  // running, to trigger conversions, and the low sides all on at zero power.
+  pub fn calibrate_offsets(&mut self) -> Result<()> {
+    let mut sums = [0f32; 3];
+    let mut samples = 0;
+
+    for _ in 0..MAX_OFFSET_POLLS {
+      if self.adc.take_injected_complete() {
+        for (i, sum) in sums.iter_mut().enumerate() {
+          *sum += to_volts(self.adc.read_injected(i));
+        }
+        samples += 1;
+        if samples == OFFSET_SAMPLES {
+          for (offset, sum) in self.offsets.iter_mut().zip(sums.iter()) {
+            *offset = sum / OFFSET_SAMPLES as f32;
+          }
+          return Ok(());
+        }
+      }
+    }
+
+    Err(Error::new("No current samples while calibrating offsets"))
+  }
+
+  // Phase currents (A) into the motor from the latest conversion.
+  pub fn read(&mut self) -> [f32; 3] {
+    if self.adc.take_injected_complete() {
+      for (i, current) in self.currents.iter_mut().enumerate() {
+        *current = (to_volts(self.adc.read_injected(i)) - self.offsets[i]) * self.scale;
+      }
+    }
+    self.currents
+  }
+
+  pub fn return_hardware(self, gpio_a: &mut GpioA) -> Result<()> {
+    gpio_a.return_pa0(self.so1.teardown())?;
+    gpio_a.return_pa1(self.so2.teardown())?;
+    gpio_a.return_pa2(self.so3.teardown())?;
+    Ok(())
+  }
+}
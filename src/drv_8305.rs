@@ -1,3 +1,4 @@
+use core::time::Duration;
 use stm32f303_api::{
   gpio::gpio_b::Pb11Output,
   gpio::gpio_b::{
@@ -22,6 +23,7 @@ pub struct Drv8305 {
   miso: Pb14AltFunc<Pb14Spi2MisoI2s2extSd>,
   mosi: Pb15AltFunc<Pb15Spi2MosiI2s2Sd>,
   last_command: Command,
+  gate_enabled: bool,
 }
 impl Drv8305 {
   pub fn new(system: &mut System, gpio_b: &mut GpioB) -> Result<Self> {
@@ -62,6 +64,7 @@ impl Drv8305 {
         OutputSpeed::High,
       ),
       last_command: Command::Nop,
+      gate_enabled: false,
     })
   }
 
@@ -72,10 +75,16 @@ impl Drv8305 {
 
   pub fn enable_gate(&mut self) {
     self.en_gate.write(DigitalValue::High);
+    self.gate_enabled = true;
   }
 
   pub fn disable_gate(&mut self) {
     self.en_gate.write(DigitalValue::Low);
+    self.gate_enabled = false;
+  }
+
+  pub fn is_gate_enabled(&self) -> bool {
+    self.gate_enabled
   }
 
   pub fn stop(&mut self) -> Result<()> {
@@ -102,6 +111,26 @@ impl Drv8305 {
     GateDriverFaults::decode(self.read(ReadCommand::GateDriverFaults)?)
   }
 
+  // The dead time the DRV8305 itself inserts between high and low side
+  // switching, from the gate drive control register.
+  pub fn read_dead_time(&mut self) -> Result<Duration> {
+    let data = self.read(ReadCommand::GateDriveControl)?;
+    if data == core::u16::MAX {
+      return Err(Error::new("Drv8305 offline"));
+    }
+
+    Ok(Duration::from_nanos(match (data >> 4) & 0b111 {
+      0b000 => 35,
+      0b001 => 52,
+      0b010 => 88,
+      0b011 => 440,
+      0b100 => 880,
+      0b101 => 1760,
+      0b110 => 3520,
+      _ => 5280,
+    }))
+  }
+
   pub fn read(&mut self, read_command: ReadCommand) -> Result<u16> {
     let command_previously_sent = match self.last_command {
       Command::Nop => false,
@@ -160,6 +189,7 @@ pub enum ReadCommand {
   OvercurrentFaults = 0b10010 << 11,
   IcFaults = 0b10011 << 11,
   GateDriverFaults = 0b0100 << 11,
+  GateDriveControl = 0b10111 << 11,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -197,6 +227,28 @@ impl Warnings {
   pub fn has(&self, flag: WarningFlag) -> bool {
     (self.data & (flag as u16)) > 0
   }
+
+  // Whether any flags other than the temperature warnings below shutdown are
+  // set.
+  pub fn has_faults(&self) -> bool {
+    let temperature_warnings = WarningFlag::TempOver135C as u16
+      | WarningFlag::TempOver125C as u16
+      | WarningFlag::TempOver105C as u16;
+    self.data & !temperature_warnings != 0
+  }
+
+  // The highest temperature (C) the warning flags say the driver is over.
+  pub fn min_temperature(&self) -> Option<f32> {
+    if self.has(WarningFlag::TempOver135C) {
+      Some(135f32)
+    } else if self.has(WarningFlag::TempOver125C) {
+      Some(125f32)
+    } else if self.has(WarningFlag::TempOver105C) {
+      Some(105f32)
+    } else {
+      None
+    }
+  }
 }
 
 #[repr(u16)]
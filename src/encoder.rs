@@ -0,0 +1,205 @@
+use core::fmt::Write;
+use core::ptr::{read_volatile, write_volatile};
+use stm32f303_api::{
+  gpio::{
+    gpio_d::{GpioD, Pd12AltFunc, Pd12Tim4Ch1, Pd13AltFunc, Pd13Tim4Ch2, Pd14AltFunc, Pd14Tim4Ch3},
+    OutputSpeed, OutputType, PullDirection,
+  },
+  timer::tim4::Tim4,
+  Error, Result, System,
+};
+
+use crate::{
+  angle_sensor::AngleSensor,
+  math::{norm_rads, PI2},
+};
+
+// TIM4 registers (RM0316 section 21.4). The timer API has no encoder mode, so
+// the timer is configured directly once activated.
+const TIM4_CR1: *mut u32 = 0x4000_0800 as *mut u32;
+const TIM4_SMCR: *mut u32 = 0x4000_0808 as *mut u32;
+const TIM4_SR: *mut u32 = 0x4000_0810 as *mut u32;
+const TIM4_CCMR1: *mut u32 = 0x4000_0818 as *mut u32;
+const TIM4_CCMR2: *mut u32 = 0x4000_081C as *mut u32;
+const TIM4_CCER: *mut u32 = 0x4000_0820 as *mut u32;
+const TIM4_CNT: *mut u32 = 0x4000_0824 as *mut u32;
+const TIM4_ARR: *mut u32 = 0x4000_082C as *mut u32;
+const TIM4_CCR3: *mut u32 = 0x4000_083C as *mut u32;
+
+const CR1_CEN: u32 = 1 << 0;
+// Encoder mode 3, counting on both edges of both inputs.
+const SMCR_SMS_ENCODER_3: u32 = 0b011;
+const SR_CC3IF: u32 = 1 << 3;
+// CCxS = 01 (input on its own TIx), ICxF = 0011 (8 samples at the timer
+// clock) for channels 1 and 2, and the same for channel 3 in CCMR2.
+const CCMR1_ENCODER_INPUTS: u32 = 0b0011_0001 | 0b0011_0001 << 8;
+const CCMR2_INDEX_INPUT: u32 = 0b0011_0001;
+const CCER_CC3E: u32 = 1 << 8;
+
+// Counts either side of a whole revolution within which a repeated index is
+// accepted as correct; outside it counts have been lost and are resynced.
+const INDEX_TOLERANCE: i64 = 2;
+
+fn read_register(register: *mut u32) -> u32 {
+  unsafe { read_volatile(register) }
+}
+
+fn write_register(register: *mut u32, value: u32) {
+  unsafe { write_volatile(register, value) }
+}
+
+// ABI incremental encoder on PD12 (A, TIM4 CH1), PD13 (B, TIM4 CH2) and PD14
+// (index, TIM4 CH3). The index captures the counter in hardware so it is not
+// missed between control loop steps. The 16-bit counter is extended into a
+// 64-bit count on each read, so it can turn indefinitely in either direction
+// as long as it is read at least once per 32768 counts.
+pub struct QuadratureEncoder {
+  num_magnet_pairs: u32,
+  rads_per_magnet_pair: f32,
+  counts_per_rev: i64,
+  offset: f32,
+  last_counter: u16,
+  count: i64,
+  index_found: bool,
+  index_resyncs: u32,
+  timer: Tim4,
+  a: Pd12AltFunc<Pd12Tim4Ch1>,
+  b: Pd13AltFunc<Pd13Tim4Ch2>,
+  index: Pd14AltFunc<Pd14Tim4Ch3>,
+}
+impl QuadratureEncoder {
+  pub fn new(
+    num_magnet_pairs: u32,
+    counts_per_rev: u32,
+    system: &mut System,
+    gpio_d: &mut GpioD,
+  ) -> Result<Self> {
+    if counts_per_rev == 0 {
+      return Err(Error::new("Encoder counts per revolution must be positive"));
+    }
+
+    let timer = system.activate_tim4()?;
+    write_register(TIM4_CR1, 0);
+    write_register(TIM4_SMCR, SMCR_SMS_ENCODER_3);
+    write_register(TIM4_CCMR1, CCMR1_ENCODER_INPUTS);
+    write_register(TIM4_CCMR2, CCMR2_INDEX_INPUT);
+    write_register(TIM4_CCER, CCER_CC3E);
+    write_register(TIM4_ARR, 0xFFFF);
+    write_register(TIM4_CNT, 0);
+    write_register(TIM4_SR, 0);
+
+    Ok(Self {
+      num_magnet_pairs,
+      rads_per_magnet_pair: PI2 / num_magnet_pairs as f32,
+      counts_per_rev: counts_per_rev as i64,
+      offset: 0f32,
+      last_counter: 0,
+      count: 0,
+      index_found: false,
+      index_resyncs: 0,
+      timer,
+      a: gpio_d.take_pd12()?.as_alt_func(
+        PullDirection::Up,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+      b: gpio_d.take_pd13()?.as_alt_func(
+        PullDirection::Up,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+      index: gpio_d.take_pd14()?.as_alt_func(
+        PullDirection::Down,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+    })
+  }
+
+  pub fn start(&mut self) {
+    self.last_counter = read_register(TIM4_CNT) as u16;
+    write_register(TIM4_CR1, CR1_CEN);
+  }
+
+  pub fn stop(&mut self) {
+    write_register(TIM4_CR1, 0);
+  }
+
+  pub fn is_index_found(&self) -> bool {
+    self.index_found
+  }
+
+  // Number of times the index arrived away from a whole revolution, meaning
+  // counts were lost.
+  pub fn get_index_resyncs(&self) -> u32 {
+    self.index_resyncs
+  }
+
+  // Multi-turn count, relative to the index once it has been seen.
+  pub fn read_count(&mut self) -> i64 {
+    let counter = read_register(TIM4_CNT) as u16;
+    self.count += counter.wrapping_sub(self.last_counter) as i16 as i64;
+    self.last_counter = counter;
+
+    if read_register(TIM4_SR) & SR_CC3IF != 0 {
+      // Reading the capture clears the flag.
+      let captured = read_register(TIM4_CCR3) as u16;
+      let index_count = self.count - counter.wrapping_sub(captured) as i16 as i64;
+      self.handle_index(index_count);
+    }
+
+    self.count
+  }
+
+  fn handle_index(&mut self, index_count: i64) {
+    let error = index_count.rem_euclid(self.counts_per_rev);
+    let error = match error > self.counts_per_rev / 2 {
+      true => error - self.counts_per_rev,
+      false => error,
+    };
+
+    if !self.index_found {
+      // Count from the index from now on, moving the calibrated offset with
+      // it so the angle stays on the same rotor position.
+      self.index_found = true;
+      self.count -= index_count;
+      self.offset = norm_rads(self.offset - self.counts_to_rads(index_count));
+      println!("Encoder index found").ok();
+    } else if error.abs() > INDEX_TOLERANCE {
+      self.index_resyncs += 1;
+      self.count -= error;
+      println!("Encoder index off by {} counts", error).ok();
+    }
+  }
+
+  fn counts_to_rads(&self, counts: i64) -> f32 {
+    counts.rem_euclid(self.counts_per_rev) as f32 / self.counts_per_rev as f32 * PI2
+  }
+
+  pub fn return_hardware(mut self, system: &mut System, gpio_d: &mut GpioD) -> Result<()> {
+    self.stop();
+    system.deactivate_tim4(self.timer)?;
+    gpio_d.return_pd12(self.a.teardown())?;
+    gpio_d.return_pd13(self.b.teardown())?;
+    gpio_d.return_pd14(self.index.teardown())?;
+    Ok(())
+  }
+}
+impl AngleSensor for QuadratureEncoder {
+  fn read_absolute_angle(&mut self) -> Result<f32> {
+    let count = self.read_count();
+    Ok(norm_rads(self.counts_to_rads(count) - self.offset))
+  }
+
+  fn absolute_to_phase_angle(&self, absolute_angle: f32) -> f32 {
+    (absolute_angle % self.rads_per_magnet_pair) * self.num_magnet_pairs as f32
+  }
+
+  fn set_offset(&mut self, offset: f32) {
+    self.offset = offset;
+  }
+
+  fn get_offset(&self) -> f32 {
+    self.offset
+  }
+}
@@ -0,0 +1,246 @@
+use core::fmt::Write;
+use cortex_m::peripheral::DWT;
+use stm32f303_api::{
+  gpio::{
+    gpio_c::{GpioC, Pc6Input, Pc7Input, Pc8Input},
+    DigitalValue, PullDirection,
+  },
+  Error, Result,
+};
+
+use crate::{
+  angle_sensor::AngleSensor,
+  math::{norm_rads, wrap_rads, PI1_3, PI2},
+};
+
+// Hall states 1 to 6; 0 and 7 only occur with a faulty or disconnected
+// sensor.
+pub const NUM_HALL_STATES: usize = 6;
+
+#[derive(Copy, Clone)]
+struct LearnSum {
+  cos: f32,
+  sin: f32,
+  count: u32,
+}
+
+// Three hall sensors on PC6, PC7 and PC8 (TIM3 CH1 to CH3), polled each
+// control loop and timestamped with the DWT cycle counter. Between edges the
+// electrical angle is extrapolated from the speed measured over the last
+// sector, limited to one sector.
+pub struct HallSensor {
+  num_magnet_pairs: u32,
+  rads_per_magnet_pair: f32,
+  offset: f32,
+  // Electrical angle in the middle of each state, indexed by state - 1.
+  table: [f32; NUM_HALL_STATES],
+  cycles_per_second: f32,
+  timeout_cycles: u32,
+  // Zero until the first valid state has been read.
+  state: u8,
+  edge_angle: Option<f32>,
+  edge_cycles: u32,
+  electrical_velocity: f32,
+  phase_angle: f32,
+  electrical_turns: u32,
+  learning: Option<[LearnSum; NUM_HALL_STATES]>,
+  invalid_state_seen: bool,
+  h1: Pc6Input,
+  h2: Pc7Input,
+  h3: Pc8Input,
+}
+impl HallSensor {
+  pub fn new(
+    num_magnet_pairs: u32,
+    table: [f32; NUM_HALL_STATES],
+    timeout: f32,
+    core_freq: u32,
+    gpio_c: &mut GpioC,
+  ) -> Result<Self> {
+    Ok(Self {
+      num_magnet_pairs,
+      rads_per_magnet_pair: PI2 / num_magnet_pairs as f32,
+      offset: 0f32,
+      table,
+      cycles_per_second: core_freq as f32,
+      timeout_cycles: (timeout * core_freq as f32) as u32,
+      state: 0,
+      edge_angle: None,
+      edge_cycles: 0,
+      electrical_velocity: 0f32,
+      phase_angle: 0f32,
+      electrical_turns: 0,
+      learning: None,
+      invalid_state_seen: false,
+      h1: gpio_c.take_pc6()?.as_input(PullDirection::Up),
+      h2: gpio_c.take_pc7()?.as_input(PullDirection::Up),
+      h3: gpio_c.take_pc8()?.as_input(PullDirection::Up),
+    })
+  }
+
+  pub fn get_table(&self) -> [f32; NUM_HALL_STATES] {
+    self.table
+  }
+
+  pub fn set_timeout(&mut self, timeout: f32) {
+    self.timeout_cycles = (timeout * self.cycles_per_second) as u32;
+  }
+
+  fn read_state(&self) -> u8 {
+    let bit = |value: DigitalValue| match value {
+      DigitalValue::High => 1u8,
+      DigitalValue::Low => 0u8,
+    };
+    bit(self.h1.read()) | bit(self.h2.read()) << 1 | bit(self.h3.read()) << 2
+  }
+
+  fn read_valid_state(&self) -> Result<u8> {
+    match self.read_state() {
+      0 | 7 => Err(Error::new("Invalid hall state")),
+      state => Ok(state),
+    }
+  }
+
+  // Updates the edge timing and returns the electrical angle.
+  fn update(&mut self) -> Result<f32> {
+    let now = DWT::cycle_count();
+    let state = self.read_valid_state()?;
+
+    if self.state == 0 {
+      self.state = state;
+      self.phase_angle = self.table[state as usize - 1];
+    } else if state != self.state {
+      let from = self.table[self.state as usize - 1];
+      let to = self.table[state as usize - 1];
+      let edge_angle = norm_rads(from + wrap_rads(to - from) / 2f32);
+      let elapsed = now.wrapping_sub(self.edge_cycles);
+
+      // Reversing direction crosses the same edge twice, giving zero.
+      self.electrical_velocity = match self.edge_angle {
+        Some(last_edge_angle) if elapsed < self.timeout_cycles => {
+          wrap_rads(edge_angle - last_edge_angle) * self.cycles_per_second / elapsed as f32
+        }
+        _ => 0f32,
+      };
+
+      self.state = state;
+      self.edge_angle = Some(edge_angle);
+      self.edge_cycles = now;
+    }
+
+    let elapsed = now.wrapping_sub(self.edge_cycles);
+    if elapsed >= self.timeout_cycles {
+      self.electrical_velocity = 0f32;
+    }
+
+    let angle = match (self.edge_angle, self.electrical_velocity == 0f32) {
+      (Some(edge_angle), false) => {
+        let travel = self.electrical_velocity * elapsed as f32 / self.cycles_per_second;
+        norm_rads(edge_angle + travel.max(-PI1_3).min(PI1_3))
+      }
+      _ => self.table[state as usize - 1],
+    };
+
+    // Count electrical turns so the mechanical angle covers a full turn.
+    let delta = wrap_rads(angle - self.phase_angle);
+    if self.phase_angle + delta >= PI2 {
+      self.electrical_turns = (self.electrical_turns + 1) % self.num_magnet_pairs;
+    } else if self.phase_angle + delta < 0f32 {
+      self.electrical_turns =
+        (self.electrical_turns + self.num_magnet_pairs - 1) % self.num_magnet_pairs;
+    }
+    self.phase_angle = angle;
+
+    Ok(angle)
+  }
+
+  pub fn return_hardware(self, gpio_c: &mut GpioC) -> Result<()> {
+    gpio_c.return_pc6(self.h1.teardown())?;
+    gpio_c.return_pc7(self.h2.teardown())?;
+    gpio_c.return_pc8(self.h3.teardown())?;
+    Ok(())
+  }
+}
+impl AngleSensor for HallSensor {
+  fn read_absolute_angle(&mut self) -> Result<f32> {
+    let phase_angle = self.update()?;
+    let angle = (self.electrical_turns as f32 * PI2 + phase_angle) / self.num_magnet_pairs as f32;
+    Ok(norm_rads(angle - self.offset))
+  }
+
+  fn absolute_to_phase_angle(&self, absolute_angle: f32) -> f32 {
+    (absolute_angle % self.rads_per_magnet_pair) * self.num_magnet_pairs as f32
+  }
+
+  fn set_offset(&mut self, offset: f32) {
+    self.offset = offset;
+  }
+
+  fn get_offset(&self) -> f32 {
+    self.offset
+  }
+
+  fn read_velocity(&self) -> Option<f32> {
+    Some(self.electrical_velocity / self.num_magnet_pairs as f32)
+  }
+
+  fn begin_learning(&mut self) {
+    self.learning = Some(
+      [LearnSum {
+        cos: 0f32,
+        sin: 0f32,
+        count: 0,
+      }; NUM_HALL_STATES],
+    );
+    self.invalid_state_seen = false;
+  }
+
+  // The field holds the rotor at the commanded phase angle, so averaging it
+  // over the time each state is seen gives the middle of that state.
+  fn learn(&mut self, phase_angle: f32) -> Result<()> {
+    let state = self.read_state();
+    if let Some(sums) = &mut self.learning {
+      match state {
+        0 | 7 => self.invalid_state_seen = true,
+        _ => {
+          let sum = &mut sums[state as usize - 1];
+          sum.cos += libm::cosf(phase_angle);
+          sum.sin += libm::sinf(phase_angle);
+          sum.count += 1;
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  fn finish_learning(&mut self) -> Result<()> {
+    let sums = match self.learning.take() {
+      Some(sums) => sums,
+      None => return Err(Error::new("Hall table learning not started")),
+    };
+
+    if self.invalid_state_seen {
+      return Err(Error::new("Invalid hall state seen while learning"));
+    }
+    if sums.iter().any(|sum| sum.count == 0) {
+      return Err(Error::new("Not every hall state seen while learning"));
+    }
+
+    for (angle, sum) in self.table.iter_mut().zip(sums.iter()) {
+      *angle = norm_rads(libm::atan2f(sum.sin, sum.cos));
+    }
+
+    // The table is now relative to the phase angle itself.
+    self.offset = 0f32;
+    self.state = 0;
+    self.edge_angle = None;
+    self.electrical_velocity = 0f32;
+
+    for (i, angle) in self.table.iter().enumerate() {
+      println!("Hall state {} at {} radians", i + 1, angle).ok();
+    }
+
+    Ok(())
+  }
+}
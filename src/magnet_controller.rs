@@ -1,4 +1,5 @@
 use core::fmt::Write;
+use core::ptr::{read_volatile, write_volatile};
 use core::time::Duration;
 use stm32f303_api::{
   gpio::gpio_e::{
@@ -11,25 +12,270 @@ use stm32f303_api::{
   timer::tim1::Ch1CompareMode,
   timer::OutputChannel,
   timer::{
-    tim1::{Ch1Output, Ch2CompareMode, Ch2Output, Ch3CompareMode, Ch3Output, Tim1},
+    tim1::{
+      Ch1Output, Ch2CompareMode, Ch2Output, Ch3CompareMode, Ch3Output, Ch4CompareMode, Ch4Output,
+      Tim1,
+    },
     Timer,
   },
-  Result, System,
+  Error, Result, System,
 };
 
-use crate::math::norm_rads;
+use crate::math::{norm_rads, PI1_2, PI1_3};
 
 const PI: f32 = 3.14159;
 const PI2: f32 = PI * 2f32;
 const PI2_3: f32 = PI2 / 3f32;
 const PI4_3: f32 = PI2_3 * 2f32;
 
+// Floor on the bus voltage, so a dead supply cannot divide by zero.
+const MIN_BUS_VOLTAGE: f32 = 1f32;
+
+// APB2 (HSI / 2 * 16 / 4) doubled for the timers, since APB2 is prescaled.
+const TIMER_CLOCK: f32 = 32_000_000f32;
+const MAX_ARR: u32 = 65535;
+
+// TIM1 registers (RM0316 section 20.4) for the settings the timer API only
+// provides edge-aligned defaults for.
+const TIM1_CR1: *mut u32 = 0x4001_2C00 as *mut u32;
+const TIM1_CR2: *mut u32 = 0x4001_2C04 as *mut u32;
+const TIM1_CCER: *mut u32 = 0x4001_2C20 as *mut u32;
+const TIM1_BDTR: *mut u32 = 0x4001_2C44 as *mut u32;
+
+const CR1_CMS_MASK: u32 = 0b11 << 5;
+const CR1_CMS_CENTER_1: u32 = 0b01 << 5;
+const CR2_MMS_MASK: u32 = 0b111 << 4;
+const CR2_MMS_OC4REF: u32 = 0b111 << 4;
+const BDTR_MOE: u32 = 1 << 15;
+// CCxE and CCxNE for channels 1 to 3; channel x uses bits 4(x-1) and 4(x-1)+2.
+const CCER_PHASES_MASK: u32 = 0b0101_0101_0101;
+const CCER_HIGH: u32 = 0b0001;
+const CCER_LOW: u32 = 0b0100;
+
+// Fraction of the commanded phase current amplitude over which dead-time
+// compensation ramps through zero, to avoid chattering at the crossing.
+const COMMANDED_CURRENT_BAND: f32 = 0.1;
+
+// Requested PWM settings, as configured by parameters.
+#[derive(Copy, Clone)]
+pub struct PwmConfig {
+  pub frequency: f32,
+  pub deadtime: Duration,
+  // How far before the middle of the low-side on-time the ADC is triggered,
+  // to allow for the sample-and-hold time.
+  pub sample_advance: Duration,
+  // Low-side on-time that must always remain for the current measurement.
+  pub min_low_side: Duration,
+}
+
+// PWM settings as achieved by the hardware.
+#[derive(Copy, Clone)]
+pub struct PwmTiming {
+  pub frequency: f32,
+  pub deadtime: Duration,
+  pub sample_advance: Duration,
+  pub max_duty: f32,
+}
+impl PwmTiming {
+  fn period_ns(&self) -> f32 {
+    1_000_000_000f32 / self.frequency
+  }
+
+  // Average phase voltage lost to the deadtime, as a duty cycle. Of the two
+  // edges in each period, only the one that turns on the switch opposing the
+  // freewheeling diode loses voltage, so the loss is one deadtime per period.
+  fn deadtime_duty(&self) -> f32 {
+    self.deadtime.as_nanos() as f32 / self.period_ns()
+  }
+
+  // CH4 duty that puts the OC4REF rising edge, which drives TRGO, the sample
+  // advance before the counter peak. With center-aligned counting the low
+  // sides are all on around the peak, so that is the middle of their on-time.
+  fn trigger_duty(&self) -> f32 {
+    1f32 - self.sample_advance.as_nanos() as f32 * 2f32 / self.period_ns()
+  }
+}
+
+// Finds the closest frequency the timer can generate when counting up and
+// down, which takes two counts of the auto-reload value per period.
+fn achievable_frequency(frequency: f32) -> Result<f32> {
+  if frequency.is_nan() || frequency <= 0f32 {
+    return Err(Error::new("PWM frequency must be positive"));
+  }
+
+  let counts = libm::roundf(TIMER_CLOCK / (2f32 * frequency)) as u32;
+  let prescaler = counts.saturating_sub(1) / MAX_ARR + 1;
+  let period = libm::roundf(TIMER_CLOCK / (prescaler as f32 * 2f32 * frequency)) as u32;
+  if period < 2 || period > MAX_ARR {
+    return Err(Error::new("PWM frequency out of timer range"));
+  }
+
+  Ok(TIMER_CLOCK / (2 * prescaler * period) as f32)
+}
+
+// Rounds the deadtime up to the next value the break and dead-time register
+// can encode (RM0316 DTG[7:0]), in ticks of the undivided timer clock.
+fn achievable_deadtime(deadtime: Duration) -> Result<Duration> {
+  let tick_ns = 1_000_000_000f32 / TIMER_CLOCK;
+  let ticks = libm::ceilf(deadtime.as_nanos() as f32 / tick_ns) as u32;
+
+  let ticks = match ticks {
+    0..=127 => ticks,
+    128..=254 => (ticks + 1) / 2 * 2,
+    255..=504 => (ticks + 7) / 8 * 8,
+    505..=1008 => (ticks + 15) / 16 * 16,
+    _ => return Err(Error::new("Deadtime too long for timer")),
+  };
+
+  Ok(Duration::from_nanos(
+    libm::roundf(ticks as f32 * tick_ns) as u64
+  ))
+}
+
+// Resolves a requested timing to what the hardware will actually produce,
+// rejecting deadtimes shorter than the gate driver's own (which would then be
+// the effective deadtime) and settings that leave too little on-time.
+pub fn resolve_pwm_timing(config: &PwmConfig, min_deadtime: Duration) -> Result<PwmTiming> {
+  let frequency = achievable_frequency(config.frequency)?;
+  let deadtime = achievable_deadtime(config.deadtime)?;
+
+  if deadtime < min_deadtime {
+    return Err(Error::new(
+      "Deadtime shorter than the gate driver dead time",
+    ));
+  }
+
+  let period_ns = 1_000_000_000f32 / frequency;
+  if deadtime.as_nanos() as f32 * 4f32 > period_ns {
+    return Err(Error::new("Deadtime too long for PWM period"));
+  }
+
+  // The low-side window is centred on the counter peak, so it must extend the
+  // sample advance either side of the trigger as well as covering the
+  // deadtime and minimum window.
+  let low_side_ns = deadtime.as_nanos() as f32
+    + config.min_low_side.as_nanos() as f32
+    + config.sample_advance.as_nanos() as f32 * 2f32;
+  let max_duty = 1f32 - low_side_ns / period_ns;
+  if max_duty < 0.5 {
+    return Err(Error::new(
+      "Current sampling window too long for PWM period",
+    ));
+  }
+
+  Ok(PwmTiming {
+    frequency,
+    deadtime,
+    sample_advance: config.sample_advance,
+    max_duty,
+  })
+}
+
+// Sign of `value`, ramping linearly within `band` of zero.
+fn soft_sign(value: f32, band: f32) -> f32 {
+  match band > 0f32 {
+    true => (value / band).max(-1f32).min(1f32),
+    false => match value < 0f32 {
+      true => -1f32,
+      false => 1f32,
+    },
+  }
+}
+
+// Enables the high (CCxE) and low (CCxN) side outputs of each phase; a
+// disabled output is released to its pull-down, turning its switch off.
+fn set_phase_outputs(outputs: [u32; 3]) {
+  let bits = outputs[0] | outputs[1] << 4 | outputs[2] << 8;
+  unsafe {
+    let ccer = read_volatile(TIM1_CCER);
+    write_volatile(TIM1_CCER, (ccer & !CCER_PHASES_MASK) | bits);
+  }
+}
+
+// With the main output disabled (and OSSI clear) every output is released to
+// its pull-down, turning all six switches off.
+fn set_main_output(enabled: bool) {
+  unsafe {
+    let bdtr = read_volatile(TIM1_BDTR);
+    let bdtr = match enabled {
+      true => bdtr | BDTR_MOE,
+      false => bdtr & !BDTR_MOE,
+    };
+    write_volatile(TIM1_BDTR, bdtr);
+  }
+}
+
+// Switches TIM1 to center-aligned counting and routes OC4REF to TRGO, which
+// starts the ADC injected conversions (JEXTSEL 0, TIM1_TRGO, set up by
+// `Adc::start_injected`). The counter must be stopped, as CMS cannot change
+// while it is enabled.
+fn config_center_aligned() {
+  unsafe {
+    let cr1 = read_volatile(TIM1_CR1);
+    write_volatile(TIM1_CR1, (cr1 & !CR1_CMS_MASK) | CR1_CMS_CENTER_1);
+
+    let cr2 = read_volatile(TIM1_CR2);
+    write_volatile(TIM1_CR2, (cr2 & !CR2_MMS_MASK) | CR2_MMS_OC4REF);
+  }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum OutputState {
+  // Phases follow the phase angle and power scale.
+  Drive,
+  // All switches off, so the motor spins freely.
+  Coast,
+  // All low sides on, shorting the windings together.
+  ShortBrake,
+  // Power applied against the direction of motion, returning energy to the
+  // supply. Carries the braking current (A), which the caller regulates
+  // through the power it passes to `brake`.
+  RegenBrake(f32),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Chopping {
+  // Only the high side switches, and current freewheels through the low
+  // side's diode during the off-time.
+  HighSide,
+  // The high and low sides switch in turn, as for sinusoidal drive.
+  Complementary,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Modulation {
+  Sinusoidal,
+  // Block commutation for trapezoidal motors: each sector drives one phase
+  // high, one low and floats the third. `advance` (rad) moves the
+  // commutation points ahead of the phase angle.
+  SixStep { advance: f32, chopping: Chopping },
+}
+
+#[derive(Copy, Clone)]
+enum PhaseDrive {
+  High,
+  Low,
+  Float,
+}
+
+// Phases U, V, W for each 60 degree sector of the phase angle, starting at
+// zero. The resulting vector sits in the middle of its sector.
+const SIX_STEP_SECTORS: [[PhaseDrive; 3]; 6] = [
+  [PhaseDrive::High, PhaseDrive::Float, PhaseDrive::Low],
+  [PhaseDrive::Float, PhaseDrive::High, PhaseDrive::Low],
+  [PhaseDrive::Low, PhaseDrive::High, PhaseDrive::Float],
+  [PhaseDrive::Low, PhaseDrive::Float, PhaseDrive::High],
+  [PhaseDrive::Float, PhaseDrive::Low, PhaseDrive::High],
+  [PhaseDrive::High, PhaseDrive::Low, PhaseDrive::Float],
+];
+
 pub struct MagnetController {
   timer: Tim1,
 
   ch_u_pwm: Ch1Output,
   ch_v_pwm: Ch2Output,
   ch_w_pwm: Ch3Output,
+  ch_adc_trigger: Ch4Output,
 
   ch_u_pin: Pe9AltFunc<Pe9Tim1Ch1>,
   ch_v_pin: Pe11AltFunc<Pe11Tim1Ch2>,
@@ -41,17 +287,37 @@ pub struct MagnetController {
 
   phase_angle: f32,
   power_scale: f32,
+  pwm_timing: PwmTiming,
+  running: bool,
+  output_state: OutputState,
+  deadtime_compensation: bool,
+  current_band: f32,
+  phase_currents: Option<[f32; 3]>,
+  modulation: Modulation,
+  bus_voltage: f32,
+  // Voltage (V) power scales are fractions of, when compensating for the
+  // supply, rather than of the supply itself.
+  compensation_voltage: Option<f32>,
+  // Largest power scale applied, whatever is commanded.
+  power_limit: f32,
 }
 impl MagnetController {
   pub fn new(
     system: &mut System,
     gpio_e: &mut GpioE,
-    pwm_freq: f32,
-    deadtime: Duration,
+    pwm_config: &PwmConfig,
+    min_deadtime: Duration,
+    bus_voltage: f32,
   ) -> Result<Self> {
+    let pwm_timing = resolve_pwm_timing(pwm_config, min_deadtime)?;
+    let deadtime = pwm_timing.deadtime;
+
     let mut timer = system.activate_tim1()?;
     timer.config_as_pwm();
-    timer.set_freq(pwm_freq)?;
+    config_center_aligned();
+    // The API sizes the period for edge-aligned counting, which is half as
+    // long as a center-aligned period.
+    timer.set_freq(2f32 * pwm_timing.frequency)?;
 
     let mut ch_u_pwm = timer.take_ch1()?.as_output(Ch1CompareMode::PwmMode1);
     ch_u_pwm.config_as_pwm();
@@ -71,11 +337,18 @@ impl MagnetController {
     ch_w_pwm.complement_mut().set_deadtime(deadtime)?;
     ch_w_pwm.complement_mut().enable();
 
+    // Not routed to a pin; only its reference signal is used, as the ADC
+    // trigger.
+    let mut ch_adc_trigger = timer.take_ch4()?.as_output(Ch4CompareMode::PwmMode2);
+    ch_adc_trigger.config_as_pwm();
+    ch_adc_trigger.set_duty_cycle(pwm_timing.trigger_duty())?;
+
     Ok(Self {
       timer,
       ch_u_pwm,
       ch_v_pwm,
       ch_w_pwm,
+      ch_adc_trigger,
 
       ch_u_pin: gpio_e.take_pe9()?.as_alt_func(
         PullDirection::Down,
@@ -110,9 +383,68 @@ impl MagnetController {
       ),
       phase_angle: 0f32,
       power_scale: 0f32,
+      pwm_timing,
+      running: false,
+      output_state: OutputState::Drive,
+      deadtime_compensation: false,
+      current_band: 0f32,
+      phase_currents: None,
+      modulation: Modulation::Sinusoidal,
+      bus_voltage: bus_voltage.max(MIN_BUS_VOLTAGE),
+      compensation_voltage: None,
+      power_limit: 1f32,
     })
   }
 
+  pub fn get_pwm_timing(&self) -> PwmTiming {
+    self.pwm_timing
+  }
+
+  // Changes the PWM frequency and deadtime, returning the values actually
+  // achieved. Only allowed while stopped, coasting or with every phase at zero
+  // duty, when reloading the period cannot glitch the outputs.
+  pub fn set_pwm_timing(
+    &mut self,
+    pwm_config: &PwmConfig,
+    min_deadtime: Duration,
+  ) -> Result<PwmTiming> {
+    let idle = match self.output_state {
+      OutputState::Drive => self.power_scale == 0f32,
+      OutputState::Coast | OutputState::ShortBrake => true,
+      OutputState::RegenBrake(_) => false,
+    };
+    if self.running && !idle {
+      return Err(Error::new(
+        "PWM timing can only change while stopped or at zero duty",
+      ));
+    }
+
+    let pwm_timing = resolve_pwm_timing(pwm_config, min_deadtime)?;
+
+    self.timer.set_freq(2f32 * pwm_timing.frequency)?;
+    self
+      .ch_u_pwm
+      .complement_mut()
+      .set_deadtime(pwm_timing.deadtime)?;
+    self
+      .ch_v_pwm
+      .complement_mut()
+      .set_deadtime(pwm_timing.deadtime)?;
+    self
+      .ch_w_pwm
+      .complement_mut()
+      .set_deadtime(pwm_timing.deadtime)?;
+    self
+      .ch_adc_trigger
+      .set_duty_cycle(pwm_timing.trigger_duty())?;
+    self.pwm_timing = pwm_timing;
+
+    // Duty cycles are relative to the period, so recompute the compare values.
+    self.refresh_outputs()?;
+
+    Ok(pwm_timing)
+  }
+
   pub fn get_phase_angle(&self) -> f32 {
     self.phase_angle
   }
@@ -129,25 +461,231 @@ impl MagnetController {
     self.set_phase_angle_and_power(norm_rads(phase_angle), self.power_scale)
   }
 
-  pub fn set_phase_angle_and_power(&mut self, phase_angle: f32, power_scale: f32) -> Result<()> {
-    let pa = norm_rads(phase_angle);
+  pub fn get_bus_voltage(&self) -> f32 {
+    self.bus_voltage
+  }
+
+  // The supply voltage (V), measured or assumed, for converting between
+  // voltages and duty cycles.
+  pub fn set_bus_voltage(&mut self, bus_voltage: f32) -> Result<()> {
+    self.bus_voltage = bus_voltage.max(MIN_BUS_VOLTAGE);
+    match self.compensation_voltage {
+      Some(_) => self.refresh_outputs(),
+      None => Ok(()),
+    }
+  }
 
-    let ps = match power_scale {
+  // With a voltage, power scales become fractions of it instead of the
+  // supply, so a command gives the same voltage whatever the supply is.
+  pub fn set_voltage_compensation(&mut self, voltage: Option<f32>) -> Result<()> {
+    self.compensation_voltage = voltage;
+    self.refresh_outputs()
+  }
+
+  pub fn set_power_limit(&mut self, power_limit: f32) -> Result<()> {
+    let power_limit = power_limit.max(0f32).min(1f32);
+    if power_limit == self.power_limit {
+      return Ok(());
+    }
+    self.power_limit = power_limit;
+    self.refresh_outputs()
+  }
+
+  // Fraction of the supply applied for a power scale.
+  fn duty_scale(&self, power_scale: f32) -> f32 {
+    let power_scale = power_scale.min(self.power_limit);
+    match self.compensation_voltage {
+      Some(voltage) => (power_scale * voltage / self.bus_voltage).min(1f32),
+      None => power_scale,
+    }
+  }
+
+  // Stores the phase angle and power, which only reach the outputs while
+  // driving.
+  pub fn set_phase_angle_and_power(&mut self, phase_angle: f32, power_scale: f32) -> Result<()> {
+    self.phase_angle = norm_rads(phase_angle);
+    self.power_scale = match power_scale {
       s if s < 0f32 => 0f32,
       s if s > 1f32 => 1f32,
       _ => power_scale,
     };
 
-    let u = Self::phase_angle_to_duty_cycle(pa);
-    let v = Self::phase_angle_to_duty_cycle(norm_rads(pa - PI2_3));
-    let w = Self::phase_angle_to_duty_cycle(norm_rads(pa - PI4_3));
+    match self.output_state {
+      OutputState::Drive => self.write_modulated(self.phase_angle, self.power_scale),
+      _ => Ok(()),
+    }
+  }
+
+  // Average phase voltage vector (V) applied, in the alpha/beta frame.
+  pub fn get_voltage_vector(&self) -> (f32, f32) {
+    match self.output_state {
+      OutputState::Drive => {
+        let amplitude = self
+          .duty_scale(self.power_scale)
+          .min(self.pwm_timing.max_duty)
+          / 2f32
+          * self.bus_voltage;
+        (
+          amplitude * libm::cosf(self.phase_angle),
+          amplitude * libm::sinf(self.phase_angle),
+        )
+      }
+      _ => (0f32, 0f32),
+    }
+  }
+
+  pub fn get_output_state(&self) -> OutputState {
+    self.output_state
+  }
 
-    self.ch_u_pwm.set_duty_cycle(u * ps)?;
-    self.ch_v_pwm.set_duty_cycle(v * ps)?;
-    self.ch_w_pwm.set_duty_cycle(w * ps)?;
+  // Changes state by first coasting, then setting up the new state's duty
+  // cycles before enabling the outputs again, so no intermediate switch
+  // pattern is ever applied. Regenerative braking stays coasting until the
+  // first call to `brake`, once the direction of motion is known.
+  pub fn set_output_state(&mut self, output_state: OutputState) -> Result<()> {
+    if let OutputState::RegenBrake(current) = output_state {
+      if !current.is_finite() || current < 0f32 {
+        return Err(Error::new("Regenerative brake current out of range"));
+      }
+    }
+
+    set_main_output(false);
+    self.output_state = output_state;
+
+    match output_state {
+      OutputState::Coast | OutputState::RegenBrake(_) => Ok(()),
+      OutputState::Drive | OutputState::ShortBrake => {
+        self.refresh_outputs()?;
+        set_main_output(self.running);
+        Ok(())
+      }
+    }
+  }
+
+  // Steps regenerative braking from the rotor's electrical angle and
+  // velocity, applying `power_scale`. Does nothing in other states.
+  pub fn brake(&mut self, rotor_phase_angle: f32, velocity: f32, power_scale: f32) -> Result<()> {
+    if !matches!(self.output_state, OutputState::RegenBrake(_)) {
+      return Ok(());
+    }
+
+    let lead = match velocity < 0f32 {
+      true => PI1_2,
+      false => -PI1_2,
+    };
+    self.write_modulated(
+      norm_rads(rotor_phase_angle + lead),
+      power_scale.min(self.power_limit),
+    )?;
+    set_main_output(self.running);
+
+    Ok(())
+  }
+
+  fn refresh_outputs(&mut self) -> Result<()> {
+    match self.output_state {
+      OutputState::Drive => self.write_modulated(self.phase_angle, self.power_scale),
+      OutputState::ShortBrake => self.write_duty_cycles(0f32, 0f32),
+      // Rewritten on the next brake step.
+      OutputState::Coast | OutputState::RegenBrake(_) => Ok(()),
+    }
+  }
+
+  // Enables adding the voltage lost during the deadtime back onto each phase,
+  // in the direction of its current. `current_band` is the measured current
+  // (A) over which the compensation ramps through zero.
+  pub fn set_deadtime_compensation(&mut self, enabled: bool, current_band: f32) -> Result<()> {
+    self.deadtime_compensation = enabled;
+    self.current_band = current_band.max(0f32);
+    self.refresh_outputs()
+  }
+
+  // Measured phase currents (A) for dead-time compensation. Without them the
+  // commanded current direction is used instead.
+  pub fn set_phase_currents(&mut self, phase_currents: Option<[f32; 3]>) {
+    self.phase_currents = phase_currents;
+  }
+
+  pub fn get_modulation(&self) -> Modulation {
+    self.modulation
+  }
+
+  pub fn set_modulation(&mut self, modulation: Modulation) -> Result<()> {
+    self.modulation = modulation;
+    self.refresh_outputs()
+  }
+
+  fn write_modulated(&mut self, phase_angle: f32, power_scale: f32) -> Result<()> {
+    let power_scale = self.duty_scale(power_scale);
+    match self.modulation {
+      Modulation::Sinusoidal => self.write_duty_cycles(phase_angle, power_scale),
+      Modulation::SixStep { advance, chopping } => {
+        self.write_six_step(norm_rads(phase_angle + advance), power_scale, chopping)
+      }
+    }
+  }
+
+  fn write_six_step(
+    &mut self,
+    phase_angle: f32,
+    power_scale: f32,
+    chopping: Chopping,
+  ) -> Result<()> {
+    let sector = (phase_angle / PI1_3) as usize % 6;
+    let duty_cycle = power_scale.min(self.pwm_timing.max_duty);
+
+    let mut outputs = [0u32; 3];
+    let mut duty_cycles = [0f32; 3];
+    for (i, drive) in SIX_STEP_SECTORS[sector].iter().enumerate() {
+      outputs[i] = match (drive, chopping) {
+        (PhaseDrive::High, Chopping::HighSide) => CCER_HIGH,
+        (PhaseDrive::High, Chopping::Complementary) | (PhaseDrive::Low, _) => CCER_HIGH | CCER_LOW,
+        (PhaseDrive::Float, _) => 0,
+      };
+      if let PhaseDrive::High = drive {
+        duty_cycles[i] = duty_cycle;
+      }
+    }
+
+    // Low and floating phases sit at zero duty, so the low side is on for
+    // the whole period wherever it is enabled.
+    self.ch_u_pwm.set_duty_cycle(duty_cycles[0])?;
+    self.ch_v_pwm.set_duty_cycle(duty_cycles[1])?;
+    self.ch_w_pwm.set_duty_cycle(duty_cycles[2])?;
+    set_phase_outputs(outputs);
+
+    Ok(())
+  }
 
-    self.phase_angle = pa;
-    self.power_scale = ps;
+  fn write_duty_cycles(&mut self, phase_angle: f32, power_scale: f32) -> Result<()> {
+    let angles = [
+      phase_angle,
+      norm_rads(phase_angle - PI2_3),
+      norm_rads(phase_angle - PI4_3),
+    ];
+    let mut duty_cycles = [0f32; 3];
+    for (duty_cycle, angle) in duty_cycles.iter_mut().zip(angles.iter()) {
+      *duty_cycle = Self::phase_angle_to_duty_cycle(*angle) * power_scale;
+    }
+
+    // Zero power is left alone so it still turns every low side fully on.
+    if self.deadtime_compensation && power_scale > 0f32 {
+      let compensation = self.pwm_timing.deadtime_duty();
+      for (i, duty_cycle) in duty_cycles.iter_mut().enumerate() {
+        let direction = match self.phase_currents {
+          Some(currents) => soft_sign(currents[i], self.current_band),
+          None => soft_sign(libm::cosf(angles[i]), COMMANDED_CURRENT_BAND),
+        };
+        *duty_cycle = (*duty_cycle + compensation * direction).max(0f32);
+      }
+    }
+
+    // Clamped so the low sides stay on long enough to sample the current.
+    let max = self.pwm_timing.max_duty;
+    self.ch_u_pwm.set_duty_cycle(duty_cycles[0].min(max))?;
+    self.ch_v_pwm.set_duty_cycle(duty_cycles[1].min(max))?;
+    self.ch_w_pwm.set_duty_cycle(duty_cycles[2].min(max))?;
+    set_phase_outputs([CCER_HIGH | CCER_LOW; 3]);
 
     Ok(())
   }
@@ -159,10 +697,17 @@ impl MagnetController {
 
   pub fn start(&mut self) {
     self.timer.start();
+    self.running = true;
+    set_main_output(match self.output_state {
+      OutputState::Drive | OutputState::ShortBrake => true,
+      OutputState::Coast | OutputState::RegenBrake(_) => false,
+    });
   }
 
   pub fn stop(&mut self) {
+    set_main_output(false);
     self.timer.stop();
+    self.running = false;
   }
 
   pub fn return_hardware(mut self, system: &mut System, gpio_e: &mut GpioE) -> Result<()> {
@@ -171,6 +716,7 @@ impl MagnetController {
     self.timer.return_ch1(self.ch_u_pwm.teardown()?)?;
     self.timer.return_ch2(self.ch_v_pwm.teardown()?)?;
     self.timer.return_ch3(self.ch_w_pwm.teardown()?)?;
+    self.timer.return_ch4(self.ch_adc_trigger.teardown()?)?;
 
     system.deactivate_tim1(self.timer)?;
 
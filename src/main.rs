@@ -29,13 +29,35 @@ const NUM_MAGNET_PAIRS: u32 = 20;
 
 extern crate panic_semihosting;
 
+mod adc;
+mod analog_input;
+mod angle_estimator;
+mod angle_sensor;
 mod bldc;
+mod bus_voltage;
+mod can;
+mod can_node;
+mod canopen_node;
+mod clock;
+mod comms;
+mod current_sense;
 mod drv_8305;
+mod encoder;
+mod hall_sensor;
 mod magnet_controller;
 mod math;
 mod modes;
+mod observer;
+mod param_store;
+mod params;
 mod position_sensor;
+mod rc_input;
 mod runner;
+mod serial;
+mod setpoint;
+mod step_dir;
+mod thermal;
+mod thermistors;
 
 use bldc::Bldc;
 use cortex_m_rt::entry;
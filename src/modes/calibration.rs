@@ -1,15 +1,12 @@
 use crate::{
-  drv_8305::Drv8305, magnet_controller::MagnetController, math::PI2,
-  position_sensor::PositionSensor,
+  angle_sensor::AngleSensor,
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  params::{ParamId, Params, MAX_CALIBRATION_SAMPLES},
 };
 use core::fmt::Write;
 use stm32f303_api::Result;
 
-const MAX_DEVIATION: f32 = PI2 / 10000f32;
-const NUM_SAMPLES: usize = 500;
-const SPEED: f32 = 0.002;
-const MAX_TURN: f32 = PI2 * 2f32;
-
 enum Phase {
   Start,
   Settle,
@@ -29,13 +26,13 @@ pub struct CalibrationMode {
   cumulative_phase_angle: f32,
 }
 impl CalibrationMode {
-  pub fn new() -> Self {
+  pub fn new(params: &Params) -> Self {
     Self {
       phase: Phase::Start,
       zero: 0f32,
       forward_extent: 0f32,
       backward_extent: 0f32,
-      settler: Settler::new(),
+      settler: Settler::new(params),
       cumulative_phase_angle: 0f32,
     }
   }
@@ -49,15 +46,20 @@ impl CalibrationMode {
 
   pub fn step(
     &mut self,
+    params: &Params,
     drv_8305: &mut Drv8305,
     magnet_controller: &mut MagnetController,
-    position_sensor: &mut PositionSensor,
+    position_sensor: &mut dyn AngleSensor,
   ) -> Result<()> {
+    let speed = params.get_f32(ParamId::CalibrationSpeed);
+    let max_turn = params.get_f32(ParamId::CalibrationMaxTurn);
+
     match self.phase {
       Phase::Start => {
         drv_8305.start();
         drv_8305.enable_gate();
-        magnet_controller.set_phase_angle_and_power(0f32, 0.1f32)?;
+        magnet_controller
+          .set_phase_angle_and_power(0f32, params.get_f32(ParamId::CalibrationPower))?;
         self.phase = Phase::Settle;
       }
       Phase::Settle => {
@@ -68,15 +70,17 @@ impl CalibrationMode {
           self.zero = zero;
           position_sensor.set_offset(zero);
           println!("Found zero at {} radians", self.zero).ok();
+          position_sensor.begin_learning();
           self.phase = Phase::ForwardTurn;
         }
       }
       Phase::ForwardTurn => {
-        self.cumulative_phase_angle += SPEED;
+        self.cumulative_phase_angle += speed;
         magnet_controller.set_phase_angle(self.cumulative_phase_angle)?;
-        if self.cumulative_phase_angle >= MAX_TURN {
-          magnet_controller.set_phase_angle(MAX_TURN)?;
-          self.settler = Settler::new();
+        position_sensor.learn(self.cumulative_phase_angle)?;
+        if self.cumulative_phase_angle >= max_turn {
+          magnet_controller.set_phase_angle(max_turn)?;
+          self.settler = Settler::new(params);
           self.phase = Phase::ForwardSettle;
         }
       }
@@ -91,10 +95,11 @@ impl CalibrationMode {
         }
       }
       Phase::BackwardTurn => {
-        self.cumulative_phase_angle -= SPEED;
+        self.cumulative_phase_angle -= speed;
         magnet_controller.set_phase_angle(self.cumulative_phase_angle)?;
+        position_sensor.learn(self.cumulative_phase_angle)?;
         if self.cumulative_phase_angle <= 0f32 {
-          self.settler = Settler::new();
+          self.settler = Settler::new(params);
           self.phase = Phase::BackwardSettle;
         }
       }
@@ -105,6 +110,7 @@ impl CalibrationMode {
         {
           self.backward_extent = backward_extent;
           println!("Found backward extent at {} radians", self.backward_extent).ok();
+          position_sensor.finish_learning()?;
           self.phase = Phase::Done;
           magnet_controller.set_phase_angle_and_power(0f32, 0f32)?;
           drv_8305.disable_gate();
@@ -123,14 +129,18 @@ enum SettleState {
 }
 
 struct Settler {
+  num_samples: usize,
+  max_deviation: f32,
   samples_collected: usize,
-  samples: [f32; NUM_SAMPLES],
+  samples: [f32; MAX_CALIBRATION_SAMPLES],
 }
 impl Settler {
-  pub fn new() -> Self {
+  pub fn new(params: &Params) -> Self {
     Self {
+      num_samples: params.get_u32(ParamId::CalibrationNumSamples) as usize,
+      max_deviation: params.get_f32(ParamId::CalibrationMaxDeviation),
       samples_collected: 0,
-      samples: [0f32; NUM_SAMPLES],
+      samples: [0f32; MAX_CALIBRATION_SAMPLES],
     }
   }
 
@@ -139,22 +149,22 @@ impl Settler {
   }
 
   pub fn add_sample(&mut self, sample: f32) -> SettleState {
-    for i in 0..NUM_SAMPLES - 1 {
+    for i in 0..self.num_samples - 1 {
       self.samples[i + 1] = self.samples[i];
     }
 
     self.samples[0] = sample;
     self.samples_collected += 1;
 
-    if self.samples_collected >= NUM_SAMPLES {
+    if self.samples_collected >= self.num_samples {
       let mut mean = 0f32;
-      for i in 0..NUM_SAMPLES {
+      for i in 0..self.num_samples {
         mean += self.samples[i];
       }
-      mean = mean / NUM_SAMPLES as f32;
+      mean = mean / self.num_samples as f32;
 
-      for i in 0..NUM_SAMPLES {
-        if libm::fabsf(mean - self.samples[i]) > MAX_DEVIATION {
+      for i in 0..self.num_samples {
+        if libm::fabsf(mean - self.samples[i]) > self.max_deviation {
           return SettleState::NotSettled;
         }
       }
@@ -0,0 +1,112 @@
+use stm32f303_api::Result;
+
+use crate::{
+  angle_sensor::AngleSensor,
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  math::{PI, PI1_2, PI2},
+  params::{ParamId, Params},
+  position_sensor::Motion,
+};
+
+// Speed (rad/s) below which friction fades out, so it does not chatter
+// around standstill.
+const FRICTION_VELOCITY: f32 = 0.2;
+// Fraction of the detent window the rotor must be within to click into it.
+const CAPTURE: f32 = 0.5;
+
+// Renders a knob feel from the rotor position: detents evenly spaced from
+// zero, walls at the ends of the travel, and friction and viscosity. The
+// effects are read from parameters each step, so can be changed while running.
+pub struct HapticMode {
+  detent: Option<i32>,
+  detent_changed: bool,
+}
+impl HapticMode {
+  pub fn new(drv_8305: &mut Drv8305) -> Self {
+    drv_8305.enable_gate();
+    Self {
+      detent: None,
+      detent_changed: false,
+    }
+  }
+
+  // The detent clicked into since the last call, if it changed.
+  pub fn take_detent_change(&mut self) -> Option<i32> {
+    match self.detent_changed {
+      true => {
+        self.detent_changed = false;
+        self.detent
+      }
+      false => None,
+    }
+  }
+
+  fn detent_effort(&mut self, params: &Params, position: f32) -> f32 {
+    let count = params.get_u32(ParamId::HapticDetentCount);
+    if count == 0 {
+      return 0f32;
+    }
+
+    let spacing = PI2 / count as f32;
+    let nearest = libm::roundf(position / spacing);
+    let offset = position - nearest * spacing;
+    // Half the width of the window each detent pulls within.
+    let window = params.get_f32(ParamId::HapticDetentWidth) * spacing / 2f32;
+    if window <= 0f32 || libm::fabsf(offset) >= window {
+      return 0f32;
+    }
+
+    let index = nearest as i32;
+    if libm::fabsf(offset) < window * CAPTURE && self.detent != Some(index) {
+      self.detent = Some(index);
+      self.detent_changed = true;
+    }
+
+    -params.get_f32(ParamId::HapticDetentStrength) * libm::sinf(PI * offset / window)
+  }
+
+  fn wall_effort(params: &Params, position: f32) -> f32 {
+    if !params.get_bool(ParamId::HapticWalls) {
+      return 0f32;
+    }
+
+    let stiffness = params.get_f32(ParamId::HapticWallStiffness);
+    let min_position = params.get_f32(ParamId::HapticMinPosition);
+    let max_position = params.get_f32(ParamId::HapticMaxPosition);
+    if position < min_position {
+      stiffness * (min_position - position)
+    } else if position > max_position {
+      stiffness * (max_position - position)
+    } else {
+      0f32
+    }
+  }
+
+  pub fn step(
+    &mut self,
+    params: &Params,
+    motion: &Motion,
+    _drv_8305: &mut Drv8305,
+    magnet_controller: &mut MagnetController,
+    position_sensor: &mut dyn AngleSensor,
+  ) -> Result<()> {
+    let max_effort = params.get_f32(ParamId::HapticMaxEffort);
+    let friction = params.get_f32(ParamId::HapticFriction)
+      * (motion.velocity / FRICTION_VELOCITY).max(-1f32).min(1f32);
+
+    let effort = (self.detent_effort(params, motion.position)
+      + Self::wall_effort(params, motion.position)
+      - friction
+      - params.get_f32(ParamId::HapticViscosity) * motion.velocity)
+      .max(-max_effort)
+      .min(max_effort);
+
+    let phase_angle = position_sensor.absolute_to_phase_angle(motion.angle);
+    let lead = match effort < 0f32 {
+      true => -PI1_2,
+      false => PI1_2,
+    };
+    magnet_controller.set_phase_angle_and_power(phase_angle + lead, libm::fabsf(effort))
+  }
+}
@@ -0,0 +1,166 @@
+use core::fmt::Write;
+use stm32f303_api::Result;
+
+use crate::{
+  angle_sensor::AngleSensor,
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  math::PI1_2,
+  params::{ParamId, Params},
+  position_sensor::Motion,
+};
+
+// Fraction of the effort limit counted as saturated.
+const SATURATION: f32 = 0.95;
+// Position error (rad) at which backing off is complete.
+const BACKOFF_TOLERANCE: f32 = 0.02;
+// Time (s) the rotor has to reach the back-off position once the target has.
+const BACKOFF_TIMEOUT: f32 = 1f32;
+
+#[derive(Copy, Clone)]
+pub enum HomingState {
+  Seeking,
+  BackingOff,
+  // Carries the position, in the frame homing started in, to become zero.
+  Homed(f32),
+  Failed,
+}
+
+// Finds a hard stop by driving slowly towards it with limited effort until
+// the rotor stalls, then backs off and reports where the zero should be.
+pub struct HomingMode {
+  state: HomingState,
+  direction: f32,
+  // Taken from the first step, as the motion may be reset before it.
+  start_position: Option<f32>,
+  stop_position: f32,
+  target: f32,
+  velocity_integral: f32,
+  // Position at which the current stall window started, and its duration.
+  progress_position: f32,
+  stall_time: f32,
+  // Time since the back-off target arrived.
+  settle_time: f32,
+}
+impl HomingMode {
+  pub fn new(params: &Params, drv_8305: &mut Drv8305) -> Self {
+    drv_8305.enable_gate();
+    Self {
+      state: HomingState::Seeking,
+      direction: match params.get_u32(ParamId::HomeDirection) {
+        1 => -1f32,
+        _ => 1f32,
+      },
+      start_position: None,
+      stop_position: 0f32,
+      target: 0f32,
+      velocity_integral: 0f32,
+      progress_position: 0f32,
+      stall_time: 0f32,
+      settle_time: 0f32,
+    }
+  }
+
+  pub fn get_state(&self) -> HomingState {
+    self.state
+  }
+
+  fn fail(&mut self, reason: &str, magnet_controller: &mut MagnetController) -> Result<()> {
+    println!("Homing failed: {}", reason).ok();
+    self.state = HomingState::Failed;
+    magnet_controller.set_phase_angle_and_power(0f32, 0f32)
+  }
+
+  pub fn step(
+    &mut self,
+    params: &Params,
+    dt: f32,
+    motion: &Motion,
+    _drv_8305: &mut Drv8305,
+    magnet_controller: &mut MagnetController,
+    position_sensor: &mut dyn AngleSensor,
+  ) -> Result<()> {
+    let max_power = params.get_f32(ParamId::HomeMaxPower);
+    let speed = params.get_f32(ParamId::HomeSpeed);
+    let start_position = match self.start_position {
+      Some(position) => position,
+      None => {
+        self.start_position = Some(motion.position);
+        self.progress_position = motion.position;
+        motion.position
+      }
+    };
+
+    let effort = match self.state {
+      HomingState::Seeking => {
+        if libm::fabsf(motion.position - start_position) > params.get_f32(ParamId::HomeMaxTravel) {
+          return self.fail("no stop within the maximum travel", magnet_controller);
+        }
+
+        let error = self.direction * speed - motion.velocity;
+        self.velocity_integral = (self.velocity_integral
+          + params.get_f32(ParamId::VelocityKi) * error * dt)
+          .max(-max_power)
+          .min(max_power);
+        let effort = (params.get_f32(ParamId::VelocityKp) * error + self.velocity_integral)
+          .max(-max_power)
+          .min(max_power);
+
+        // Stalled once the effort has stayed saturated without the rotor
+        // making progress for the stall time.
+        let saturated = libm::fabsf(effort) >= max_power * SATURATION;
+        let progress = libm::fabsf(motion.position - self.progress_position);
+        if !saturated || progress > params.get_f32(ParamId::HomeStallDistance) {
+          self.progress_position = motion.position;
+          self.stall_time = 0f32;
+        } else {
+          self.stall_time += dt;
+        }
+
+        if self.stall_time >= params.get_f32(ParamId::HomeStallTime) {
+          println!("Homing found the stop").ok();
+          self.stop_position = motion.position;
+          self.target = motion.position;
+          self.state = HomingState::BackingOff;
+        }
+        effort
+      }
+      HomingState::BackingOff => {
+        let backoff_position =
+          self.stop_position - self.direction * params.get_f32(ParamId::HomeBackoff);
+        let max_step = speed * dt;
+        self.target += (backoff_position - self.target)
+          .max(-max_step)
+          .min(max_step);
+
+        if libm::fabsf(backoff_position - self.target) < BACKOFF_TOLERANCE {
+          if libm::fabsf(backoff_position - motion.position) < BACKOFF_TOLERANCE {
+            self.state = HomingState::Homed(
+              self.stop_position - self.direction * params.get_f32(ParamId::HomeOffset),
+            );
+          } else {
+            // The power limit or a load can hold the rotor short of the
+            // target, which would otherwise leave homing waiting forever.
+            self.settle_time += dt;
+            if self.settle_time >= BACKOFF_TIMEOUT {
+              return self.fail("did not reach the back-off position", magnet_controller);
+            }
+          }
+        }
+
+        (params.get_f32(ParamId::PositionKp) * (self.target - motion.position)
+          - params.get_f32(ParamId::PositionKd) * motion.velocity)
+          .max(-max_power)
+          .min(max_power)
+      }
+      HomingState::Homed(_) | HomingState::Failed => return Ok(()),
+    };
+
+    let phase_angle = position_sensor.absolute_to_phase_angle(motion.angle);
+    let lead = match effort < 0f32 {
+      true => -PI1_2,
+      false => PI1_2,
+    };
+    magnet_controller.set_phase_angle_and_power(phase_angle + lead, libm::fabsf(effort))
+  }
+}
@@ -0,0 +1,66 @@
+use bldc_protocol::ImpedanceTarget;
+use stm32f303_api::Result;
+
+use crate::{
+  angle_sensor::AngleSensor,
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  math::PI1_2,
+  params::{ParamId, Params},
+  position_sensor::Motion,
+};
+
+// Virtual spring and damper about a target, plus a feed-forward effort, with
+// all terms set by the host each cycle. Starts limp, holding no position.
+pub struct ImpedanceMode {
+  target: ImpedanceTarget,
+}
+impl ImpedanceMode {
+  pub fn new(drv_8305: &mut Drv8305, motion: &Motion) -> Self {
+    drv_8305.enable_gate();
+    Self {
+      target: ImpedanceTarget {
+        position: motion.position,
+        velocity: 0f32,
+        stiffness: 0f32,
+        damping: 0f32,
+        feedforward: 0f32,
+      },
+    }
+  }
+
+  pub fn set_target(&mut self, target: ImpedanceTarget) {
+    self.target = target;
+  }
+
+  pub fn step(
+    &mut self,
+    params: &Params,
+    motion: &Motion,
+    _drv_8305: &mut Drv8305,
+    magnet_controller: &mut MagnetController,
+    position_sensor: &mut dyn AngleSensor,
+  ) -> Result<()> {
+    let ImpedanceTarget {
+      position,
+      velocity,
+      stiffness,
+      damping,
+      feedforward,
+    } = self.target;
+    let max_effort = params.get_f32(ParamId::ImpedanceMaxEffort);
+
+    let effort = (stiffness * (position - motion.position)
+      + damping * (velocity - motion.velocity)
+      + feedforward)
+      .max(-max_effort)
+      .min(max_effort);
+
+    let phase_angle = position_sensor.absolute_to_phase_angle(motion.angle);
+    let lead = match effort < 0f32 {
+      true => -PI1_2,
+      false => PI1_2,
+    };
+    magnet_controller.set_phase_angle_and_power(phase_angle + lead, libm::fabsf(effort))
+  }
+}
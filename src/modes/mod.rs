@@ -1,3 +1,10 @@
 pub mod calibration;
 pub mod demo;
+pub mod haptic;
+pub mod homing;
+pub mod impedance;
+pub mod open_loop;
 pub mod recovery;
+pub mod sensorless;
+pub mod servo;
+pub mod step_dir;
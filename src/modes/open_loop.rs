@@ -0,0 +1,77 @@
+use stm32f303_api::Result;
+
+use crate::{
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  math::{norm_rads, PI2},
+  params::{ParamId, Params},
+};
+
+// Turns the field at an electrical velocity (rad/s) slewed towards a target
+// at a limited acceleration, without regard to where the rotor is.
+pub struct OpenLoopRamp {
+  angle: f32,
+  velocity: f32,
+}
+impl OpenLoopRamp {
+  pub fn new() -> Self {
+    Self {
+      angle: 0f32,
+      velocity: 0f32,
+    }
+  }
+
+  pub fn get_velocity(&self) -> f32 {
+    self.velocity
+  }
+
+  // Advances by `dt` seconds, returning the electrical angle to drive.
+  pub fn step(&mut self, target_velocity: f32, acceleration: f32, dt: f32) -> f32 {
+    let max_change = acceleration * dt;
+    self.velocity += (target_velocity - self.velocity)
+      .max(-max_change)
+      .min(max_change);
+    self.angle = norm_rads(self.angle + self.velocity * dt);
+    self.angle
+  }
+}
+
+// Spins the motor open-loop, for checking the wiring of a new motor before
+// any sensor works. The electrical frequency ramps to the target, with the
+// power rising from the boost along a volts-per-hertz line. All settings are
+// read each step, so can be changed while running.
+pub struct OpenLoopMode {
+  ramp: OpenLoopRamp,
+}
+impl OpenLoopMode {
+  pub fn new(drv_8305: &mut Drv8305) -> Self {
+    drv_8305.enable_gate();
+    Self {
+      ramp: OpenLoopRamp::new(),
+    }
+  }
+
+  // Electrical frequency (Hz) currently driven.
+  pub fn get_frequency(&self) -> f32 {
+    self.ramp.get_velocity() / PI2
+  }
+
+  pub fn step(
+    &mut self,
+    params: &Params,
+    dt: f32,
+    _drv_8305: &mut Drv8305,
+    magnet_controller: &mut MagnetController,
+  ) -> Result<()> {
+    let angle = self.ramp.step(
+      params.get_f32(ParamId::OpenLoopFrequency) * PI2,
+      params.get_f32(ParamId::OpenLoopAccel) * PI2,
+      dt,
+    );
+    let power = (params.get_f32(ParamId::OpenLoopBoost)
+      + params.get_f32(ParamId::OpenLoopVoltsPerHertz) * libm::fabsf(self.get_frequency()))
+    .min(params.get_f32(ParamId::OpenLoopMaxPower));
+
+    magnet_controller.set_phase_angle_and_power(angle, power)
+  }
+}
@@ -1,8 +1,6 @@
 use stm32f303_api::Result;
 
-use crate::{
-  drv_8305::Drv8305, magnet_controller::MagnetController, position_sensor::PositionSensor,
-};
+use crate::{angle_sensor::AngleSensor, drv_8305::Drv8305, magnet_controller::MagnetController};
 
 pub struct RecoveryMode {}
 impl RecoveryMode {
@@ -14,7 +12,7 @@ impl RecoveryMode {
     &mut self,
     _drv_8305: &mut Drv8305,
     _current_controller: &mut MagnetController,
-    _position_sensor: &mut PositionSensor,
+    _position_sensor: &mut dyn AngleSensor,
   ) -> Result<()> {
     Ok(())
   }
@@ -0,0 +1,216 @@
+use core::fmt::Write;
+use stm32f303_api::Result;
+
+use crate::{
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  math::PI1_2,
+  modes::open_loop::OpenLoopRamp,
+  observer::{clarke, FluxObserver, MotorModel},
+  params::{ParamId, Params},
+};
+
+// Times the observer may lose lock and the motor be restarted before giving
+// up.
+const MAX_RESTARTS: u32 = 3;
+// Time (s) the observer must stay locked for the restarts to be forgiven.
+const RESTART_FORGIVE_TIME: f32 = 1f32;
+
+enum Phase {
+  Align(f32),
+  Ramp,
+  ClosedLoop(f32),
+  Stopped,
+}
+
+pub fn motor_model(params: &Params) -> MotorModel {
+  MotorModel {
+    resistance: params.get_f32(ParamId::MotorResistance),
+    inductance: params.get_f32(ParamId::MotorInductance),
+    flux_linkage: params.get_f32(ParamId::MotorFluxLinkage),
+  }
+}
+
+// Velocity control without a position sensor. The rotor is aligned and then
+// dragged open-loop with a rising frequency until it turns fast enough for
+// the flux observer to lock onto its back-EMF, after which the observer's
+// angle commutates the motor. Losing lock starts over from the alignment, a
+// few times at most before the motor is stopped. A target below the handoff
+// speed can't be held, so it slows the motor down and stops it instead.
+pub struct SensorlessMode {
+  phase: Phase,
+  target: f32,
+  direction: f32,
+  num_magnet_pairs: f32,
+  observer: FluxObserver,
+  ramp: OpenLoopRamp,
+  velocity_integral: f32,
+  restarts: u32,
+}
+impl SensorlessMode {
+  pub fn new(params: &Params, num_magnet_pairs: u32, drv_8305: &mut Drv8305) -> Self {
+    drv_8305.enable_gate();
+    Self {
+      phase: Phase::Align(0f32),
+      target: 0f32,
+      direction: 1f32,
+      num_magnet_pairs: num_magnet_pairs as f32,
+      observer: FluxObserver::new(
+        motor_model(params),
+        params.get_f32(ParamId::ObserverGain),
+        params.get_f32(ParamId::ObserverPllBandwidth),
+      ),
+      ramp: OpenLoopRamp::new(),
+      velocity_integral: 0f32,
+      restarts: 0,
+    }
+  }
+
+  pub fn get_target(&self) -> f32 {
+    self.target
+  }
+
+  // Mechanical velocity (rad/s). The startup direction follows the target
+  // when it is set; reversing needs a restart.
+  pub fn set_target(&mut self, target: f32) {
+    if let Phase::Align(_) = self.phase {
+      self.direction = match target < 0f32 {
+        true => -1f32,
+        false => 1f32,
+      };
+    }
+    self.target = target;
+  }
+
+  // Whether the motor has been stopped, on command or after losing lock too
+  // many times.
+  pub fn is_stopped(&self) -> bool {
+    matches!(self.phase, Phase::Stopped)
+  }
+
+  // Estimated mechanical velocity (rad/s), once running closed-loop.
+  pub fn get_velocity(&self) -> Option<f32> {
+    match self.phase {
+      Phase::ClosedLoop(_) => Some(self.observer.get_velocity() / self.num_magnet_pairs),
+      _ => None,
+    }
+  }
+
+  pub fn step(
+    &mut self,
+    params: &Params,
+    dt: f32,
+    phase_currents: [f32; 3],
+    _drv_8305: &mut Drv8305,
+    magnet_controller: &mut MagnetController,
+  ) -> Result<()> {
+    // The currents were measured under the voltage applied since the last
+    // step.
+    let voltage = magnet_controller.get_voltage_vector();
+    self.observer.set_model(
+      motor_model(params),
+      params.get_f32(ParamId::ObserverGain),
+      params.get_f32(ParamId::ObserverPllBandwidth),
+    );
+    self.observer.update(voltage, clarke(phase_currents), dt);
+
+    let handoff_speed = params.get_f32(ParamId::SensorlessHandoffSpeed);
+    let lock_error = params.get_f32(ParamId::SensorlessLockError);
+    // The handoff speed is electrical, the target mechanical.
+    let stopping = libm::fabsf(self.target) * self.num_magnet_pairs < handoff_speed;
+
+    match self.phase {
+      // Wait unpowered for a target to start towards.
+      Phase::Align(_) if stopping => {
+        magnet_controller.set_phase_angle_and_power(0f32, 0f32)?;
+        self.phase = Phase::Align(0f32);
+      }
+      Phase::Align(elapsed) => {
+        magnet_controller
+          .set_phase_angle_and_power(0f32, params.get_f32(ParamId::SensorlessAlignPower))?;
+        let elapsed = elapsed + dt;
+        self.phase = match elapsed >= params.get_f32(ParamId::SensorlessAlignTime) {
+          true => {
+            self.ramp = OpenLoopRamp::new();
+            self.observer.reset(0f32, 0f32);
+            magnet_controller.set_power_scale(params.get_f32(ParamId::SensorlessRampPower))?;
+            Phase::Ramp
+          }
+          false => Phase::Align(elapsed),
+        };
+      }
+      Phase::Ramp => {
+        let ramp_target = match stopping {
+          true => 0f32,
+          false => self.direction * handoff_speed,
+        };
+        let angle = self.ramp.step(
+          ramp_target,
+          params.get_f32(ParamId::SensorlessRampAccel),
+          dt,
+        );
+        magnet_controller.set_phase_angle(angle)?;
+
+        if stopping && self.ramp.get_velocity() == 0f32 {
+          magnet_controller.set_phase_angle_and_power(0f32, 0f32)?;
+          self.phase = Phase::Stopped;
+        } else if !stopping
+          && libm::fabsf(self.ramp.get_velocity()) >= handoff_speed
+          && self.observer.is_locked(lock_error)
+        {
+          println!("Sensorless observer locked").ok();
+          self.velocity_integral = self.direction * magnet_controller.get_power_scale();
+          self.phase = Phase::ClosedLoop(0f32);
+        }
+      }
+      Phase::ClosedLoop(locked_time) => {
+        let velocity = self.observer.get_velocity();
+        if !self.observer.is_locked(lock_error * 2f32)
+          || libm::fabsf(velocity) < handoff_speed / 2f32
+        {
+          magnet_controller.set_phase_angle_and_power(0f32, 0f32)?;
+          // The velocity loop has slowed the motor for a commanded stop.
+          if stopping {
+            self.phase = Phase::Stopped;
+          } else if self.restarts < MAX_RESTARTS {
+            println!("Sensorless observer lost lock, restarting").ok();
+            self.restarts += 1;
+            self.phase = Phase::Align(0f32);
+            // The direction follows the target again, as when starting.
+            self.set_target(self.target);
+          } else {
+            println!("Sensorless observer lost lock, stopping").ok();
+            self.phase = Phase::Stopped;
+          }
+          return Ok(());
+        }
+
+        let locked_time = locked_time + dt;
+        if locked_time >= RESTART_FORGIVE_TIME {
+          self.restarts = 0;
+        }
+        self.phase = Phase::ClosedLoop(locked_time);
+
+        let max_power = params.get_f32(ParamId::ServoMaxPower);
+        let error = self.target - velocity / self.num_magnet_pairs;
+        self.velocity_integral = (self.velocity_integral
+          + params.get_f32(ParamId::VelocityKi) * error * dt)
+          .max(-max_power)
+          .min(max_power);
+        let effort = (params.get_f32(ParamId::VelocityKp) * error + self.velocity_integral)
+          .max(-max_power)
+          .min(max_power);
+
+        let lead = match effort < 0f32 {
+          true => -PI1_2,
+          false => PI1_2,
+        };
+        magnet_controller
+          .set_phase_angle_and_power(self.observer.get_angle() + lead, libm::fabsf(effort))?;
+      }
+      Phase::Stopped => {}
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,134 @@
+use bldc_control::trajectory::{Goal, Limits, Setpoint, Shape, Trajectory};
+use stm32f303_api::Result;
+
+use crate::{
+  angle_sensor::AngleSensor,
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  math::PI1_2,
+  params::{ParamId, Params},
+  position_sensor::Motion,
+};
+
+#[derive(Copy, Clone)]
+pub enum Target {
+  Position(f32),
+  Velocity(f32),
+  Torque(f32),
+}
+
+fn trajectory_shape(params: &Params) -> Option<Shape> {
+  match params.get_u32(ParamId::TrajectoryShape) {
+    1 => Some(Shape::Trapezoidal),
+    2 => Some(Shape::SCurve),
+    _ => None,
+  }
+}
+
+fn trajectory_limits(params: &Params) -> Limits {
+  Limits {
+    velocity: params.get_f32(ParamId::TrajectoryMaxVelocity),
+    acceleration: params.get_f32(ParamId::TrajectoryMaxAccel),
+    jerk: params.get_f32(ParamId::TrajectoryMaxJerk),
+  }
+}
+
+pub struct ServoMode {
+  target: Target,
+  velocity_integral: f32,
+  trajectory: Option<Trajectory>,
+}
+impl ServoMode {
+  pub fn new(drv_8305: &mut Drv8305, target: Target) -> Self {
+    drv_8305.enable_gate();
+    Self {
+      target,
+      velocity_integral: 0f32,
+      trajectory: None,
+    }
+  }
+
+  pub fn get_target(&self) -> Target {
+    self.target
+  }
+
+  pub fn set_target(&mut self, target: Target) {
+    match (self.target, target) {
+      (Target::Velocity(_), Target::Velocity(_)) => {}
+      _ => self.velocity_integral = 0f32,
+    }
+    self.target = target;
+  }
+
+  // Runs position and velocity targets through the trajectory planner, when
+  // one is configured. It starts from the measured motion and carries on
+  // from its last setpoint when the target changes.
+  fn plan(&mut self, params: &Params, dt: f32, motion: &Motion) -> Option<Setpoint> {
+    let goal = match self.target {
+      Target::Position(position) => Some(Goal::Position(position)),
+      Target::Velocity(velocity) => Some(Goal::Velocity(velocity)),
+      Target::Torque(_) => None,
+    };
+    let (goal, shape) = match (goal, trajectory_shape(params)) {
+      (Some(goal), Some(shape)) => (goal, shape),
+      _ => {
+        self.trajectory = None;
+        return None;
+      }
+    };
+
+    let limits = trajectory_limits(params);
+    let trajectory = self.trajectory.get_or_insert_with(|| {
+      let mut trajectory = Trajectory::new(shape, limits);
+      trajectory.reset(motion.position, motion.velocity);
+      trajectory
+    });
+    trajectory.set_shape(shape);
+    trajectory.set_limits(limits);
+    trajectory.set_goal(goal);
+    Some(trajectory.step(dt))
+  }
+
+  pub fn step(
+    &mut self,
+    params: &Params,
+    dt: f32,
+    motion: &Motion,
+    _drv_8305: &mut Drv8305,
+    magnet_controller: &mut MagnetController,
+    position_sensor: &mut dyn AngleSensor,
+  ) -> Result<()> {
+    let max_power = params.get_f32(ParamId::ServoMaxPower);
+    let setpoint = self.plan(params, dt, motion);
+
+    let effort = match self.target {
+      Target::Position(position) => {
+        let (position, velocity) = match setpoint {
+          Some(setpoint) => (setpoint.position, setpoint.velocity),
+          None => (position, 0f32),
+        };
+        params.get_f32(ParamId::PositionKp) * (position - motion.position)
+          + params.get_f32(ParamId::PositionKd) * (velocity - motion.velocity)
+      }
+      Target::Velocity(velocity) => {
+        let velocity = setpoint.map_or(velocity, |setpoint| setpoint.velocity);
+        let error = velocity - motion.velocity;
+        self.velocity_integral = (self.velocity_integral
+          + params.get_f32(ParamId::VelocityKi) * error * dt)
+          .max(-max_power)
+          .min(max_power);
+        params.get_f32(ParamId::VelocityKp) * error + self.velocity_integral
+      }
+      Target::Torque(effort) => effort,
+    };
+
+    let effort = effort.max(-max_power).min(max_power);
+    let phase_angle = position_sensor.absolute_to_phase_angle(motion.angle);
+    let lead = match effort < 0f32 {
+      true => -PI1_2,
+      false => PI1_2,
+    };
+
+    magnet_controller.set_phase_angle_and_power(phase_angle + lead, libm::fabsf(effort))
+  }
+}
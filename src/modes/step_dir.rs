@@ -0,0 +1,87 @@
+use stm32f303_api::Result;
+
+use crate::{
+  angle_sensor::AngleSensor,
+  drv_8305::Drv8305,
+  magnet_controller::MagnetController,
+  math::PI2,
+  modes::servo::{ServoMode, Target},
+  params::{ParamId, Params},
+  position_sensor::Motion,
+};
+
+// Position control from a step/direction input, so the motor can replace a
+// stepper. Steps are counted from where the mode was entered, or last
+// enabled, and scaled by the steps per revolution into a position target.
+pub struct StepDirMode {
+  servo: ServoMode,
+  origin_steps: Option<i64>,
+  origin_position: f32,
+  enabled: bool,
+}
+impl StepDirMode {
+  pub fn new(drv_8305: &mut Drv8305, motion: &Motion) -> Self {
+    Self {
+      servo: ServoMode::new(drv_8305, Target::Position(motion.position)),
+      origin_steps: None,
+      origin_position: motion.position,
+      enabled: true,
+    }
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  pub fn step(
+    &mut self,
+    params: &Params,
+    dt: f32,
+    motion: &Motion,
+    steps: i64,
+    enable_high: bool,
+    drv_8305: &mut Drv8305,
+    magnet_controller: &mut MagnetController,
+    position_sensor: &mut dyn AngleSensor,
+  ) -> Result<()> {
+    let enabled = enable_high != params.get_bool(ParamId::StepDirEnableActiveLow);
+    if enabled != self.enabled {
+      self.enabled = enabled;
+      match enabled {
+        // Holds wherever the rotor was left while disabled.
+        true => {
+          self.origin_steps = None;
+          drv_8305.enable_gate();
+        }
+        false => drv_8305.disable_gate(),
+      }
+    }
+    if !enabled {
+      return magnet_controller.set_phase_angle_and_power(0f32, 0f32);
+    }
+
+    let origin_steps = match self.origin_steps {
+      Some(origin_steps) => origin_steps,
+      None => {
+        self.origin_steps = Some(steps);
+        self.origin_position = motion.position;
+        steps
+      }
+    };
+    let direction = match params.get_bool(ParamId::StepDirInvert) {
+      true => -1f32,
+      false => 1f32,
+    };
+    let revolutions =
+      (steps - origin_steps) as f32 / params.get_u32(ParamId::StepDirStepsPerRev) as f32;
+    self.servo.set_target(Target::Position(
+      self.origin_position + direction * revolutions * PI2,
+    ));
+
+    self.servo.step(
+      params,
+      dt,
+      motion,
+      drv_8305,
+      magnet_controller,
+      position_sensor,
+    )
+  }
+}
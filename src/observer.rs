@@ -0,0 +1,164 @@
+use crate::math::{norm_rads, wrap_rads, PI2};
+
+const SQRT_3: f32 = 1.732_050_8;
+
+// Clarke transform of three phase quantities into the stationary alpha/beta
+// frame, without assuming they sum to zero.
+pub fn clarke(phases: [f32; 3]) -> (f32, f32) {
+  (
+    (2f32 * phases[0] - phases[1] - phases[2]) / 3f32,
+    (phases[1] - phases[2]) / SQRT_3,
+  )
+}
+
+#[derive(Copy, Clone)]
+pub struct MotorModel {
+  // Phase resistance (ohm), inductance (H) and permanent magnet flux
+  // linkage (Wb).
+  pub resistance: f32,
+  pub inductance: f32,
+  pub flux_linkage: f32,
+}
+
+// Tracks an angle with a type 2 phase locked loop, giving a filtered angle
+// and velocity from a noisy angle measurement. Critically damped, with the
+// bandwidth (rad/s) setting how quickly it follows.
+pub struct Pll {
+  kp: f32,
+  ki: f32,
+  angle: f32,
+  velocity: f32,
+  error: f32,
+}
+impl Pll {
+  pub fn new(bandwidth: f32) -> Self {
+    let mut pll = Self {
+      kp: 0f32,
+      ki: 0f32,
+      angle: 0f32,
+      velocity: 0f32,
+      error: 0f32,
+    };
+    pll.set_bandwidth(bandwidth);
+    pll
+  }
+
+  pub fn set_bandwidth(&mut self, bandwidth: f32) {
+    self.kp = 2f32 * bandwidth;
+    self.ki = bandwidth * bandwidth;
+  }
+
+  pub fn reset(&mut self, angle: f32, velocity: f32) {
+    self.angle = norm_rads(angle);
+    self.velocity = velocity;
+    self.error = 0f32;
+  }
+
+  pub fn update(&mut self, measured_angle: f32, dt: f32) {
+    self.error = wrap_rads(measured_angle - self.angle);
+    self.velocity += self.ki * self.error * dt;
+    self.angle = norm_rads(self.angle + (self.velocity + self.kp * self.error) * dt);
+  }
+
+  pub fn get_angle(&self) -> f32 {
+    self.angle
+  }
+
+  pub fn get_velocity(&self) -> f32 {
+    self.velocity
+  }
+
+  // Rate of change of the tracked velocity at the last update.
+  pub fn get_acceleration(&self) -> f32 {
+    self.ki * self.error
+  }
+
+  // Difference between the last measurement and the tracked angle.
+  pub fn get_error(&self) -> f32 {
+    self.error
+  }
+}
+
+// Non-linear flux observer (Ortega et al., 2011). Integrates the stator
+// voltage equation and corrects the estimate towards the known magnitude of
+// the magnet flux, whose direction is the rotor's electrical angle. A PLL
+// then smooths the angle and gives the electrical velocity.
+pub struct FluxObserver {
+  model: MotorModel,
+  gain: f32,
+  flux: (f32, f32),
+  pll: Pll,
+  // Low-pass filtered PLL error, used to decide whether it is locked.
+  lock_error: f32,
+}
+impl FluxObserver {
+  pub fn new(model: MotorModel, gain: f32, pll_bandwidth: f32) -> Self {
+    Self {
+      model,
+      gain,
+      flux: (0f32, 0f32),
+      pll: Pll::new(pll_bandwidth),
+      lock_error: PI2,
+    }
+  }
+
+  pub fn set_model(&mut self, model: MotorModel, gain: f32, pll_bandwidth: f32) {
+    self.model = model;
+    self.gain = gain;
+    self.pll.set_bandwidth(pll_bandwidth);
+  }
+
+  // Starts from the magnet flux at a known electrical angle, such as the
+  // open-loop angle during startup.
+  pub fn reset(&mut self, angle: f32, velocity: f32) {
+    self.flux = (
+      self.model.flux_linkage * libm::cosf(angle),
+      self.model.flux_linkage * libm::sinf(angle),
+    );
+    self.pll.reset(angle, velocity);
+    self.lock_error = PI2;
+  }
+
+  // Steps the observer with the alpha/beta voltages (V) applied and
+  // currents (A) measured over the last `dt` seconds.
+  pub fn update(&mut self, voltage: (f32, f32), current: (f32, f32), dt: f32) {
+    let MotorModel {
+      resistance,
+      inductance,
+      flux_linkage,
+    } = self.model;
+
+    let magnet = (
+      self.flux.0 - inductance * current.0,
+      self.flux.1 - inductance * current.1,
+    );
+    let magnitude_error = flux_linkage * flux_linkage - (magnet.0 * magnet.0 + magnet.1 * magnet.1);
+    let correction = self.gain / 2f32 * magnitude_error;
+
+    self.flux.0 += (voltage.0 - resistance * current.0 + correction * magnet.0) * dt;
+    self.flux.1 += (voltage.1 - resistance * current.1 + correction * magnet.1) * dt;
+
+    let magnet = (
+      self.flux.0 - inductance * current.0,
+      self.flux.1 - inductance * current.1,
+    );
+    self.pll.update(libm::atan2f(magnet.1, magnet.0), dt);
+
+    // About 10 ms to settle.
+    let alpha = (dt * 100f32).min(1f32);
+    self.lock_error += (libm::fabsf(self.pll.get_error()) - self.lock_error) * alpha;
+  }
+
+  pub fn get_angle(&self) -> f32 {
+    self.pll.get_angle()
+  }
+
+  // Electrical velocity (rad/s).
+  pub fn get_velocity(&self) -> f32 {
+    self.pll.get_velocity()
+  }
+
+  pub fn is_locked(&self, max_error: f32) -> bool {
+    self.lock_error < max_error
+  }
+}
@@ -0,0 +1,169 @@
+use core::ptr::{read_volatile, write_volatile};
+
+use bldc_protocol::{frame::crc16, ParamValue};
+use stm32f303_api::{Error, Result};
+
+use crate::params::{ParamId, Params, ALL_PARAMS, PARAM_COUNT};
+
+// Flash interface registers (RM0316 section 4.5).
+const FLASH_KEYR: *mut u32 = 0x4002_2004 as *mut u32;
+const FLASH_SR: *mut u32 = 0x4002_200C as *mut u32;
+const FLASH_CR: *mut u32 = 0x4002_2010 as *mut u32;
+const FLASH_AR: *mut u32 = 0x4002_2014 as *mut u32;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+const SR_BSY: u32 = 1 << 0;
+const SR_PGERR: u32 = 1 << 2;
+const SR_WRPRTERR: u32 = 1 << 4;
+const SR_EOP: u32 = 1 << 5;
+
+const CR_PG: u32 = 1 << 0;
+const CR_PER: u32 = 1 << 1;
+const CR_STRT: u32 = 1 << 6;
+const CR_LOCK: u32 = 1 << 7;
+
+// The last 2K page of flash, kept out of the program image by memory.x.
+const PAGE_ADDR: u32 = 0x0803_F800;
+
+const MAGIC: u32 = 0x4244_5041;
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 8;
+const IMAGE_LEN: usize = HEADER_LEN + PARAM_COUNT * ENTRY_LEN + 4;
+
+// Image layout, little-endian:
+//   magic u32, version u16, count u16,
+//   count * (key u16, type u8, reserved u8, value u32),
+//   crc16 u16 of everything before it, padding u16
+fn serialize(params: &Params) -> [u8; IMAGE_LEN] {
+  let mut image = [0u8; IMAGE_LEN];
+  image[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+  image[4..6].copy_from_slice(&VERSION.to_le_bytes());
+  image[6..8].copy_from_slice(&(PARAM_COUNT as u16).to_le_bytes());
+
+  for (i, id) in ALL_PARAMS.iter().enumerate() {
+    let (tag, raw) = match params.get(*id) {
+      ParamValue::F32(v) => (0u8, v.to_bits()),
+      ParamValue::U32(v) => (1u8, v),
+      ParamValue::Bool(v) => (2u8, v as u32),
+    };
+    let entry = &mut image[HEADER_LEN + i * ENTRY_LEN..HEADER_LEN + (i + 1) * ENTRY_LEN];
+    entry[0..2].copy_from_slice(&id.def().key.to_le_bytes());
+    entry[2] = tag;
+    entry[4..8].copy_from_slice(&raw.to_le_bytes());
+  }
+
+  let crc_pos = IMAGE_LEN - 4;
+  let crc = crc16(&image[..crc_pos]);
+  image[crc_pos..crc_pos + 2].copy_from_slice(&crc.to_le_bytes());
+  image
+}
+
+fn read_flash_byte(offset: usize) -> u8 {
+  unsafe { read_volatile((PAGE_ADDR as usize + offset) as *const u8) }
+}
+
+// Applies stored values to `params`, returning how many were applied. Entries
+// for unknown keys or out of range are skipped so that a store written by
+// other firmware versions still loads. The values are applied together, and
+// if they are inconsistent as a whole `params` is left unchanged.
+pub fn load(params: &mut Params) -> Result<usize> {
+  let mut header = [0u8; HEADER_LEN];
+  for (i, byte) in header.iter_mut().enumerate() {
+    *byte = read_flash_byte(i);
+  }
+
+  let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+  let version = u16::from_le_bytes([header[4], header[5]]);
+  let count = u16::from_le_bytes([header[6], header[7]]) as usize;
+  if magic != MAGIC || version != VERSION || count > 255 {
+    return Err(Error::new("No stored parameters"));
+  }
+
+  let crc_pos = HEADER_LEN + count * ENTRY_LEN;
+  let mut crc_data = [0u8; HEADER_LEN + 255 * ENTRY_LEN];
+  for (i, byte) in crc_data[..crc_pos].iter_mut().enumerate() {
+    *byte = read_flash_byte(i);
+  }
+  let stored_crc = u16::from_le_bytes([read_flash_byte(crc_pos), read_flash_byte(crc_pos + 1)]);
+  if crc16(&crc_data[..crc_pos]) != stored_crc {
+    return Err(Error::new("Stored parameters are corrupt"));
+  }
+
+  let entries = crc_data[HEADER_LEN..crc_pos]
+    .chunks(ENTRY_LEN)
+    .filter_map(|entry| {
+      let key = u16::from_le_bytes([entry[0], entry[1]]);
+      let raw = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+      let value = match entry[2] {
+        0 => ParamValue::F32(f32::from_bits(raw)),
+        1 => ParamValue::U32(raw),
+        2 => ParamValue::Bool(raw != 0),
+        _ => return None,
+      };
+      Some((ParamId::from_key(key)?, value))
+    });
+
+  params.set_all(entries).map_err(|e| Error::new(e.message()))
+}
+
+fn wait_until_idle() -> Result<()> {
+  while unsafe { read_volatile(FLASH_SR) } & SR_BSY != 0 {}
+
+  let sr = unsafe { read_volatile(FLASH_SR) };
+  unsafe { write_volatile(FLASH_SR, SR_EOP | SR_PGERR | SR_WRPRTERR) };
+
+  if sr & SR_WRPRTERR != 0 {
+    return Err(Error::new("Flash write protected"));
+  }
+  if sr & SR_PGERR != 0 {
+    return Err(Error::new("Flash programming error"));
+  }
+  Ok(())
+}
+
+fn write_page(image: &[u8]) -> Result<()> {
+  unsafe {
+    // Erase
+    write_volatile(FLASH_CR, CR_PER);
+    write_volatile(FLASH_AR, PAGE_ADDR);
+    write_volatile(FLASH_CR, CR_PER | CR_STRT);
+  }
+  wait_until_idle()?;
+
+  unsafe { write_volatile(FLASH_CR, CR_PG) };
+  for (i, half_word) in image.chunks(2).enumerate() {
+    let value = u16::from_le_bytes([half_word[0], *half_word.get(1).unwrap_or(&0xFF)]);
+    unsafe { write_volatile((PAGE_ADDR as usize + i * 2) as *mut u16, value) };
+    wait_until_idle()?;
+  }
+
+  for (i, byte) in image.iter().enumerate() {
+    if read_flash_byte(i) != *byte {
+      return Err(Error::new("Flash verify failed"));
+    }
+  }
+
+  Ok(())
+}
+
+// Erases the parameter page and writes every parameter to it. The CPU stalls
+// while the page erases, so only call this with the motor stopped.
+pub fn save(params: &Params) -> Result<()> {
+  let image = serialize(params);
+
+  wait_until_idle()?;
+  unsafe {
+    if read_volatile(FLASH_CR) & CR_LOCK != 0 {
+      write_volatile(FLASH_KEYR, KEY1);
+      write_volatile(FLASH_KEYR, KEY2);
+    }
+  }
+
+  let result = write_page(&image);
+
+  unsafe { write_volatile(FLASH_CR, CR_LOCK) };
+  result
+}
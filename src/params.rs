@@ -0,0 +1,918 @@
+use bldc_control::fault_policy::NUM_FAULT_SOURCES;
+use bldc_protocol::ParamValue;
+
+use crate::math::{PI1_3, PI2};
+
+// Capacity of the calibration settling buffer; bounds `CalibrationNumSamples`.
+pub const MAX_CALIBRATION_SAMPLES: usize = 500;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ParamId {
+  CalibrationPower,
+  CalibrationSpeed,
+  CalibrationMaxTurn,
+  CalibrationMaxDeviation,
+  CalibrationNumSamples,
+  DemoMinPower,
+  DemoMaxPower,
+  DemoAccel,
+  ServoMaxPower,
+  PositionKp,
+  PositionKd,
+  VelocityKp,
+  VelocityKi,
+  VelocityTimeConstant,
+  PwmFrequency,
+  Deadtime,
+  PwmSampleAdvance,
+  PwmMinLowSide,
+  StopMode,
+  StopRegenCurrent,
+  DeadtimeCompensation,
+  DeadtimeCompensationBand,
+  Modulation,
+  SixStepAdvance,
+  SensorType,
+  HallTimeout,
+  HallAngle1,
+  HallAngle2,
+  HallAngle3,
+  HallAngle4,
+  HallAngle5,
+  HallAngle6,
+  EncoderCountsPerRev,
+  CurrentSense,
+  CurrentShunt,
+  CurrentAmpGain,
+  SupplyVoltage,
+  MotorResistance,
+  MotorInductance,
+  MotorFluxLinkage,
+  ObserverGain,
+  ObserverPllBandwidth,
+  SensorlessAlignPower,
+  SensorlessAlignTime,
+  SensorlessRampPower,
+  SensorlessRampAccel,
+  SensorlessHandoffSpeed,
+  SensorlessLockError,
+  FusionBlendStart,
+  FusionBlendEnd,
+  FusionModelWeight,
+  FusionMaxDeviation,
+  FusionMaxBadReadings,
+  FusionFallback,
+  MotionTracking,
+  MotionTrackingBandwidth,
+  MotionLatency,
+  TrajectoryShape,
+  TrajectoryMaxVelocity,
+  TrajectoryMaxAccel,
+  TrajectoryMaxJerk,
+  HomeOnStartup,
+  HomeDirection,
+  HomeSpeed,
+  HomeMaxPower,
+  HomeStallTime,
+  HomeStallDistance,
+  HomeMaxTravel,
+  HomeBackoff,
+  HomeOffset,
+  ImpedanceMaxEffort,
+  HapticDetentCount,
+  HapticDetentStrength,
+  HapticDetentWidth,
+  HapticWalls,
+  HapticMinPosition,
+  HapticMaxPosition,
+  HapticWallStiffness,
+  HapticFriction,
+  HapticViscosity,
+  HapticMaxEffort,
+  OpenLoopFrequency,
+  OpenLoopAccel,
+  OpenLoopBoost,
+  OpenLoopVoltsPerHertz,
+  OpenLoopMaxPower,
+  StepDir,
+  StepDirStepsPerRev,
+  StepDirInvert,
+  StepDirEnableActiveLow,
+  SetpointSource,
+  SetpointTarget,
+  SetpointMin,
+  SetpointMax,
+  SetpointTimeout,
+  RcMinPulse,
+  RcCenterPulse,
+  RcMaxPulse,
+  RcDeadband,
+  AnalogMinVolts,
+  AnalogMaxVolts,
+  AnalogFilterTime,
+  AnalogDeadband,
+  BusVoltageMeasured,
+  BusVoltageDivider,
+  BusVoltageCompensation,
+  BusOvervoltage,
+  BusUndervoltage,
+  ThermalAmbient,
+  ThermalWindingResistance,
+  ThermalWindingTimeConstant,
+  ThermalMotorDerateTemp,
+  ThermalMotorTripTemp,
+  ThermalFetDerateTemp,
+  ThermalFetTripTemp,
+  ThermalFetNtc,
+  ThermalMotorNtc,
+  ThermalNtcNominal,
+  ThermalNtcBeta,
+  ThermalNtcPullup,
+  FaultDriverAction,
+  FaultDriverDebounce,
+  FaultDriverTempAction,
+  FaultDriverTempDebounce,
+  FaultAngleSensorAction,
+  FaultAngleSensorDebounce,
+  FaultOvervoltageAction,
+  FaultOvervoltageDebounce,
+  FaultUndervoltageAction,
+  FaultUndervoltageDebounce,
+  FaultOverspeedAction,
+  FaultOverspeedDebounce,
+  FaultLoopOverrunAction,
+  FaultLoopOverrunDebounce,
+  FaultOvertemperatureAction,
+  FaultOvertemperatureDebounce,
+  FaultMaxVelocity,
+  FaultMaxLoopTime,
+  FaultDeratePower,
+  CanNodeId,
+  CanOpen,
+}
+
+pub const PARAM_COUNT: usize = 141;
+pub const ALL_PARAMS: [ParamId; PARAM_COUNT] = [
+  ParamId::CalibrationPower,
+  ParamId::CalibrationSpeed,
+  ParamId::CalibrationMaxTurn,
+  ParamId::CalibrationMaxDeviation,
+  ParamId::CalibrationNumSamples,
+  ParamId::DemoMinPower,
+  ParamId::DemoMaxPower,
+  ParamId::DemoAccel,
+  ParamId::ServoMaxPower,
+  ParamId::PositionKp,
+  ParamId::PositionKd,
+  ParamId::VelocityKp,
+  ParamId::VelocityKi,
+  ParamId::VelocityTimeConstant,
+  ParamId::PwmFrequency,
+  ParamId::Deadtime,
+  ParamId::PwmSampleAdvance,
+  ParamId::PwmMinLowSide,
+  ParamId::StopMode,
+  ParamId::StopRegenCurrent,
+  ParamId::DeadtimeCompensation,
+  ParamId::DeadtimeCompensationBand,
+  ParamId::Modulation,
+  ParamId::SixStepAdvance,
+  ParamId::SensorType,
+  ParamId::HallTimeout,
+  ParamId::HallAngle1,
+  ParamId::HallAngle2,
+  ParamId::HallAngle3,
+  ParamId::HallAngle4,
+  ParamId::HallAngle5,
+  ParamId::HallAngle6,
+  ParamId::EncoderCountsPerRev,
+  ParamId::CurrentSense,
+  ParamId::CurrentShunt,
+  ParamId::CurrentAmpGain,
+  ParamId::SupplyVoltage,
+  ParamId::MotorResistance,
+  ParamId::MotorInductance,
+  ParamId::MotorFluxLinkage,
+  ParamId::ObserverGain,
+  ParamId::ObserverPllBandwidth,
+  ParamId::SensorlessAlignPower,
+  ParamId::SensorlessAlignTime,
+  ParamId::SensorlessRampPower,
+  ParamId::SensorlessRampAccel,
+  ParamId::SensorlessHandoffSpeed,
+  ParamId::SensorlessLockError,
+  ParamId::FusionBlendStart,
+  ParamId::FusionBlendEnd,
+  ParamId::FusionModelWeight,
+  ParamId::FusionMaxDeviation,
+  ParamId::FusionMaxBadReadings,
+  ParamId::FusionFallback,
+  ParamId::MotionTracking,
+  ParamId::MotionTrackingBandwidth,
+  ParamId::MotionLatency,
+  ParamId::TrajectoryShape,
+  ParamId::TrajectoryMaxVelocity,
+  ParamId::TrajectoryMaxAccel,
+  ParamId::TrajectoryMaxJerk,
+  ParamId::HomeOnStartup,
+  ParamId::HomeDirection,
+  ParamId::HomeSpeed,
+  ParamId::HomeMaxPower,
+  ParamId::HomeStallTime,
+  ParamId::HomeStallDistance,
+  ParamId::HomeMaxTravel,
+  ParamId::HomeBackoff,
+  ParamId::HomeOffset,
+  ParamId::ImpedanceMaxEffort,
+  ParamId::HapticDetentCount,
+  ParamId::HapticDetentStrength,
+  ParamId::HapticDetentWidth,
+  ParamId::HapticWalls,
+  ParamId::HapticMinPosition,
+  ParamId::HapticMaxPosition,
+  ParamId::HapticWallStiffness,
+  ParamId::HapticFriction,
+  ParamId::HapticViscosity,
+  ParamId::HapticMaxEffort,
+  ParamId::OpenLoopFrequency,
+  ParamId::OpenLoopAccel,
+  ParamId::OpenLoopBoost,
+  ParamId::OpenLoopVoltsPerHertz,
+  ParamId::OpenLoopMaxPower,
+  ParamId::StepDir,
+  ParamId::StepDirStepsPerRev,
+  ParamId::StepDirInvert,
+  ParamId::StepDirEnableActiveLow,
+  ParamId::SetpointSource,
+  ParamId::SetpointTarget,
+  ParamId::SetpointMin,
+  ParamId::SetpointMax,
+  ParamId::SetpointTimeout,
+  ParamId::RcMinPulse,
+  ParamId::RcCenterPulse,
+  ParamId::RcMaxPulse,
+  ParamId::RcDeadband,
+  ParamId::AnalogMinVolts,
+  ParamId::AnalogMaxVolts,
+  ParamId::AnalogFilterTime,
+  ParamId::AnalogDeadband,
+  ParamId::BusVoltageMeasured,
+  ParamId::BusVoltageDivider,
+  ParamId::BusVoltageCompensation,
+  ParamId::BusOvervoltage,
+  ParamId::BusUndervoltage,
+  ParamId::ThermalAmbient,
+  ParamId::ThermalWindingResistance,
+  ParamId::ThermalWindingTimeConstant,
+  ParamId::ThermalMotorDerateTemp,
+  ParamId::ThermalMotorTripTemp,
+  ParamId::ThermalFetDerateTemp,
+  ParamId::ThermalFetTripTemp,
+  ParamId::ThermalFetNtc,
+  ParamId::ThermalMotorNtc,
+  ParamId::ThermalNtcNominal,
+  ParamId::ThermalNtcBeta,
+  ParamId::ThermalNtcPullup,
+  ParamId::FaultDriverAction,
+  ParamId::FaultDriverDebounce,
+  ParamId::FaultDriverTempAction,
+  ParamId::FaultDriverTempDebounce,
+  ParamId::FaultAngleSensorAction,
+  ParamId::FaultAngleSensorDebounce,
+  ParamId::FaultOvervoltageAction,
+  ParamId::FaultOvervoltageDebounce,
+  ParamId::FaultUndervoltageAction,
+  ParamId::FaultUndervoltageDebounce,
+  ParamId::FaultOverspeedAction,
+  ParamId::FaultOverspeedDebounce,
+  ParamId::FaultLoopOverrunAction,
+  ParamId::FaultLoopOverrunDebounce,
+  ParamId::FaultOvertemperatureAction,
+  ParamId::FaultOvertemperatureDebounce,
+  ParamId::FaultMaxVelocity,
+  ParamId::FaultMaxLoopTime,
+  ParamId::FaultDeratePower,
+  ParamId::CanNodeId,
+  ParamId::CanOpen,
+];
+
+#[derive(Copy, Clone)]
+pub enum Range {
+  F32 { min: f32, max: f32, default: f32 },
+  U32 { min: u32, max: u32, default: u32 },
+  Bool { default: bool },
+}
+
+pub struct ParamDef {
+  // Stable identifier used in flash; never reuse a key for a different
+  // parameter.
+  pub key: u16,
+  pub name: &'static str,
+  pub unit: &'static str,
+  pub range: Range,
+}
+
+fn f32_def(
+  key: u16,
+  name: &'static str,
+  unit: &'static str,
+  min: f32,
+  max: f32,
+  default: f32,
+) -> ParamDef {
+  ParamDef {
+    key,
+    name,
+    unit,
+    range: Range::F32 { min, max, default },
+  }
+}
+
+fn u32_def(
+  key: u16,
+  name: &'static str,
+  unit: &'static str,
+  min: u32,
+  max: u32,
+  default: u32,
+) -> ParamDef {
+  ParamDef {
+    key,
+    name,
+    unit,
+    range: Range::U32 { min, max, default },
+  }
+}
+
+fn hall_angle_def(key: u16, name: &'static str, sector: u32) -> ParamDef {
+  f32_def(key, name, "rad", 0f32, PI2, PI1_3 * (sector as f32 + 0.5))
+}
+
+// Indexed by hall state - 1.
+pub const HALL_ANGLE_PARAMS: [ParamId; 6] = [
+  ParamId::HallAngle1,
+  ParamId::HallAngle2,
+  ParamId::HallAngle3,
+  ParamId::HallAngle4,
+  ParamId::HallAngle5,
+  ParamId::HallAngle6,
+];
+
+// Fault policy rules, in `FaultSource::ALL` order.
+pub const FAULT_ACTION_PARAMS: [ParamId; NUM_FAULT_SOURCES] = [
+  ParamId::FaultDriverAction,
+  ParamId::FaultDriverTempAction,
+  ParamId::FaultAngleSensorAction,
+  ParamId::FaultOvervoltageAction,
+  ParamId::FaultUndervoltageAction,
+  ParamId::FaultOverspeedAction,
+  ParamId::FaultLoopOverrunAction,
+  ParamId::FaultOvertemperatureAction,
+];
+pub const FAULT_DEBOUNCE_PARAMS: [ParamId; NUM_FAULT_SOURCES] = [
+  ParamId::FaultDriverDebounce,
+  ParamId::FaultDriverTempDebounce,
+  ParamId::FaultAngleSensorDebounce,
+  ParamId::FaultOvervoltageDebounce,
+  ParamId::FaultUndervoltageDebounce,
+  ParamId::FaultOverspeedDebounce,
+  ParamId::FaultLoopOverrunDebounce,
+  ParamId::FaultOvertemperatureDebounce,
+];
+
+impl ParamId {
+  pub fn from_index(index: u16) -> Option<ParamId> {
+    ALL_PARAMS.get(index as usize).copied()
+  }
+
+  pub fn from_key(key: u16) -> Option<ParamId> {
+    ALL_PARAMS.iter().copied().find(|id| id.def().key == key)
+  }
+
+  pub fn def(&self) -> ParamDef {
+    match self {
+      ParamId::CalibrationPower => f32_def(1, "cal.power", "", 0f32, 1f32, 0.1),
+      ParamId::CalibrationSpeed => f32_def(2, "cal.speed", "rad/step", 0.0001, 0.05, 0.002),
+      ParamId::CalibrationMaxTurn => {
+        f32_def(3, "cal.max_turn", "rad", 0.1, PI2 * 10f32, PI2 * 2f32)
+      }
+      ParamId::CalibrationMaxDeviation => {
+        f32_def(4, "cal.max_deviation", "rad", 0.000001, 0.1, PI2 / 10000f32)
+      }
+      ParamId::CalibrationNumSamples => u32_def(
+        5,
+        "cal.num_samples",
+        "",
+        10,
+        MAX_CALIBRATION_SAMPLES as u32,
+        MAX_CALIBRATION_SAMPLES as u32,
+      ),
+      ParamId::DemoMinPower => f32_def(6, "demo.min_power", "", 0f32, 1f32, 0f32),
+      ParamId::DemoMaxPower => f32_def(7, "demo.max_power", "", 0f32, 1f32, 0.2),
+      ParamId::DemoAccel => f32_def(8, "demo.accel", "1/step", 0f32, 0.01, 0.0001),
+      ParamId::ServoMaxPower => f32_def(9, "servo.max_power", "", 0f32, 1f32, 0.3),
+      ParamId::PositionKp => f32_def(10, "servo.position_kp", "1/rad", 0f32, 100f32, 0.5),
+      ParamId::PositionKd => f32_def(11, "servo.position_kd", "s/rad", 0f32, 10f32, 0.02),
+      ParamId::VelocityKp => f32_def(12, "servo.velocity_kp", "s/rad", 0f32, 10f32, 0.01),
+      ParamId::VelocityKi => f32_def(13, "servo.velocity_ki", "1/rad", 0f32, 100f32, 0.05),
+      ParamId::VelocityTimeConstant => f32_def(14, "motion.velocity_tc", "s", 0.0001, 1f32, 0.01),
+      ParamId::PwmFrequency => f32_def(15, "pwm.frequency", "Hz", 1000f32, 100000f32, 20000f32),
+      ParamId::Deadtime => u32_def(16, "pwm.deadtime", "ns", 0, 5000, 500),
+      ParamId::CanNodeId => u32_def(17, "can.node_id", "", 1, 63, 1),
+      ParamId::CanOpen => ParamDef {
+        key: 18,
+        name: "can.canopen",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::PwmSampleAdvance => u32_def(19, "pwm.sample_advance", "ns", 0, 5000, 0),
+      ParamId::PwmMinLowSide => u32_def(20, "pwm.min_low_side", "ns", 0, 10000, 1000),
+      // 0 coast, 1 short brake, 2 regenerative brake. Regenerative braking
+      // needs current sensing, and short brakes without it.
+      ParamId::StopMode => u32_def(21, "stop.mode", "", 0, 2, 0),
+      ParamId::StopRegenCurrent => f32_def(22, "stop.regen_current", "A", 0f32, 50f32, 2f32),
+      ParamId::DeadtimeCompensation => ParamDef {
+        key: 23,
+        name: "pwm.deadtime_comp",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::DeadtimeCompensationBand => {
+        f32_def(24, "pwm.deadtime_comp_band", "A", 0f32, 10f32, 0.2)
+      }
+      // 0 sinusoidal, 1 six-step with complementary chopping, 2 six-step
+      // chopping the high side only.
+      ParamId::Modulation => u32_def(25, "drive.modulation", "", 0, 2, 0),
+      ParamId::SixStepAdvance => f32_def(26, "drive.six_step_advance", "rad", -PI1_3, PI1_3, 0f32),
+      // 0 magnetic encoder on SPI1, 1 hall sensors, 2 quadrature encoder,
+      // 3 none (sensorless mode only).
+      ParamId::SensorType => u32_def(27, "sensor.type", "", 0, 3, 0),
+      ParamId::HallTimeout => f32_def(28, "hall.timeout", "s", 0.001, 1f32, 0.1),
+      // Electrical angle in the middle of each hall state, learned during
+      // calibration. The defaults suit sensors 120 degrees apart.
+      ParamId::HallAngle1 => hall_angle_def(29, "hall.angle_1", 0),
+      ParamId::HallAngle2 => hall_angle_def(30, "hall.angle_2", 2),
+      ParamId::HallAngle3 => hall_angle_def(31, "hall.angle_3", 1),
+      ParamId::HallAngle4 => hall_angle_def(32, "hall.angle_4", 4),
+      ParamId::HallAngle5 => hall_angle_def(33, "hall.angle_5", 5),
+      ParamId::HallAngle6 => hall_angle_def(34, "hall.angle_6", 3),
+      // Counted on every edge of both channels, so four per line.
+      ParamId::EncoderCountsPerRev => u32_def(35, "encoder.counts_per_rev", "", 4, 1_000_000, 4096),
+      ParamId::CurrentSense => ParamDef {
+        key: 36,
+        name: "current.enabled",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::CurrentShunt => f32_def(37, "current.shunt", "ohm", 0.0001, 1f32, 0.007),
+      // Negative when the amplifier output falls as current flows into the
+      // motor, as with the DRV8305.
+      ParamId::CurrentAmpGain => f32_def(38, "current.amp_gain", "V/V", -80f32, 80f32, -10f32),
+      // Assumed when the bus voltage is not measured, and the voltage power
+      // scales are relative to with compensation on.
+      ParamId::SupplyVoltage => f32_def(39, "motor.supply_voltage", "V", 1f32, 60f32, 24f32),
+      ParamId::MotorResistance => f32_def(40, "motor.resistance", "ohm", 0.001, 100f32, 0.5),
+      ParamId::MotorInductance => f32_def(41, "motor.inductance", "H", 0.000001, 0.1, 0.0002),
+      ParamId::MotorFluxLinkage => f32_def(42, "motor.flux_linkage", "Wb", 0.00001, 1f32, 0.003),
+      ParamId::ObserverGain => f32_def(43, "observer.gain", "", 0f32, 1e10, 1e8),
+      ParamId::ObserverPllBandwidth => f32_def(
+        44,
+        "observer.pll_bandwidth",
+        "rad/s",
+        10f32,
+        10000f32,
+        1000f32,
+      ),
+      ParamId::SensorlessAlignPower => f32_def(45, "sensorless.align_power", "", 0f32, 1f32, 0.1),
+      ParamId::SensorlessAlignTime => f32_def(46, "sensorless.align_time", "s", 0f32, 5f32, 0.5),
+      ParamId::SensorlessRampPower => f32_def(47, "sensorless.ramp_power", "", 0f32, 1f32, 0.15),
+      // Electrical, as are the handoff speed and lock error.
+      ParamId::SensorlessRampAccel => f32_def(
+        48,
+        "sensorless.ramp_accel",
+        "rad/s^2",
+        1f32,
+        100000f32,
+        500f32,
+      ),
+      ParamId::SensorlessHandoffSpeed => f32_def(
+        49,
+        "sensorless.handoff_speed",
+        "rad/s (electrical)",
+        10f32,
+        10000f32,
+        300f32,
+      ),
+      ParamId::SensorlessLockError => f32_def(50, "sensorless.lock_error", "rad", 0.01, 1f32, 0.2),
+      // Mechanical speeds over which the observer is blended into the
+      // sensor's angle, up to the model weight.
+      ParamId::FusionBlendStart => {
+        f32_def(51, "fusion.blend_start", "rad/s", 0f32, 10000f32, 50f32)
+      }
+      ParamId::FusionBlendEnd => f32_def(52, "fusion.blend_end", "rad/s", 0f32, 10000f32, 150f32),
+      ParamId::FusionModelWeight => f32_def(53, "fusion.model_weight", "", 0f32, 1f32, 0.2),
+      ParamId::FusionMaxDeviation => f32_def(54, "fusion.max_deviation", "rad", 0.001, PI2, 0.3),
+      ParamId::FusionMaxBadReadings => u32_def(55, "fusion.max_bad_readings", "", 1, 10000, 10),
+      ParamId::FusionFallback => ParamDef {
+        key: 56,
+        name: "fusion.fallback",
+        unit: "",
+        range: Range::Bool { default: true },
+      },
+      ParamId::MotionTracking => ParamDef {
+        key: 57,
+        name: "motion.tracking",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::MotionTrackingBandwidth => f32_def(
+        58,
+        "motion.tracking_bandwidth",
+        "rad/s",
+        1f32,
+        10000f32,
+        300f32,
+      ),
+      // From reading the angle sensor to the next PWM update.
+      ParamId::MotionLatency => f32_def(59, "motion.latency", "s", 0f32, 0.01, 0f32),
+      // 0 steps straight to the target, 1 trapezoidal profile, 2 S-curve.
+      ParamId::TrajectoryShape => u32_def(60, "traj.shape", "", 0, 2, 0),
+      ParamId::TrajectoryMaxVelocity => {
+        f32_def(61, "traj.max_velocity", "rad/s", 0.01, 10000f32, 20f32)
+      }
+      ParamId::TrajectoryMaxAccel => f32_def(62, "traj.max_accel", "rad/s^2", 0.01, 1e6, 100f32),
+      ParamId::TrajectoryMaxJerk => f32_def(63, "traj.max_jerk", "rad/s^3", 0.01, 1e8, 2000f32),
+      // Home after the startup calibration, rather than running the demo.
+      ParamId::HomeOnStartup => ParamDef {
+        key: 64,
+        name: "home.on_startup",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      // 0 seeks the stop in the positive direction, 1 in the negative.
+      ParamId::HomeDirection => u32_def(65, "home.direction", "", 0, 1, 0),
+      ParamId::HomeSpeed => f32_def(66, "home.speed", "rad/s", 0.01, 100f32, 2f32),
+      ParamId::HomeMaxPower => f32_def(67, "home.max_power", "", 0f32, 1f32, 0.1),
+      // A stall is the effort saturating while the rotor moves less than the
+      // stall distance for the stall time.
+      ParamId::HomeStallTime => f32_def(68, "home.stall_time", "s", 0.01, 10f32, 0.2),
+      ParamId::HomeStallDistance => f32_def(69, "home.stall_distance", "rad", 0.0001, 1f32, 0.01),
+      ParamId::HomeMaxTravel => f32_def(70, "home.max_travel", "rad", 0.1, 100000f32, PI2 * 50f32),
+      ParamId::HomeBackoff => f32_def(71, "home.backoff", "rad", 0f32, 1000f32, 0.5),
+      // Distance from the stop back to the zero, against the seek direction.
+      ParamId::HomeOffset => f32_def(72, "home.offset", "rad", -100000f32, 100000f32, 0f32),
+      ParamId::ImpedanceMaxEffort => f32_def(73, "impedance.max_effort", "", 0f32, 1f32, 0.3),
+      // Detents per revolution, or 0 for none.
+      ParamId::HapticDetentCount => u32_def(74, "haptic.detent_count", "", 0, 1000, 12),
+      ParamId::HapticDetentStrength => f32_def(75, "haptic.detent_strength", "", 0f32, 1f32, 0.1),
+      // Fraction of the detent spacing each detent pulls within.
+      ParamId::HapticDetentWidth => f32_def(76, "haptic.detent_width", "", 0f32, 1f32, 1f32),
+      ParamId::HapticWalls => ParamDef {
+        key: 77,
+        name: "haptic.walls",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::HapticMinPosition => f32_def(
+        78,
+        "haptic.min_position",
+        "rad",
+        -100000f32,
+        100000f32,
+        0f32,
+      ),
+      ParamId::HapticMaxPosition => {
+        f32_def(79, "haptic.max_position", "rad", -100000f32, 100000f32, PI2)
+      }
+      ParamId::HapticWallStiffness => {
+        f32_def(80, "haptic.wall_stiffness", "1/rad", 0f32, 1000f32, 2f32)
+      }
+      ParamId::HapticFriction => f32_def(81, "haptic.friction", "", 0f32, 1f32, 0f32),
+      ParamId::HapticViscosity => f32_def(82, "haptic.viscosity", "s/rad", 0f32, 10f32, 0f32),
+      ParamId::HapticMaxEffort => f32_def(83, "haptic.max_effort", "", 0f32, 1f32, 0.3),
+      // Electrical, signed for direction.
+      ParamId::OpenLoopFrequency => {
+        f32_def(84, "open_loop.frequency", "Hz", -1000f32, 1000f32, 5f32)
+      }
+      ParamId::OpenLoopAccel => f32_def(85, "open_loop.accel", "Hz/s", 0.1, 10000f32, 10f32),
+      // Power at standstill, to overcome the winding resistance.
+      ParamId::OpenLoopBoost => f32_def(86, "open_loop.boost", "", 0f32, 1f32, 0.05),
+      // Power added per hertz, for the rising back-EMF.
+      ParamId::OpenLoopVoltsPerHertz => {
+        f32_def(87, "open_loop.volts_per_hertz", "1/Hz", 0f32, 1f32, 0.002)
+      }
+      ParamId::OpenLoopMaxPower => f32_def(88, "open_loop.max_power", "", 0f32, 1f32, 0.3),
+      // Whether a step/direction input is fitted; it runs once calibrated.
+      ParamId::StepDir => ParamDef {
+        key: 89,
+        name: "step_dir.enabled",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::StepDirStepsPerRev => u32_def(90, "step_dir.steps_per_rev", "", 1, 1_000_000, 3200),
+      ParamId::StepDirInvert => ParamDef {
+        key: 91,
+        name: "step_dir.invert",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::StepDirEnableActiveLow => ParamDef {
+        key: 92,
+        name: "step_dir.enable_active_low",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      // 0 takes targets only from commands, 1 from an RC pulse input, 2 from
+      // an analog input.
+      ParamId::SetpointSource => u32_def(93, "setpoint.source", "", 0, 2, 0),
+      // 0 velocity, 1 position, 2 torque; the input range maps onto the
+      // setpoint range in that target's units.
+      ParamId::SetpointTarget => u32_def(94, "setpoint.target", "", 0, 2, 0),
+      ParamId::SetpointMin => f32_def(95, "setpoint.min", "", -100000f32, 100000f32, -10f32),
+      ParamId::SetpointMax => f32_def(96, "setpoint.max", "", -100000f32, 100000f32, 10f32),
+      // Time without a valid input before the motor is stopped.
+      ParamId::SetpointTimeout => f32_def(97, "setpoint.timeout", "s", 0.01, 10f32, 0.1),
+      ParamId::RcMinPulse => f32_def(98, "rc.min_pulse", "us", 500f32, 2500f32, 1000f32),
+      ParamId::RcCenterPulse => f32_def(99, "rc.center_pulse", "us", 500f32, 2500f32, 1500f32),
+      ParamId::RcMaxPulse => f32_def(100, "rc.max_pulse", "us", 500f32, 2500f32, 2000f32),
+      ParamId::RcDeadband => f32_def(101, "rc.deadband", "us", 0f32, 500f32, 10f32),
+      // Input voltages at the ends of the setpoint range.
+      ParamId::AnalogMinVolts => f32_def(102, "analog.min_volts", "V", 0f32, 3.3, 0f32),
+      ParamId::AnalogMaxVolts => f32_def(103, "analog.max_volts", "V", 0f32, 3.3, 3.3),
+      ParamId::AnalogFilterTime => f32_def(104, "analog.filter_time", "s", 0f32, 1f32, 0.02),
+      // Fraction of the range the input must move by before the setpoint
+      // follows.
+      ParamId::AnalogDeadband => f32_def(105, "analog.deadband", "", 0f32, 0.5, 0.005),
+      ParamId::BusVoltageMeasured => ParamDef {
+        key: 106,
+        name: "vbus.enabled",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      // Bus voltage over the voltage at the ADC pin.
+      ParamId::BusVoltageDivider => f32_def(107, "vbus.divider", "", 1f32, 100f32, 11f32),
+      // Scales duty cycles by the supply voltage to the motor supply voltage,
+      // so commands give the same voltage on any supply.
+      ParamId::BusVoltageCompensation => ParamDef {
+        key: 108,
+        name: "vbus.compensation",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::BusOvervoltage => f32_def(109, "vbus.overvoltage", "V", 1f32, 100f32, 30f32),
+      ParamId::BusUndervoltage => f32_def(110, "vbus.undervoltage", "V", 0f32, 100f32, 8f32),
+      ParamId::ThermalAmbient => f32_def(111, "thermal.ambient", "C", -40f32, 100f32, 25f32),
+      // Winding to ambient, with the time constant of the winding's heating.
+      ParamId::ThermalWindingResistance => {
+        f32_def(112, "thermal.winding_resistance", "C/W", 0f32, 100f32, 2f32)
+      }
+      ParamId::ThermalWindingTimeConstant => f32_def(
+        113,
+        "thermal.winding_time_constant",
+        "s",
+        1f32,
+        10000f32,
+        120f32,
+      ),
+      // Derating runs from the derate temperature down to nothing at the trip
+      // temperature, where the motor is stopped.
+      ParamId::ThermalMotorDerateTemp => {
+        f32_def(114, "thermal.motor_derate_temp", "C", 0f32, 200f32, 100f32)
+      }
+      ParamId::ThermalMotorTripTemp => {
+        f32_def(115, "thermal.motor_trip_temp", "C", 0f32, 200f32, 120f32)
+      }
+      ParamId::ThermalFetDerateTemp => {
+        f32_def(116, "thermal.fet_derate_temp", "C", 0f32, 200f32, 90f32)
+      }
+      ParamId::ThermalFetTripTemp => {
+        f32_def(117, "thermal.fet_trip_temp", "C", 0f32, 200f32, 115f32)
+      }
+      ParamId::ThermalFetNtc => ParamDef {
+        key: 118,
+        name: "thermal.fet_ntc",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      ParamId::ThermalMotorNtc => ParamDef {
+        key: 119,
+        name: "thermal.motor_ntc",
+        unit: "",
+        range: Range::Bool { default: false },
+      },
+      // Resistance at 25 C; the thermistors pull down against the pull-up.
+      ParamId::ThermalNtcNominal => {
+        f32_def(120, "thermal.ntc_nominal", "ohm", 100f32, 1e6, 10000f32)
+      }
+      ParamId::ThermalNtcBeta => f32_def(121, "thermal.ntc_beta", "K", 1000f32, 10000f32, 3380f32),
+      ParamId::ThermalNtcPullup => f32_def(122, "thermal.ntc_pullup", "ohm", 100f32, 1e6, 10000f32),
+      // Actions are 0 log, 1 derate, 2 stop, 3 coast, 4 latch; debounce is
+      // the consecutive steps a condition must be present to trip.
+      ParamId::FaultDriverAction => u32_def(123, "fault.driver.action", "", 0, 4, 3),
+      ParamId::FaultDriverDebounce => u32_def(124, "fault.driver.debounce", "", 1, 10000, 1),
+      ParamId::FaultDriverTempAction => u32_def(125, "fault.driver_temp.action", "", 0, 4, 0),
+      ParamId::FaultDriverTempDebounce => {
+        u32_def(126, "fault.driver_temp.debounce", "", 1, 10000, 1)
+      }
+      ParamId::FaultAngleSensorAction => u32_def(127, "fault.angle_sensor.action", "", 0, 4, 0),
+      ParamId::FaultAngleSensorDebounce => {
+        u32_def(128, "fault.angle_sensor.debounce", "", 1, 10000, 1)
+      }
+      ParamId::FaultOvervoltageAction => u32_def(129, "fault.overvoltage.action", "", 0, 4, 2),
+      ParamId::FaultOvervoltageDebounce => {
+        u32_def(130, "fault.overvoltage.debounce", "", 1, 10000, 1)
+      }
+      ParamId::FaultUndervoltageAction => u32_def(131, "fault.undervoltage.action", "", 0, 4, 2),
+      ParamId::FaultUndervoltageDebounce => {
+        u32_def(132, "fault.undervoltage.debounce", "", 1, 10000, 1)
+      }
+      ParamId::FaultOverspeedAction => u32_def(133, "fault.overspeed.action", "", 0, 4, 2),
+      ParamId::FaultOverspeedDebounce => u32_def(134, "fault.overspeed.debounce", "", 1, 10000, 3),
+      ParamId::FaultLoopOverrunAction => u32_def(135, "fault.loop_overrun.action", "", 0, 4, 0),
+      ParamId::FaultLoopOverrunDebounce => {
+        u32_def(136, "fault.loop_overrun.debounce", "", 1, 10000, 3)
+      }
+      ParamId::FaultOvertemperatureAction => {
+        u32_def(137, "fault.overtemperature.action", "", 0, 4, 2)
+      }
+      ParamId::FaultOvertemperatureDebounce => {
+        u32_def(138, "fault.overtemperature.debounce", "", 1, 10000, 1)
+      }
+      ParamId::FaultMaxVelocity => {
+        f32_def(139, "fault.max_velocity", "rad/s", 0f32, 10000f32, 500f32)
+      }
+      ParamId::FaultMaxLoopTime => f32_def(140, "fault.max_loop_time", "s", 0.0001, 1f32, 0.01),
+      // Fraction of full power allowed while a derating fault is active.
+      ParamId::FaultDeratePower => f32_def(141, "fault.derate_power", "", 0f32, 1f32, 0.5),
+    }
+  }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ParamError {
+  WrongType,
+  OutOfRange,
+  Inconsistent,
+  Rejected,
+}
+impl ParamError {
+  pub fn message(&self) -> &'static str {
+    match self {
+      ParamError::WrongType => "Parameter value has the wrong type",
+      ParamError::OutOfRange => "Parameter value out of range",
+      ParamError::Inconsistent => "Parameter value conflicts with other parameters",
+      ParamError::Rejected => "Parameter value rejected by the hardware",
+    }
+  }
+}
+
+fn check_range(id: ParamId, value: ParamValue) -> Result<(), ParamError> {
+  match (id.def().range, value) {
+    (Range::F32 { min, max, .. }, ParamValue::F32(v)) => {
+      if v.is_nan() || v < min || v > max {
+        return Err(ParamError::OutOfRange);
+      }
+    }
+    (Range::U32 { min, max, .. }, ParamValue::U32(v)) => {
+      if v < min || v > max {
+        return Err(ParamError::OutOfRange);
+      }
+    }
+    (Range::Bool { .. }, ParamValue::Bool(_)) => {}
+    _ => return Err(ParamError::WrongType),
+  }
+  Ok(())
+}
+
+pub struct Params {
+  values: [ParamValue; PARAM_COUNT],
+}
+impl Params {
+  pub fn new() -> Self {
+    let mut values = [ParamValue::Bool(false); PARAM_COUNT];
+    for (i, id) in ALL_PARAMS.iter().enumerate() {
+      values[i] = match id.def().range {
+        Range::F32 { default, .. } => ParamValue::F32(default),
+        Range::U32 { default, .. } => ParamValue::U32(default),
+        Range::Bool { default } => ParamValue::Bool(default),
+      };
+    }
+    Self { values }
+  }
+
+  pub fn get(&self, id: ParamId) -> ParamValue {
+    self.values[id as usize]
+  }
+
+  pub fn get_f32(&self, id: ParamId) -> f32 {
+    match self.get(id) {
+      ParamValue::F32(v) => v,
+      ParamValue::U32(v) => v as f32,
+      ParamValue::Bool(v) => v as u32 as f32,
+    }
+  }
+
+  pub fn get_u32(&self, id: ParamId) -> u32 {
+    match self.get(id) {
+      ParamValue::U32(v) => v,
+      ParamValue::F32(v) => v as u32,
+      ParamValue::Bool(v) => v as u32,
+    }
+  }
+
+  pub fn get_bool(&self, id: ParamId) -> bool {
+    match self.get(id) {
+      ParamValue::Bool(v) => v,
+      ParamValue::U32(v) => v != 0,
+      ParamValue::F32(v) => v != 0f32,
+    }
+  }
+
+  // Checks the value against the parameter's type and range, and against any
+  // parameters it must stay consistent with, before storing it.
+  pub fn set(&mut self, id: ParamId, value: ParamValue) -> Result<(), ParamError> {
+    check_range(id, value)?;
+
+    let previous = self.values[id as usize];
+    self.values[id as usize] = value;
+
+    if let Err(e) = self.check_consistency() {
+      self.values[id as usize] = previous;
+      return Err(e);
+    }
+
+    Ok(())
+  }
+
+  // Sets several values together, checking consistency only once they are
+  // all written so that related parameters can move past each other's
+  // current values. Values out of range or of the wrong type are skipped.
+  // If the result is inconsistent every value is left as it was. Returns how
+  // many values were set.
+  pub fn set_all<I>(&mut self, values: I) -> Result<usize, ParamError>
+  where
+    I: IntoIterator<Item = (ParamId, ParamValue)>,
+  {
+    let previous = self.values;
+    let mut count = 0;
+    for (id, value) in values {
+      if check_range(id, value).is_ok() {
+        self.values[id as usize] = value;
+        count += 1;
+      }
+    }
+
+    if let Err(e) = self.check_consistency() {
+      self.values = previous;
+      return Err(e);
+    }
+
+    Ok(count)
+  }
+
+  fn check_consistency(&self) -> Result<(), ParamError> {
+    if self.get_f32(ParamId::DemoMinPower) > self.get_f32(ParamId::DemoMaxPower) {
+      return Err(ParamError::Inconsistent);
+    }
+    if self.get_f32(ParamId::FusionBlendStart) > self.get_f32(ParamId::FusionBlendEnd) {
+      return Err(ParamError::Inconsistent);
+    }
+    if self.get_f32(ParamId::HapticMinPosition) > self.get_f32(ParamId::HapticMaxPosition) {
+      return Err(ParamError::Inconsistent);
+    }
+    if self.get_f32(ParamId::BusUndervoltage) >= self.get_f32(ParamId::BusOvervoltage) {
+      return Err(ParamError::Inconsistent);
+    }
+    if self.get_f32(ParamId::ThermalMotorDerateTemp) >= self.get_f32(ParamId::ThermalMotorTripTemp)
+      || self.get_f32(ParamId::ThermalFetDerateTemp) >= self.get_f32(ParamId::ThermalFetTripTemp)
+    {
+      return Err(ParamError::Inconsistent);
+    }
+    if self.get_f32(ParamId::RcMinPulse) >= self.get_f32(ParamId::RcCenterPulse)
+      || self.get_f32(ParamId::RcCenterPulse) >= self.get_f32(ParamId::RcMaxPulse)
+    {
+      return Err(ParamError::Inconsistent);
+    }
+    // The gain may be inverting but currents are divided by it, so it can't
+    // be near zero.
+    if libm::fabsf(self.get_f32(ParamId::CurrentAmpGain)) < 1f32 {
+      return Err(ParamError::Inconsistent);
+    }
+
+    // The deadtime must leave some on-time in each PWM period, and with the
+    // current sampling window must leave at least half of it for driving.
+    let period_ns = 1_000_000_000f32 / self.get_f32(ParamId::PwmFrequency);
+    let deadtime_ns = self.get_u32(ParamId::Deadtime) as f32;
+    if deadtime_ns * 4f32 > period_ns {
+      return Err(ParamError::Inconsistent);
+    }
+    let low_side_ns = deadtime_ns
+      + self.get_u32(ParamId::PwmMinLowSide) as f32
+      + self.get_u32(ParamId::PwmSampleAdvance) as f32 * 2f32;
+    if low_side_ns * 2f32 > period_ns {
+      return Err(ParamError::Inconsistent);
+    }
+
+    Ok(())
+  }
+}
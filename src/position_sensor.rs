@@ -11,14 +11,27 @@ use stm32f303_api::{
 };
 use stm32f303_api::{
   spi::{BitOrder, ClockPhase, ClockPolarity},
-  Result,
+  Error, Result,
 };
 
-use crate::math::{norm_rads, PI1_2, PI2};
+use crate::{
+  angle_sensor::AngleSensor,
+  math::{norm_rads, wrap_rads, PI1_2, PI2},
+  observer::Pll,
+};
 
 const POS_MAX_U16: u16 = 0b0011111111111111; // Max value of 14-bit position sensor
 const POS_MAX_F32: f32 = POS_MAX_U16 as f32; // Max value of 14-bit position sensor
 
+// Response frames carry even parity in bit 15 and the error flag in bit 14.
+const ERROR_FLAG: u16 = 1 << 14;
+// Diagnostics register: field too weak, field too strong, CORDIC overflow.
+const DIAG_COMP_HIGH: u16 = 1 << 11;
+const DIAG_COMP_LOW: u16 = 1 << 10;
+const DIAG_COF: u16 = 1 << 9;
+// Angle reads between checks of the magnetic field.
+const DIAGNOSTICS_INTERVAL: u32 = 100;
+
 fn raw_to_rads(raw: u16) -> f32 {
   (raw as f32 / POS_MAX_F32) * PI2
 }
@@ -33,6 +46,7 @@ pub struct PositionSensor {
   miso: Pa6AltFunc<Pa6Spi1Miso>,
   mosi: Pa7AltFunc<Pa7Spi1Mosi>,
   last_command: Command,
+  reads_since_diagnostics: u32,
 }
 impl PositionSensor {
   pub fn new(num_magnet_pairs: u32, system: &mut System, gpio_a: &mut GpioA) -> Result<Self> {
@@ -69,18 +83,10 @@ impl PositionSensor {
         OutputSpeed::High,
       ),
       last_command: Command::Nop,
+      reads_since_diagnostics: 0,
     })
   }
 
-  pub fn set_offset(&mut self, offset: f32) {
-    println!("SET OFFSET").ok();
-    self.offset = offset;
-  }
-
-  pub fn get_offset(&self) -> f32 {
-    self.offset
-  }
-
   pub fn start(&mut self) {
     self.csn.write(DigitalValue::High);
     self.spi.start();
@@ -93,19 +99,6 @@ impl PositionSensor {
     Ok(())
   }
 
-  pub fn read_absolute_angle(&mut self) -> Result<f32> {
-    let rads = raw_to_rads(self.read(ReadCommand::Angle)? & POS_MAX_U16);
-    Ok(norm_rads(rads - self.offset))
-  }
-
-  pub fn read_phase_angle(&mut self) -> Result<f32> {
-    Ok(
-      (self.read_absolute_angle()? % (PI2 / self.num_magnet_pairs as f32))
-        * self.num_magnet_pairs as f32,
-    )
-    //Ok(self.read_absolute_angle()? * self.num_magnet_pairs as f32)
-  }
-
   pub fn read(&mut self, read_command: ReadCommand) -> Result<u16> {
     let command_previously_sent = match self.last_command {
       Command::Nop => false,
@@ -133,6 +126,34 @@ impl PositionSensor {
     Ok(self.spi.read())
   }
 
+  // Checks a response's parity and error flag, reading the error register to
+  // clear the flag if it is set.
+  fn check_frame(&mut self, frame: u16) -> Result<u16> {
+    if frame.count_ones() % 2 != 0 {
+      return Err(Error::new("Position sensor parity error"));
+    }
+    if frame & ERROR_FLAG != 0 {
+      self.read(ReadCommand::Errors)?;
+      return Err(Error::new("Position sensor error flag set"));
+    }
+    Ok(frame & POS_MAX_U16)
+  }
+
+  fn check_magnet(&mut self) -> Result<()> {
+    let frame = self.read(ReadCommand::Diagnostics)?;
+    let diagnostics = self.check_frame(frame)?;
+    if diagnostics & DIAG_COMP_HIGH != 0 {
+      return Err(Error::new("Position sensor magnet too weak"));
+    }
+    if diagnostics & DIAG_COMP_LOW != 0 {
+      return Err(Error::new("Position sensor magnet too strong"));
+    }
+    if diagnostics & DIAG_COF != 0 {
+      return Err(Error::new("Position sensor CORDIC overflow"));
+    }
+    Ok(())
+  }
+
   pub fn return_hardware(mut self, system: &mut System, gpio_a: &mut GpioA) -> Result<()> {
     self.stop()?;
     system.deactivate_spi_i2s_1(self.spi.teardown())?;
@@ -144,6 +165,157 @@ impl PositionSensor {
   }
 }
 
+impl AngleSensor for PositionSensor {
+  fn read_absolute_angle(&mut self) -> Result<f32> {
+    self.reads_since_diagnostics += 1;
+    if self.reads_since_diagnostics >= DIAGNOSTICS_INTERVAL {
+      self.reads_since_diagnostics = 0;
+      self.check_magnet()?;
+    }
+
+    let frame = self.read(ReadCommand::Angle)?;
+    let rads = raw_to_rads(self.check_frame(frame)?);
+    Ok(norm_rads(rads - self.offset))
+  }
+
+  fn absolute_to_phase_angle(&self, absolute_angle: f32) -> f32 {
+    (absolute_angle % self.rads_per_magnet_pair) * self.num_magnet_pairs as f32
+  }
+
+  fn set_offset(&mut self, offset: f32) {
+    println!("SET OFFSET").ok();
+    self.offset = offset;
+  }
+
+  fn get_offset(&self) -> f32 {
+    self.offset
+  }
+}
+
+#[derive(Copy, Clone)]
+pub struct Motion {
+  pub angle: f32,
+  pub position: f32,
+  pub velocity: f32,
+  pub acceleration: f32,
+}
+
+// Unwraps successive absolute angle readings into a multi-turn position,
+// velocity and acceleration. By default the velocity is the low-pass filtered
+// difference of successive angles; with tracking enabled a PLL follows the
+// angle instead, which rejects quantization noise without the filter's lag.
+pub struct MotionTracker {
+  last_angle: Option<f32>,
+  turns: i32,
+  // Position reported as zero, such as a homed stop.
+  origin: f32,
+  velocity: f32,
+  acceleration: f32,
+  velocity_time_constant: f32,
+  tracker: Option<Pll>,
+  // Seconds from reading the angle to the outputs computed from it taking
+  // effect, over which the motion is extrapolated.
+  latency: f32,
+}
+impl MotionTracker {
+  pub fn new(velocity_time_constant: f32) -> Self {
+    Self {
+      last_angle: None,
+      turns: 0,
+      origin: 0f32,
+      velocity: 0f32,
+      acceleration: 0f32,
+      velocity_time_constant,
+      tracker: None,
+      latency: 0f32,
+    }
+  }
+
+  pub fn set_velocity_time_constant(&mut self, velocity_time_constant: f32) {
+    self.velocity_time_constant = velocity_time_constant;
+  }
+
+  // Tracks the angle with a PLL of the given bandwidth (rad/s), or goes back
+  // to filtering differences when None. The position carries on across the
+  // switch, with a new PLL starting from the last angle and velocity.
+  pub fn set_tracking(&mut self, bandwidth: Option<f32>) {
+    match (&mut self.tracker, bandwidth) {
+      (Some(pll), Some(bandwidth)) => pll.set_bandwidth(bandwidth),
+      (tracker, bandwidth) => {
+        *tracker = bandwidth.map(Pll::new);
+        if let (Some(pll), Some(last_angle)) = (tracker, self.last_angle) {
+          pll.reset(last_angle, self.velocity);
+        }
+      }
+    }
+  }
+
+  pub fn set_latency(&mut self, latency: f32) {
+    self.latency = latency;
+  }
+
+  // Makes `position`, as currently reported, read as zero from now on.
+  pub fn set_zero(&mut self, position: f32) {
+    self.origin += position;
+  }
+
+  pub fn reset(&mut self) {
+    self.last_angle = None;
+    self.turns = 0;
+    self.origin = 0f32;
+    self.velocity = 0f32;
+    self.acceleration = 0f32;
+  }
+
+  pub fn update(&mut self, measured_angle: f32, dt: f32) -> Motion {
+    let angle = match &mut self.tracker {
+      Some(pll) => {
+        match self.last_angle {
+          Some(_) => pll.update(measured_angle, dt),
+          None => pll.reset(measured_angle, 0f32),
+        }
+        pll.get_angle()
+      }
+      None => measured_angle,
+    };
+
+    if let Some(last_angle) = self.last_angle {
+      let delta = wrap_rads(angle - last_angle);
+
+      if last_angle + delta >= PI2 {
+        self.turns += 1;
+      } else if last_angle + delta < 0f32 {
+        self.turns -= 1;
+      }
+
+      if dt > 0f32 {
+        match &self.tracker {
+          Some(pll) => {
+            self.velocity = pll.get_velocity();
+            self.acceleration = pll.get_acceleration();
+          }
+          None => {
+            let alpha = (dt / self.velocity_time_constant).min(1f32);
+            let last_velocity = self.velocity;
+            self.velocity += (delta / dt - self.velocity) * alpha;
+            self.acceleration += ((self.velocity - last_velocity) / dt - self.acceleration) * alpha;
+          }
+        }
+      }
+    }
+
+    self.last_angle = Some(angle);
+
+    let lead = (self.velocity + 0.5 * self.acceleration * self.latency) * self.latency;
+    Motion {
+      angle: norm_rads(angle + lead),
+      position: self.turns as f32 * PI2 + angle + lead - self.origin,
+      velocity: self.velocity + self.acceleration * self.latency,
+      acceleration: self.acceleration,
+    }
+  }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 #[repr(u16)]
 pub enum Command {
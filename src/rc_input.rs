@@ -0,0 +1,106 @@
+use core::ptr::{read_volatile, write_volatile};
+use stm32f303_api::{
+  gpio::{
+    gpio_b::{GpioB, Pb4AltFunc, Pb4Tim3Ch1},
+    OutputSpeed, OutputType, PullDirection,
+  },
+  timer::tim3::Tim3,
+  Result, System,
+};
+
+// TIM3 registers (RM0316 section 21.4). The timer API has no input capture,
+// so the timer is configured directly once activated.
+const TIM3_CR1: *mut u32 = 0x4000_0400 as *mut u32;
+const TIM3_SMCR: *mut u32 = 0x4000_0408 as *mut u32;
+const TIM3_SR: *mut u32 = 0x4000_0410 as *mut u32;
+const TIM3_CCMR1: *mut u32 = 0x4000_0418 as *mut u32;
+const TIM3_CCER: *mut u32 = 0x4000_0420 as *mut u32;
+const TIM3_CNT: *mut u32 = 0x4000_0424 as *mut u32;
+const TIM3_PSC: *mut u32 = 0x4000_0428 as *mut u32;
+const TIM3_ARR: *mut u32 = 0x4000_042C as *mut u32;
+const TIM3_CCR1: *mut u32 = 0x4000_0434 as *mut u32;
+const TIM3_CCR2: *mut u32 = 0x4000_0438 as *mut u32;
+
+// APB1 runs at HCLK / 8, and timers on a divided APB1 at twice that.
+const TIMER_CLOCK: u32 = 16_000_000;
+const TICKS_PER_SECOND: u32 = 1_000_000;
+
+const CR1_CEN: u32 = 1 << 0;
+// Slave reset mode (SMS = 100) on TI1FP1 (TS = 101), so the counter restarts
+// on each rising edge.
+const SMCR_RESET_ON_TI1: u32 = 0b100 | 0b101 << 4;
+const SR_CC1IF: u32 = 1 << 1;
+// CC1S = 01 (IC1 on TI1) and CC2S = 10 (IC2 also on TI1), both with IC1F =
+// 0011 (8 samples at the timer clock).
+const CCMR1_PWM_INPUT: u32 = 0b0011_0001 | 0b0011_0010 << 8;
+// IC1 captures rising edges and IC2 falling edges.
+const CCER_PWM_INPUT: u32 = 1 << 0 | 1 << 4 | 1 << 5;
+
+fn read_register(register: *mut u32) -> u32 {
+  unsafe { read_volatile(register) }
+}
+
+fn write_register(register: *mut u32, value: u32) {
+  unsafe { write_volatile(register, value) }
+}
+
+// RC servo pulses on PB4 (TIM3 CH1), measured in hardware in PWM input mode:
+// each rising edge captures the period and restarts the counter, and the
+// falling edge captures the pulse width, both in microseconds.
+pub struct RcInput {
+  timer: Tim3,
+  pin: Pb4AltFunc<Pb4Tim3Ch1>,
+}
+impl RcInput {
+  pub fn new(system: &mut System, gpio_b: &mut GpioB) -> Result<Self> {
+    let timer = system.activate_tim3()?;
+    write_register(TIM3_CR1, 0);
+    write_register(TIM3_PSC, TIMER_CLOCK / TICKS_PER_SECOND - 1);
+    write_register(TIM3_ARR, 0xFFFF);
+    write_register(TIM3_SMCR, SMCR_RESET_ON_TI1);
+    write_register(TIM3_CCMR1, CCMR1_PWM_INPUT);
+    write_register(TIM3_CCER, CCER_PWM_INPUT);
+    write_register(TIM3_CNT, 0);
+    write_register(TIM3_SR, 0);
+
+    Ok(Self {
+      timer,
+      pin: gpio_b.take_pb4()?.as_alt_func(
+        PullDirection::Down,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+    })
+  }
+
+  pub fn start(&mut self) {
+    write_register(TIM3_CR1, CR1_CEN);
+  }
+
+  pub fn stop(&mut self) {
+    write_register(TIM3_CR1, 0);
+  }
+
+  // Width (us) of the pulse completed since the last call, if any.
+  pub fn read_pulse(&mut self) -> Option<f32> {
+    if read_register(TIM3_SR) & SR_CC1IF == 0 {
+      return None;
+    }
+
+    // Reading the capture clears the flag. A width as long as the period
+    // means the edges were glitches rather than a pulse.
+    let period = read_register(TIM3_CCR1);
+    let width = read_register(TIM3_CCR2);
+    match period > 0 && width < period {
+      true => Some(width as f32),
+      false => None,
+    }
+  }
+
+  pub fn return_hardware(mut self, system: &mut System, gpio_b: &mut GpioB) -> Result<()> {
+    self.stop();
+    system.deactivate_tim3(self.timer)?;
+    gpio_b.return_pb4(self.pin.teardown())?;
+    Ok(())
+  }
+}
@@ -0,0 +1,75 @@
+use stm32f303_api::{
+  gpio::{
+    gpio_a::{GpioA, Pa10AltFunc, Pa10Usart1Rx, Pa9AltFunc, Pa9Usart1Tx},
+    OutputSpeed, OutputType, PullDirection,
+  },
+  usart::{usart1::Usart1, StopBits, WordLength},
+  Result, System,
+};
+
+pub struct SerialPort {
+  usart: Usart1,
+  tx: Pa9AltFunc<Pa9Usart1Tx>,
+  rx: Pa10AltFunc<Pa10Usart1Rx>,
+}
+impl SerialPort {
+  pub fn new(baud_rate: u32, system: &mut System, gpio_a: &mut GpioA) -> Result<Self> {
+    let mut usart = system.activate_usart1()?;
+    usart.set_word_length(WordLength::Bits8);
+    usart.set_stop_bits(StopBits::One);
+    usart.set_baud_rate(baud_rate)?;
+    usart.enable_transmitter();
+    usart.enable_receiver();
+
+    Ok(Self {
+      usart,
+      tx: gpio_a.take_pa9()?.as_alt_func(
+        PullDirection::Up,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+      rx: gpio_a.take_pa10()?.as_alt_func(
+        PullDirection::Up,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+    })
+  }
+
+  pub fn start(&mut self) {
+    self.usart.start();
+  }
+
+  pub fn stop(&mut self) -> Result<()> {
+    self.usart.wait_for_transmission_complete()?;
+    self.usart.stop();
+    Ok(())
+  }
+
+  pub fn read_byte(&mut self) -> Option<u8> {
+    if self.usart.has_overrun() {
+      self.usart.clear_overrun();
+    }
+
+    match self.usart.is_rx_not_empty() {
+      true => Some(self.usart.read() as u8),
+      false => None,
+    }
+  }
+
+  pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+    for byte in bytes {
+      self.usart.wait_for_tx_empty()?;
+      self.usart.write(*byte as u16);
+    }
+    Ok(())
+  }
+
+  pub fn return_hardware(mut self, system: &mut System, gpio_a: &mut GpioA) -> Result<()> {
+    self.stop()?;
+    system.deactivate_usart1(self.usart)?;
+    gpio_a.return_pa9(self.tx.teardown())?;
+    gpio_a.return_pa10(self.rx.teardown())?;
+    Ok(())
+  }
+}
@@ -0,0 +1,120 @@
+use bldc_protocol::ModeId;
+
+use crate::{
+  modes::servo::Target,
+  params::{ParamId, Params},
+};
+
+// Widths (us) outside which a pulse cannot be from an RC receiver.
+const MIN_VALID_PULSE: f32 = 500f32;
+const MAX_VALID_PULSE: f32 = 2500f32;
+// Fraction of the calibrated range an analog input may read beyond its
+// endpoints before it is taken to be disconnected.
+const ANALOG_MARGIN: f32 = 0.1;
+
+// Where servo targets come from, besides the serial and CAN commands.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SetpointSource {
+  Commands,
+  RcPulse,
+  Analog,
+}
+
+pub fn setpoint_source(params: &Params) -> SetpointSource {
+  match params.get_u32(ParamId::SetpointSource) {
+    1 => SetpointSource::RcPulse,
+    2 => SetpointSource::Analog,
+    _ => SetpointSource::Commands,
+  }
+}
+
+// The servo mode an input source drives.
+pub fn setpoint_mode(params: &Params) -> ModeId {
+  match params.get_u32(ParamId::SetpointTarget) {
+    1 => ModeId::Position,
+    2 => ModeId::Torque,
+    _ => ModeId::Velocity,
+  }
+}
+
+// Maps an input, as a fraction from 0 to 1 of its calibrated range, onto the
+// configured setpoint range.
+pub fn setpoint_target(params: &Params, fraction: f32) -> Target {
+  let min = params.get_f32(ParamId::SetpointMin);
+  let value = min + fraction * (params.get_f32(ParamId::SetpointMax) - min);
+  match setpoint_mode(params) {
+    ModeId::Position => Target::Position(value),
+    ModeId::Torque => Target::Torque(value),
+    _ => Target::Velocity(value),
+  }
+}
+
+// Fraction of the stick travel for a pulse width (us), with the centre at a
+// half and a deadband around it. None for a pulse no receiver would send.
+pub fn rc_fraction(params: &Params, width: f32) -> Option<f32> {
+  if !(MIN_VALID_PULSE..=MAX_VALID_PULSE).contains(&width) {
+    return None;
+  }
+
+  let center = params.get_f32(ParamId::RcCenterPulse);
+  let deadband = params.get_f32(ParamId::RcDeadband);
+  let (offset, span) = match width < center {
+    true => (center - width, center - params.get_f32(ParamId::RcMinPulse)),
+    false => (width - center, params.get_f32(ParamId::RcMaxPulse) - center),
+  };
+  let deflection = ((offset - deadband) / (span - deadband).max(1f32))
+    .max(0f32)
+    .min(1f32);
+
+  Some(match width < center {
+    true => 0.5 - deflection / 2f32,
+    false => 0.5 + deflection / 2f32,
+  })
+}
+
+// Filters an analog input and maps it between its calibrated endpoints. The
+// output only follows once the input has moved by more than the deadband,
+// so noise does not dither the setpoint.
+pub struct AnalogSetpoint {
+  volts: Option<f32>,
+  fraction: f32,
+}
+impl AnalogSetpoint {
+  pub fn new() -> Self {
+    Self {
+      volts: None,
+      fraction: 0f32,
+    }
+  }
+
+  // Fraction from 0 to 1 for a new reading (V), or None while the input is
+  // well outside its endpoints.
+  pub fn update(&mut self, params: &Params, volts: f32, dt: f32) -> Option<f32> {
+    let filter_time = params.get_f32(ParamId::AnalogFilterTime);
+    let filtered = match self.volts {
+      Some(last) if filter_time > 0f32 => last + (volts - last) * (dt / filter_time).min(1f32),
+      _ => volts,
+    };
+    self.volts = Some(filtered);
+
+    // Endpoints may be either way round, to reverse the input.
+    let min_volts = params.get_f32(ParamId::AnalogMinVolts);
+    let span = params.get_f32(ParamId::AnalogMaxVolts) - min_volts;
+    if libm::fabsf(span) < core::f32::EPSILON {
+      return None;
+    }
+    let fraction = (filtered - min_volts) / span;
+    if fraction < -ANALOG_MARGIN || fraction > 1f32 + ANALOG_MARGIN {
+      return None;
+    }
+
+    let fraction = fraction.max(0f32).min(1f32);
+    if libm::fabsf(fraction - self.fraction) > params.get_f32(ParamId::AnalogDeadband)
+      || fraction == 0f32
+      || fraction == 1f32
+    {
+      self.fraction = fraction;
+    }
+    Some(self.fraction)
+  }
+}
@@ -0,0 +1,112 @@
+use core::ptr::{read_volatile, write_volatile};
+use stm32f303_api::{
+  gpio::{
+    gpio_d::{GpioD, Pd3AltFunc, Pd3Tim2Ch1, Pd4Input, Pd5Input},
+    DigitalValue, OutputSpeed, OutputType, PullDirection,
+  },
+  timer::tim2::Tim2,
+  Result, System,
+};
+
+// TIM2 registers (RM0316 section 21.4). The timer API has no external clock
+// mode, so the timer is configured directly once activated.
+const TIM2_CR1: *mut u32 = 0x4000_0000 as *mut u32;
+const TIM2_SMCR: *mut u32 = 0x4000_0008 as *mut u32;
+const TIM2_SR: *mut u32 = 0x4000_0010 as *mut u32;
+const TIM2_CCMR1: *mut u32 = 0x4000_0018 as *mut u32;
+const TIM2_CCER: *mut u32 = 0x4000_0020 as *mut u32;
+const TIM2_CNT: *mut u32 = 0x4000_0024 as *mut u32;
+const TIM2_ARR: *mut u32 = 0x4000_002C as *mut u32;
+
+const CR1_CEN: u32 = 1 << 0;
+// External clock mode 1 (SMS = 111) clocked by TI1FP1 (TS = 101), so each
+// rising edge on channel 1 counts once.
+const SMCR_EXTERNAL_CLOCK_TI1: u32 = 0b111 | 0b101 << 4;
+// CC1S = 01 (input on TI1), IC1F = 0011 (8 samples at the timer clock).
+const CCMR1_STEP_INPUT: u32 = 0b0011_0001;
+
+fn read_register(register: *mut u32) -> u32 {
+  unsafe { read_volatile(register) }
+}
+
+fn write_register(register: *mut u32, value: u32) {
+  unsafe { write_volatile(register, value) }
+}
+
+// Step/direction inputs from a stepper motion controller: step pulses on PD3
+// (TIM2 CH1) are counted in hardware, so none are missed between control
+// loop steps, while the direction on PD4 and enable on PD5 are polled. The
+// direction is sampled when the steps are read, so the controller has to
+// hold it for at least a control loop period around a reversal.
+pub struct StepDirInput {
+  last_counter: u32,
+  count: i64,
+  timer: Tim2,
+  step: Pd3AltFunc<Pd3Tim2Ch1>,
+  dir: Pd4Input,
+  enable: Pd5Input,
+}
+impl StepDirInput {
+  pub fn new(system: &mut System, gpio_d: &mut GpioD) -> Result<Self> {
+    let timer = system.activate_tim2()?;
+    write_register(TIM2_CR1, 0);
+    write_register(TIM2_SMCR, SMCR_EXTERNAL_CLOCK_TI1);
+    write_register(TIM2_CCMR1, CCMR1_STEP_INPUT);
+    write_register(TIM2_CCER, 0);
+    write_register(TIM2_ARR, 0xFFFF_FFFF);
+    write_register(TIM2_CNT, 0);
+    write_register(TIM2_SR, 0);
+
+    Ok(Self {
+      last_counter: 0,
+      count: 0,
+      timer,
+      step: gpio_d.take_pd3()?.as_alt_func(
+        PullDirection::Down,
+        OutputType::PushPull,
+        OutputSpeed::High,
+      ),
+      dir: gpio_d.take_pd4()?.as_input(PullDirection::Down),
+      // Unconnected, the drive is enabled.
+      enable: gpio_d.take_pd5()?.as_input(PullDirection::Up),
+    })
+  }
+
+  pub fn start(&mut self) {
+    self.last_counter = read_register(TIM2_CNT);
+    write_register(TIM2_CR1, CR1_CEN);
+  }
+
+  pub fn stop(&mut self) {
+    write_register(TIM2_CR1, 0);
+  }
+
+  // Net steps since starting, counting down while the direction is high.
+  pub fn read_steps(&mut self) -> i64 {
+    let counter = read_register(TIM2_CNT);
+    let steps = counter.wrapping_sub(self.last_counter) as i64;
+    self.last_counter = counter;
+
+    self.count += match self.dir.read() {
+      DigitalValue::High => -steps,
+      DigitalValue::Low => steps,
+    };
+    self.count
+  }
+
+  pub fn is_enable_high(&self) -> bool {
+    match self.enable.read() {
+      DigitalValue::High => true,
+      DigitalValue::Low => false,
+    }
+  }
+
+  pub fn return_hardware(mut self, system: &mut System, gpio_d: &mut GpioD) -> Result<()> {
+    self.stop();
+    system.deactivate_tim2(self.timer)?;
+    gpio_d.return_pd3(self.step.teardown())?;
+    gpio_d.return_pd4(self.dir.teardown())?;
+    gpio_d.return_pd5(self.enable.teardown())?;
+    Ok(())
+  }
+}
@@ -0,0 +1,130 @@
+// Degrees Celsius of absolute zero.
+const KELVIN: f32 = 273.15;
+// Reference temperature (K) of an NTC's nominal resistance.
+const NTC_REFERENCE: f32 = 25f32 + KELVIN;
+
+#[derive(Copy, Clone)]
+pub struct ThermalConfig {
+  // Temperature (C) the motor starts from and cools towards.
+  pub ambient: f32,
+  // Winding to ambient thermal resistance (C/W) and time constant (s).
+  pub winding_resistance: f32,
+  pub winding_time_constant: f32,
+  // Phase resistance (ohm) for the copper loss.
+  pub phase_resistance: f32,
+  // Temperatures (C) at which derating starts and at which it reaches zero
+  // and the motor is stopped.
+  pub motor_derate: f32,
+  pub motor_trip: f32,
+  pub fet_derate: f32,
+  pub fet_trip: f32,
+}
+
+#[derive(Copy, Clone)]
+pub struct NtcConfig {
+  // Resistance (ohm) at 25 C, beta (K), and the pull-up (ohm) to the ADC
+  // reference forming a divider with the thermistor to ground.
+  pub nominal: f32,
+  pub beta: f32,
+  pub pullup: f32,
+}
+
+// Temperature (C) from the voltage across an NTC thermistor, or None when it
+// reads as open or shorted.
+pub fn ntc_temperature(config: &NtcConfig, volts: f32, vref: f32) -> Option<f32> {
+  if volts <= vref * 0.01 || volts >= vref * 0.99 {
+    return None;
+  }
+
+  let resistance = config.pullup * volts / (vref - volts);
+  let inverse = 1f32 / NTC_REFERENCE + libm::logf(resistance / config.nominal) / config.beta;
+  Some(1f32 / inverse - KELVIN)
+}
+
+fn hottest(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+  match (a, b) {
+    (Some(a), Some(b)) => Some(a.max(b)),
+    (a, None) => a,
+    (None, b) => b,
+  }
+}
+
+// Fraction of full power allowed at a temperature, falling linearly from one
+// at the derating temperature to zero at the trip temperature.
+fn derate(temperature: f32, derate: f32, trip: f32) -> f32 {
+  ((trip - temperature) / (trip - derate).max(core::f32::EPSILON))
+    .max(0f32)
+    .min(1f32)
+}
+
+// Tracks the motor winding and power stage temperatures. The winding is a
+// first-order I^2t model of its copper loss, and is never taken as cooler
+// than a thermistor on the motor says; the power stage is only known from a
+// thermistor or the DRV8305's temperature flags.
+pub struct ThermalModel {
+  config: ThermalConfig,
+  winding_rise: f32,
+  motor_sensor: Option<f32>,
+  fet_sensor: Option<f32>,
+}
+impl ThermalModel {
+  pub fn new(config: ThermalConfig) -> Self {
+    Self {
+      config,
+      winding_rise: 0f32,
+      motor_sensor: None,
+      fet_sensor: None,
+    }
+  }
+
+  pub fn set_config(&mut self, config: ThermalConfig) {
+    self.config = config;
+  }
+
+  // Steps the model by `dt` seconds with the peak phase current (A) and the
+  // latest measured temperatures (C), if any.
+  pub fn update(
+    &mut self,
+    current: f32,
+    motor_sensor: Option<f32>,
+    fet_sensors: (Option<f32>, Option<f32>),
+    dt: f32,
+  ) {
+    let loss = 1.5 * current * current * self.config.phase_resistance;
+    let steady_rise = loss * self.config.winding_resistance;
+    let alpha = (dt / self.config.winding_time_constant).min(1f32);
+    self.winding_rise += (steady_rise - self.winding_rise) * alpha;
+
+    self.motor_sensor = motor_sensor;
+    self.fet_sensor = hottest(fet_sensors.0, fet_sensors.1);
+  }
+
+  pub fn motor_temperature(&self) -> f32 {
+    let modelled = self.config.ambient + self.winding_rise;
+    hottest(Some(modelled), self.motor_sensor).unwrap_or(modelled)
+  }
+
+  pub fn fet_temperature(&self) -> Option<f32> {
+    self.fet_sensor
+  }
+
+  // Fraction of full power allowed, from zero to one.
+  pub fn derating(&self) -> f32 {
+    let motor = derate(
+      self.motor_temperature(),
+      self.config.motor_derate,
+      self.config.motor_trip,
+    );
+    let fet = self.fet_sensor.map_or(1f32, |temperature| {
+      derate(temperature, self.config.fet_derate, self.config.fet_trip)
+    });
+    motor.min(fet)
+  }
+
+  pub fn is_overtemperature(&self) -> bool {
+    self.motor_temperature() >= self.config.motor_trip
+      || self
+        .fet_sensor
+        .map_or(false, |temperature| temperature >= self.config.fet_trip)
+  }
+}
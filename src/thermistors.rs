@@ -0,0 +1,78 @@
+use stm32f303_api::{
+  gpio::gpio_d::{GpioD, Pd8Analog, Pd9Analog},
+  Result,
+};
+
+use crate::{
+  adc::{to_volts, Adc, AdcUnit, InjectedTrigger, VREF},
+  thermal::{ntc_temperature, NtcConfig},
+};
+
+// PD8 and PD9 are ADC4 channels 12 and 13.
+const FET_CHANNEL: u8 = 12;
+const MOTOR_CHANNEL: u8 = 13;
+
+// NTC thermistors on the power stage (PD8) and in the motor (PD9), either
+// of which may be fitted, converted by ADC4 on each TIM1 trigger.
+pub struct Thermistors {
+  adc: Adc,
+  fet_volts: Option<f32>,
+  motor_volts: Option<f32>,
+  fet_pin: Option<Pd8Analog>,
+  motor_pin: Option<Pd9Analog>,
+}
+impl Thermistors {
+  pub fn new(fet: bool, motor: bool, gpio_d: &mut GpioD) -> Result<Self> {
+    let mut adc = Adc::new(AdcUnit::Adc4)?;
+    let channels = [FET_CHANNEL, MOTOR_CHANNEL];
+    let channels = match (fet, motor) {
+      (true, true) => &channels[..],
+      (true, false) => &channels[..1],
+      (false, _) => &channels[1..],
+    };
+    adc.start_injected(channels, InjectedTrigger::Tim1Trgo)?;
+
+    Ok(Self {
+      adc,
+      fet_volts: None,
+      motor_volts: None,
+      fet_pin: match fet {
+        true => Some(gpio_d.take_pd8()?.as_analog()),
+        false => None,
+      },
+      motor_pin: match motor {
+        true => Some(gpio_d.take_pd9()?.as_analog()),
+        false => None,
+      },
+    })
+  }
+
+  // Temperatures (C) of the power stage and motor from the latest
+  // conversion, for those fitted and reading sensibly.
+  pub fn read(&mut self, config: &NtcConfig) -> (Option<f32>, Option<f32>) {
+    if self.adc.take_injected_complete() {
+      let mut rank = 0;
+      if self.fet_pin.is_some() {
+        self.fet_volts = Some(to_volts(self.adc.read_injected(rank)));
+        rank += 1;
+      }
+      if self.motor_pin.is_some() {
+        self.motor_volts = Some(to_volts(self.adc.read_injected(rank)));
+      }
+    }
+
+    let temperature =
+      |volts: Option<f32>| volts.and_then(|volts| ntc_temperature(config, volts, VREF));
+    (temperature(self.fet_volts), temperature(self.motor_volts))
+  }
+
+  pub fn return_hardware(self, gpio_d: &mut GpioD) -> Result<()> {
+    if let Some(pin) = self.fet_pin {
+      gpio_d.return_pd8(pin.teardown())?;
+    }
+    if let Some(pin) = self.motor_pin {
+      gpio_d.return_pd9(pin.teardown())?;
+    }
+    Ok(())
+  }
+}